@@ -28,6 +28,20 @@ fn criterion_benchmark(c: &mut Criterion) {
             start.elapsed()
         });
     }); 
+
+    #[cfg(feature = "rayon")]
+    c.bench_function("glf100_par", |b| {
+        b.iter_custom( |iters| {
+
+            let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            d.push("pytritech_testdata/test_tritech.glf");
+            let glf = GLF::new(Path::new(&d)).unwrap();
+            let indices: Vec<usize> = (0..iters as usize).collect();
+            let start = Instant::now();
+            black_box(glf.extract_images_par(&indices).unwrap());
+            start.elapsed()
+        });
+    });
 }
 
 criterion_group!(benches, criterion_benchmark);
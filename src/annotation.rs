@@ -0,0 +1,311 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # Annotation
+//! Labels drawn on sonar frames. An annotation is anchored to the device and
+//! time of its frame rather than to a frame index, so it still finds its
+//! frame after a GLF has been trimmed, sliced or merged with another.
+//!
+//! Annotations are kept in a sidecar JSON file next to the GLF, named
+//! `<file>.glf.annotations.json`, and resolved to frames when loaded.
+
+use crate::{BoxAnnotation, BoxCoords, GLF};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The version of the sidecar format written by this crate.
+pub const ANNOTATION_FORMAT_VERSION: u32 = 1;
+
+/// The coordinates the points of a shape are given in.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CoordSpace {
+    /// `[x, y]` pixels in the raw polar image, from the top left corner.
+    Pixel,
+    /// `[range, bearing]` with range in samples and bearing in radians.
+    RangeBearing,
+}
+
+/// The geometry of an annotation. Points are pairs in the annotation's CoordSpace.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "points", rename_all = "snake_case")]
+pub enum Shape {
+    /// A single point.
+    Point([f64; 2]),
+    /// Two opposite corners of a box.
+    Box([[f64; 2]; 2]),
+    /// A closed polygon.
+    Polygon(Vec<[f64; 2]>),
+    /// An open line through the points.
+    Polyline(Vec<[f64; 2]>),
+}
+
+impl Shape {
+    /// All the points of the shape.
+    pub fn points(&self) -> Vec<[f64; 2]> {
+        match self {
+            Shape::Point(p) => vec![*p],
+            Shape::Box(corners) => corners.to_vec(),
+            Shape::Polygon(points) | Shape::Polyline(points) => points.clone(),
+        }
+    }
+
+    /// The bounding box of the shape as `(min, max)`, or None if it has no points.
+    pub fn bounds(&self) -> Option<([f64; 2], [f64; 2])> {
+        let points = self.points();
+
+        if points.is_empty() {
+            return None;
+        }
+
+        let mut min = [f64::MAX; 2];
+        let mut max = [f64::MIN; 2];
+
+        for p in points {
+            for i in 0..2 {
+                min[i] = min[i].min(p[i]);
+                max[i] = max[i].max(p[i]);
+            }
+        }
+
+        Some((min, max))
+    }
+}
+
+/// A labelled shape on the frame of a device at a given time.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Annotation {
+    /// The device ID (the sonar id) of the frame.
+    pub device_id: u16,
+    /// The time of the frame, from its CIHeader.
+    pub timestamp: DateTime<Utc>,
+    /// The class label.
+    pub label: String,
+    /// The coordinates of the shape's points.
+    pub space: CoordSpace,
+    /// The shape itself.
+    pub shape: Shape,
+    /// Free form attributes, such as the annotator or a confidence.
+    #[serde(default)]
+    pub attributes: BTreeMap<String, String>,
+}
+
+impl Annotation {
+    /// Create an annotation anchored to a frame of a GLF.
+    ///
+    /// * `glf` - the GLF the frame is in.
+    /// * `frame` - index into GLF::images.
+    /// * `label` - the class label.
+    /// * `space` - the coordinates of the shape's points.
+    /// * `shape` - the shape.
+    pub fn for_frame(glf: &GLF, frame: usize, label: &str, space: CoordSpace, shape: Shape) -> Result<Annotation, &'static str> {
+        let img_rec = glf.images.get(frame).ok_or("Frame index out of range.")?;
+
+        Ok(Annotation {
+            device_id: img_rec.header.device_id,
+            timestamp: img_rec.header.time,
+            label: label.to_string(),
+            space,
+            shape,
+            attributes: BTreeMap::new(),
+        })
+    }
+
+    /// The bounding box of this annotation on the given frame, for the dataset exporter.
+    ///
+    /// * `frame` - index into GLF::images, usually from AnnotationSet::resolve.
+    pub fn to_box(&self, frame: usize) -> Option<BoxAnnotation> {
+        let (min, max) = self.shape.bounds()?;
+        let bbox = match self.space {
+            CoordSpace::Pixel => BoxCoords::Pixel { x: min[0], y: min[1], width: max[0] - min[0], height: max[1] - min[1] },
+            CoordSpace::RangeBearing => BoxCoords::RangeBearing { range_min: min[0], range_max: max[0], bearing_min: min[1], bearing_max: max[1] },
+        };
+
+        Some(BoxAnnotation { frame, label: self.label.clone(), bbox })
+    }
+}
+
+/// An annotation matched to a frame of a GLF.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ResolvedAnnotation<'a> {
+    /// The annotation.
+    pub annotation: &'a Annotation,
+    /// Index into GLF::images of its frame, or None if the GLF doesn't hold it.
+    pub frame: Option<usize>,
+}
+
+/// The contents of an annotation sidecar file.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct AnnotationSet {
+    /// The sidecar format version.
+    pub version: u32,
+    /// The annotations, in no particular order.
+    pub annotations: Vec<Annotation>,
+}
+
+impl Default for AnnotationSet {
+    fn default() -> Self {
+        AnnotationSet::new()
+    }
+}
+
+/// Return the sidecar path for a GLF file.
+///
+/// * `glf_path` - the path of the GLF.
+pub fn annotation_sidecar_path(glf_path: &Path) -> PathBuf {
+    let mut name = glf_path.as_os_str().to_owned();
+    name.push(".annotations.json");
+    PathBuf::from(name)
+}
+
+impl AnnotationSet {
+    /// Create an empty set.
+    pub fn new() -> AnnotationSet {
+        AnnotationSet { version: ANNOTATION_FORMAT_VERSION, annotations: vec![] }
+    }
+
+    /// Read a sidecar file.
+    ///
+    /// * `path` - the sidecar file.
+    pub fn load(path: &Path) -> Result<AnnotationSet, &'static str> {
+        let text = fs::read_to_string(path).map_err(|_| "Failed to read annotation sidecar.")?;
+        let set: AnnotationSet = serde_json::from_str(&text).map_err(|_| "Failed to parse annotation sidecar.")?;
+
+        if set.version > ANNOTATION_FORMAT_VERSION {
+            return Err("Annotation sidecar is from a newer version.");
+        }
+
+        Ok(set)
+    }
+
+    /// Write a sidecar file.
+    ///
+    /// * `path` - the sidecar file.
+    pub fn save(&self, path: &Path) -> Result<(), &'static str> {
+        let text = serde_json::to_string_pretty(self).map_err(|_| "Failed to encode annotations.")?;
+        fs::write(path, text).map_err(|_| "Failed to write annotation sidecar.")
+    }
+
+    /// Add the annotations of another set, such as the sidecar of a GLF being merged in.
+    ///
+    /// * `other` - the set to take the annotations from.
+    pub fn merge(&mut self, other: AnnotationSet) {
+        self.annotations.extend(other.annotations);
+    }
+
+    /// Match each annotation to the frame of its device nearest its timestamp.
+    ///
+    /// * `glf` - the GLF to find the frames in.
+    /// * `tolerance` - how far a frame may be from the timestamp and still match.
+    pub fn resolve<'a>(&'a self, glf: &GLF, tolerance: Duration) -> Vec<ResolvedAnnotation<'a>> {
+        // Frames sorted by device then time, for a binary search per annotation.
+        let mut frames: Vec<(u16, DateTime<Utc>, usize)> = glf.images.iter().enumerate()
+            .map(|(idx, img_rec)| (img_rec.header.device_id, img_rec.header.time, idx))
+            .collect();
+        frames.sort();
+
+        self.annotations.iter().map(|annotation| {
+            let key = (annotation.device_id, annotation.timestamp);
+            let pos = frames.partition_point(|f| (f.0, f.1) < key);
+            let frame = [pos.checked_sub(1), Some(pos)].into_iter()
+                .flatten()
+                .filter_map(|p| frames.get(p))
+                .filter(|f| f.0 == annotation.device_id)
+                .map(|f| ((f.1 - annotation.timestamp).abs(), f.2))
+                .filter(|(delta, _)| *delta <= tolerance)
+                .min()
+                .map(|(_, idx)| idx);
+
+            ResolvedAnnotation { annotation, frame }
+        }).collect()
+    }
+
+    /// The bounding boxes of all the annotations that resolve to a frame.
+    ///
+    /// * `glf` - the GLF to find the frames in.
+    /// * `tolerance` - how far a frame may be from the timestamp and still match.
+    pub fn boxes(&self, glf: &GLF, tolerance: Duration) -> Vec<BoxAnnotation> {
+        self.resolve(glf, tolerance).iter()
+            .filter_map(|r| r.frame.and_then(|frame| r.annotation.to_box(frame)))
+            .collect()
+    }
+}
+
+impl GLF {
+    /// Load the annotation sidecar of this GLF, or an empty set if there isn't one.
+    pub fn load_annotations(&self) -> Result<AnnotationSet, &'static str> {
+        let path = annotation_sidecar_path(&self.filepath);
+
+        if path.exists() {
+            AnnotationSet::load(&path)
+        } else {
+            Ok(AnnotationSet::new())
+        }
+    }
+
+    /// Write the annotation sidecar of this GLF.
+    ///
+    /// * `annotations` - the annotations to save.
+    pub fn save_annotations(&self, annotations: &AnnotationSet) -> Result<(), &'static str> {
+        annotations.save(&annotation_sidecar_path(&self.filepath))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{build_dat, glf_from_dat, TestImage};
+
+    #[test]
+    fn test_resolve_annotations() {
+        let full = glf_from_dat(build_dat(&[TestImage::new(1, 1.0), TestImage::new(2, 1.0), TestImage::new(1, 2.0)]));
+        let mut set = AnnotationSet::default();
+        assert_eq!(set.version, ANNOTATION_FORMAT_VERSION);
+        set.annotations.push(Annotation::for_frame(&full, 2, "rock", CoordSpace::RangeBearing, Shape::Box([[2.0, -0.1], [4.0, 0.1]])).unwrap());
+        set.annotations.push(Annotation::for_frame(&full, 1, "fish", CoordSpace::Pixel, Shape::Point([1.0, 1.0])).unwrap());
+
+        let text = serde_json::to_string(&set).unwrap();
+        let set: AnnotationSet = serde_json::from_str(&text).unwrap();
+
+        // The same frames in a trimmed file, without the second device.
+        let trimmed = glf_from_dat(build_dat(&[TestImage::new(1, 1.0), TestImage::new(1, 2.0)]));
+        let resolved = set.resolve(&trimmed, Duration::milliseconds(1));
+        assert_eq!(resolved[0].frame, Some(1));
+        assert_eq!(resolved[1].frame, None);
+
+        let boxes = set.boxes(&trimmed, Duration::milliseconds(1));
+        assert_eq!(boxes.len(), 1);
+        assert_eq!(boxes[0].bbox, BoxCoords::RangeBearing { range_min: 2.0, range_max: 4.0, bearing_min: -0.1, bearing_max: 0.1 });
+    }
+
+    #[test]
+    fn test_annotation_sidecar() {
+        let mut glf = glf_from_dat(build_dat(&[TestImage::new(1, 1.0), TestImage::new(1, 2.0)]));
+        glf.filepath = std::env::temp_dir().join(format!("glf_annotation_{}.glf", std::process::id()));
+        let sidecar = annotation_sidecar_path(&glf.filepath);
+        assert_eq!(sidecar.file_name().unwrap().to_string_lossy(), format!("glf_annotation_{}.glf.annotations.json", std::process::id()));
+
+        // No sidecar yet is an empty set, not an error.
+        assert_eq!(glf.load_annotations().unwrap(), AnnotationSet::new());
+
+        let mut set = AnnotationSet::new();
+        set.annotations.push(Annotation::for_frame(&glf, 1, "rock", CoordSpace::Pixel, Shape::Box([[0.0, 0.0], [2.0, 3.0]])).unwrap());
+        glf.save_annotations(&set).unwrap();
+        assert!(sidecar.exists());
+        assert_eq!(glf.load_annotations().unwrap(), set);
+
+        // Sidecars from a newer version are refused.
+        let mut value = serde_json::to_value(&set).unwrap();
+        value["version"] = (ANNOTATION_FORMAT_VERSION + 1).into();
+        fs::write(&sidecar, value.to_string()).unwrap();
+        assert!(glf.load_annotations().is_err());
+        assert!(AnnotationSet::load(&sidecar).is_err());
+
+        fs::remove_file(&sidecar).unwrap();
+    }
+}
@@ -0,0 +1,320 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # Archive
+//! A GLF is a zip holding a .dat of records, a .cfg and a .xml, and
+//! sometimes more. GlfArchive lists every entry and reads any of them,
+//! matching entries by their real extension, so an archive with several
+//! .dat entries can be read one .dat at a time.
+
+use crate::glf::parse_dat;
+use crate::{DatBuffer, GLF};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Does a zip entry name have this extension, ignoring case?
+///
+/// * `name` - the entry name.
+/// * `ext` - the extension, without the dot.
+pub(crate) fn has_extension(name: &str, ext: &str) -> bool {
+    Path::new(name).extension().is_some_and(|e| e.eq_ignore_ascii_case(ext))
+}
+
+/// An entry name made safe to use in a file name, for sidecars.
+///
+/// * `name` - the full entry name.
+pub(crate) fn entry_file_name(name: &str) -> String {
+    name.replace(['/', '\\', ':'], "_")
+}
+
+/// How a zip entry is compressed.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum EntryCompression {
+    /// Stored as is.
+    Stored,
+    /// Deflated.
+    Deflated,
+    /// Any other method.
+    Other,
+}
+
+/// An entry in the GLF zip.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ArchiveEntry {
+    /// The position of the entry in the zip.
+    pub index: usize,
+    /// The full name, including any directories.
+    pub name: String,
+    /// Size once decompressed, in bytes.
+    pub size: u64,
+    /// Size in the zip, in bytes.
+    pub compressed_size: u64,
+    /// How the entry is compressed.
+    pub compression: EntryCompression,
+    /// Offset of the entry's data in the GLF file.
+    pub data_start: u64,
+    /// CRC32 of the decompressed entry.
+    pub crc32: u32,
+}
+
+impl ArchiveEntry {
+    /// The extension of the entry name, without the dot.
+    pub fn extension(&self) -> Option<&str> {
+        Path::new(&self.name).extension().and_then(|e| e.to_str())
+    }
+
+    /// Does the entry have this extension, ignoring case?
+    ///
+    /// * `ext` - the extension, without the dot.
+    pub fn has_extension(&self, ext: &str) -> bool {
+        has_extension(&self.name, ext)
+    }
+}
+
+/// A GLF opened as a zip, giving access to all its entries.
+pub struct GlfArchive {
+    path: PathBuf,
+    zip: zip::ZipArchive<File>,
+    entries: Vec<ArchiveEntry>,
+}
+
+impl GlfArchive {
+    /// Open a GLF and list its entries.
+    ///
+    /// * `path` - the path to the GLF.
+    pub fn open(path: &Path) -> Result<GlfArchive, &'static str> {
+        let file = File::open(path).map_err(|_| "Failed to open GLF File")?;
+        let mut zip = zip::ZipArchive::new(file).map_err(|_| "Error parsing GLF.")?;
+        let mut entries: Vec<ArchiveEntry> = vec![];
+
+        for index in 0..zip.len() {
+            let entry = zip.by_index_raw(index).map_err(|_| "Error parsing GLF.")?;
+            let compression = match entry.compression() {
+                zip::CompressionMethod::Stored => EntryCompression::Stored,
+                zip::CompressionMethod::Deflated => EntryCompression::Deflated,
+                _ => EntryCompression::Other,
+            };
+
+            entries.push(ArchiveEntry {
+                index,
+                name: entry.name().to_string(),
+                size: entry.size(),
+                compressed_size: entry.compressed_size(),
+                compression,
+                data_start: entry.data_start(),
+                crc32: entry.crc32(),
+            });
+        }
+
+        Ok(GlfArchive { path: path.to_path_buf(), zip, entries })
+    }
+
+    /// The path to the GLF.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Every entry, in the order they are in the zip.
+    pub fn entries(&self) -> &[ArchiveEntry] {
+        &self.entries
+    }
+
+    /// The entry with this name.
+    ///
+    /// * `name` - the full entry name.
+    pub fn entry(&self, name: &str) -> Option<&ArchiveEntry> {
+        self.entries.iter().find(|e| e.name == name)
+    }
+
+    /// The entries with this extension, ignoring case.
+    ///
+    /// * `ext` - the extension, without the dot.
+    pub fn by_extension<'a>(&'a self, ext: &'a str) -> impl Iterator<Item = &'a ArchiveEntry> + 'a {
+        self.entries.iter().filter(move |e| e.has_extension(ext))
+    }
+
+    /// The .dat entries, which hold the records.
+    pub fn dat_entries(&self) -> impl Iterator<Item = &ArchiveEntry> {
+        self.by_extension("dat")
+    }
+
+    /// The first .cfg entry, the sonar configuration.
+    pub fn cfg(&self) -> Option<&ArchiveEntry> {
+        self.by_extension("cfg").next()
+    }
+
+    /// The first .xml entry, the log description.
+    pub fn xml(&self) -> Option<&ArchiveEntry> {
+        self.by_extension("xml").next()
+    }
+
+    /// Read and decompress an entry.
+    ///
+    /// * `name` - the full entry name.
+    pub fn read(&mut self, name: &str) -> Result<Vec<u8>, &'static str> {
+        let mut entry = self.zip.by_name(name).map_err(|_| "No such entry in the GLF.")?;
+        let mut buffer: Vec<u8> = vec![];
+        entry.read_to_end(&mut buffer).map_err(|_| "Failed to read the entry, or it fails its CRC.")?;
+        Ok(buffer)
+    }
+
+    /// Read an entry as text, such as the .cfg or .xml.
+    ///
+    /// * `name` - the full entry name.
+    pub fn read_to_string(&mut self, name: &str) -> Result<String, &'static str> {
+        String::from_utf8(self.read(name)?).map_err(|_| "Entry is not UTF-8 text.")
+    }
+
+    /// Read a .dat entry into memory.
+    ///
+    /// * `name` - the full name of the .dat entry.
+    pub fn dat(&mut self, name: &str) -> Result<DatBuffer, &'static str> {
+        Ok(DatBuffer::from(self.read(name)?))
+    }
+
+    /// Map a .dat entry from the file rather than reading it into memory, if
+    /// it is stored uncompressed. It is checked against its CRC once, when
+    /// mapped. Deflated entries are read as by `dat`.
+    ///
+    /// # Safety
+    ///
+    /// The GLF file must not be changed or truncated while the buffer, or
+    /// any clone of it, is alive. Reading a mapped page that has gone is
+    /// undefined behaviour, and on most systems kills the process with SIGBUS.
+    ///
+    /// * `name` - the full name of the .dat entry.
+    #[cfg(feature = "mmap")]
+    pub unsafe fn dat_mapped(&mut self, name: &str) -> Result<DatBuffer, &'static str> {
+        let entry = self.entry(name).ok_or("No such entry in the GLF.")?.clone();
+
+        if entry.compression != EntryCompression::Stored {
+            return self.dat(name);
+        }
+
+        let file = File::open(&self.path).map_err(|_| "Failed to open GLF File")?;
+        // Safety: passed on to the caller.
+        let dat = unsafe { crate::datbuf::map_entry(&file, entry.data_start, entry.size) }.ok_or("Failed to map the .dat entry.")?;
+
+        if crc32fast::hash(&dat) != entry.crc32 {
+            return Err("The .dat entry fails its CRC.");
+        }
+
+        Ok(dat)
+    }
+
+    /// Parse a .dat entry into a GLF, reading it into memory.
+    ///
+    /// * `name` - the full name of the .dat entry.
+    pub fn glf(&mut self, name: &str) -> Result<GLF, &'static str> {
+        let dat = self.dat(name)?;
+        self.parse_entry(name, dat)
+    }
+
+    /// Parse a .dat entry into a GLF, mapping it as by `dat_mapped`.
+    ///
+    /// # Safety
+    ///
+    /// As for `dat_mapped`, the GLF file must not be changed or truncated
+    /// while the GLF, or any clone of its dat, is alive.
+    ///
+    /// * `name` - the full name of the .dat entry.
+    #[cfg(feature = "mmap")]
+    pub unsafe fn glf_mapped(&mut self, name: &str) -> Result<GLF, &'static str> {
+        // Safety: passed on to the caller.
+        let dat = unsafe { self.dat_mapped(name) }?;
+        self.parse_entry(name, dat)
+    }
+
+    /// Parse the bytes of a .dat entry into a GLF.
+    ///
+    /// * `name` - the full name of the .dat entry.
+    /// * `dat` - the bytes of the entry.
+    fn parse_entry(&self, name: &str, dat: DatBuffer) -> Result<GLF, &'static str> {
+        let parsed = parse_dat(&dat)?;
+
+        Ok(GLF {
+            filepath: self.path.clone(),
+            entry_name: name.to_string(),
+            images: parsed.images,
+            statuses: parsed.statuses,
+            serials: parsed.serials,
+            records: parsed.records,
+            dat,
+        })
+    }
+
+    /// Parse every .dat entry, in zip order.
+    pub fn glfs(&mut self) -> Result<Vec<GLF>, &'static str> {
+        let names: Vec<String> = self.dat_entries().map(|e| e.name.clone()).collect();
+        names.iter().map(|name| self.glf(name)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{build_dat, TestImage};
+    use crate::{inflate_entry_index_path, record_entry_index_path, record_index_path, IndexedDat, RecordIndex};
+    use std::io::Write;
+
+    #[test]
+    fn test_glf_archive() {
+        let first = build_dat(&[TestImage::new(1, 1.0)]);
+        let second = build_dat(&[TestImage::new(2, 2.0), TestImage::new(2, 3.0)]);
+        let path = std::env::temp_dir().join(format!("glf_archive_{}.glf", std::process::id()));
+
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        let deflated = zip::write::FileOptions::default();
+        let stored = deflated.compression_method(zip::CompressionMethod::Stored);
+        zip.start_file("update.xml", deflated).unwrap();
+        zip.write_all(b"<update/>").unwrap();
+        zip.start_file("log/a.DAT", stored).unwrap();
+        zip.write_all(&first).unwrap();
+        zip.start_file("log/a.cfg", deflated).unwrap();
+        zip.write_all(b"<cfg/>").unwrap();
+        zip.start_file("log/b.dat", deflated).unwrap();
+        zip.write_all(&second).unwrap();
+        zip.finish().unwrap();
+
+        let mut archive = GlfArchive::open(&path).unwrap();
+        assert_eq!(archive.entries().len(), 4);
+        assert_eq!(archive.dat_entries().map(|e| e.name.as_str()).collect::<Vec<_>>(), vec!["log/a.DAT", "log/b.dat"]);
+        assert_eq!(archive.entry("log/a.DAT").unwrap().compression, EntryCompression::Stored);
+        assert_eq!(archive.entry("log/b.dat").unwrap().size, second.len() as u64);
+        assert_eq!(archive.xml().unwrap().extension(), Some("xml"));
+        let cfg = archive.cfg().unwrap().name.clone();
+        assert_eq!(archive.read_to_string(&cfg).unwrap(), "<cfg/>");
+
+        let glfs = archive.glfs().unwrap();
+        assert_eq!((glfs[0].images.len(), glfs[1].images.len()), (1, 2));
+        assert_eq!(glfs[1].images[0].header.device_id, 2);
+        assert_eq!((glfs[0].entry_name.as_str(), glfs[1].entry_name.as_str()), ("log/a.DAT", "log/b.dat"));
+
+        // The second .dat has sidecars of its own.
+        let index = RecordIndex::open_entry(&path, "log/b.dat", None).unwrap();
+        assert_eq!(index.entry_name(), "log/b.dat");
+        assert_eq!(index.len(), 2);
+        assert_eq!(RecordIndex::open(&path, None).unwrap().entry_name(), "log/a.DAT");
+
+        let mut reader = IndexedDat::open_entry(&path, "log/b.dat").unwrap();
+        assert_eq!(reader.index().entry_name(), "log/b.dat");
+        assert_eq!(reader.read_image(&index.records[1].entry).unwrap(), glfs[1].extract_image(1).unwrap());
+
+        let second = GLF::with_record_index(&path, &index, archive.dat("log/b.dat").unwrap()).unwrap();
+        assert_eq!(second.entry_name, "log/b.dat");
+        assert_eq!(second.images[1].header.time, glfs[1].images[1].header.time);
+
+        // GLF::new skips update.xml and reads the first .dat.
+        assert_eq!(GLF::new(&path).unwrap().images.len(), 1);
+        assert!(archive.read("missing.dat").is_err());
+
+        for sidecar in [record_index_path(&path, None), record_entry_index_path(&path, "log/b.dat", None), inflate_entry_index_path(&path, "log/b.dat")] {
+            std::fs::remove_file(sidecar).unwrap();
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
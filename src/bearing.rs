@@ -0,0 +1,165 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # Bearing
+//! Consecutive frames from one sonar head almost always share a bearing
+//! table, so rather than each ImageRecord holding its own copy, the tables
+//! are interned while parsing. Every distinct table is stored once, as an
+//! `Arc<[f64]>`, and numbered in order of first appearance. The number is
+//! the record's `bearing_table_id`, which caches - such as scan conversion
+//! lookups - can key on instead of comparing tables.
+
+use crate::GLF;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::sync::Arc;
+
+/// The distinct bearing tables of a file, keyed by content.
+#[derive(Clone, Debug, Default)]
+pub struct BearingTableSet {
+    tables: Vec<Arc<[f64]>>,
+    /// Table ids by the hash of their contents.
+    index: HashMap<u64, Vec<u32>>,
+    /// The last table interned, checked first.
+    last: Option<u32>,
+}
+
+/// Compare a table with a sequence of bearings, bit for bit.
+fn same_bearings<I: Iterator<Item = f64>>(table: &[f64], bearings: I) -> bool {
+    let mut count = 0;
+
+    for (i, b) in bearings.enumerate() {
+        if table.get(i).map(|t| t.to_bits()) != Some(b.to_bits()) {
+            return false;
+        }
+
+        count += 1;
+    }
+
+    count == table.len()
+}
+
+impl BearingTableSet {
+    /// Create an empty set.
+    pub fn new() -> BearingTableSet {
+        BearingTableSet::default()
+    }
+
+    /// Return the id and shared copy of a table, adding it if it is new.
+    /// Only a new table is allocated.
+    ///
+    /// * `bearings` - the bearing of each beam in radians.
+    pub fn intern<I: Iterator<Item = f64> + Clone>(&mut self, bearings: I) -> (u32, Arc<[f64]>) {
+        if let Some(id) = self.last {
+            if same_bearings(&self.tables[id as usize], bearings.clone()) {
+                return (id, self.tables[id as usize].clone());
+            }
+        }
+
+        let mut hasher = DefaultHasher::new();
+
+        for b in bearings.clone() {
+            hasher.write_u64(b.to_bits());
+        }
+
+        let candidates = self.index.entry(hasher.finish()).or_default();
+        let found = candidates.iter().find(|id| same_bearings(&self.tables[**id as usize], bearings.clone())).copied();
+
+        let id = match found {
+            Some(id) => id,
+            None => {
+                let id = self.tables.len() as u32;
+                self.tables.push(bearings.collect());
+                candidates.push(id);
+                id
+            },
+        };
+
+        self.last = Some(id);
+        (id, self.tables[id as usize].clone())
+    }
+
+    /// The table with the given id.
+    ///
+    /// * `id` - the bearing_table_id.
+    pub fn get(&self, id: u32) -> Option<&Arc<[f64]>> {
+        self.tables.get(id as usize)
+    }
+
+    /// All the tables, indexed by id.
+    pub fn tables(&self) -> &[Arc<[f64]>] {
+        &self.tables
+    }
+
+    /// The number of distinct tables.
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    /// True if no tables have been interned.
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+}
+
+/// A distinct bearing table and the frames that use it.
+#[derive(Clone, PartialEq, Debug)]
+pub struct BearingTableUsage {
+    /// The bearing_table_id of the frames.
+    pub id: u32,
+    /// The table, shared with the frames.
+    pub table: Arc<[f64]>,
+    /// Indices into GLF::images of the frames using it, in order.
+    pub frames: Vec<usize>,
+}
+
+impl GLF {
+    /// List the distinct bearing tables in this GLF, in order of first
+    /// appearance, with the frames that use each.
+    pub fn bearing_tables(&self) -> Vec<BearingTableUsage> {
+        let mut usage: Vec<BearingTableUsage> = vec![];
+        // Ids are dense from zero in a parsed GLF, but don't rely on it.
+        let mut slots: HashMap<u32, usize> = HashMap::new();
+
+        for (idx, img_rec) in self.images.iter().enumerate() {
+            let slot = *slots.entry(img_rec.bearing_table_id).or_insert_with(|| {
+                usage.push(BearingTableUsage { id: img_rec.bearing_table_id, table: img_rec.bearing_table.clone(), frames: vec![] });
+                usage.len() - 1
+            });
+
+            usage[slot].frames.push(idx);
+        }
+
+        usage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{build_dat, glf_from_dat, TestImage};
+
+    #[test]
+    fn test_bearing_tables() {
+        let mut wide = TestImage::new(1, 2.0);
+        wide.bearings = 6;
+        let glf = glf_from_dat(build_dat(&[TestImage::new(1, 1.0), wide, TestImage::new(1, 3.0), TestImage::new(2, 3.5)]));
+
+        let tables = glf.bearing_tables();
+        assert_eq!(tables.len(), 2);
+        assert_eq!(tables[0].frames, vec![0, 2, 3]);
+        assert_eq!(tables[1].frames, vec![1]);
+        assert_eq!(tables[1].table.len(), 6);
+        assert!(Arc::ptr_eq(&glf.images[0].bearing_table, &glf.images[3].bearing_table));
+        assert_eq!(glf.images[2].bearing_table_id, 0);
+
+        let mut set = BearingTableSet::new();
+        assert_eq!(set.intern([0.1, 0.2].into_iter()).0, 0);
+        assert_eq!(set.intern([0.1].into_iter()).0, 1);
+        assert_eq!(set.intern([0.1, 0.2].into_iter()).0, 0);
+        assert_eq!(set.len(), 2);
+    }
+}
@@ -0,0 +1,509 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # glf
+//! A command line tool for looking inside GLF files.
+//!
+//! * `glf info <file>` - devices, duration, frame counts, ranges and sonar models.
+//! * `glf list <file>` - one line per record.
+//! * `glf extract <file> <dir>` - write frames as PNG or TIFF, raw or fan.
+//! * `glf convert <file> <output> --to <format>` - run one of the exporters.
+//! * `glf validate <files>` - check files for damage, failing if any are.
+//! * `glf diff <left> <right>` - compare two files record by record.
+//!
+//! Output is for people by default, or JSON with `--json`. As with diff(1),
+//! the exit status is 0 on success, 1 if `validate` finds damage or `diff`
+//! finds differences, and 2 if a file can't be read or parsed.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use glf::{AnnotationFormat, DatasetOptions, DiffOptions, FrameKind, HeaderType, IssueKind, Severity, Validator, GLF};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+#[derive(Parser)]
+#[command(name = "glf", version, about = "Inspect and convert Tritech GLF files.")]
+struct Cli {
+    /// Print JSON rather than text.
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Summarise the devices and frames in a GLF.
+    Info {
+        /// The GLF file.
+        file: PathBuf,
+    },
+    /// Print one line per record.
+    List {
+        /// The GLF file.
+        file: PathBuf,
+    },
+    /// Write frames as image files.
+    Extract {
+        /// The GLF file.
+        file: PathBuf,
+        /// The directory to write the images into.
+        output: PathBuf,
+        /// The image file format.
+        #[arg(long, value_enum, default_value_t = ImageFormat::Png)]
+        format: ImageFormat,
+        #[command(flatten)]
+        select: Select,
+    },
+    /// Convert a GLF with one of the exporters.
+    Convert {
+        /// The GLF file.
+        file: PathBuf,
+        /// The file, or directory for csv, parquet and dataset, to write.
+        output: PathBuf,
+        /// What to convert to.
+        #[arg(long, value_enum)]
+        to: ConvertFormat,
+        /// Write YOLO labels rather than COCO for a dataset.
+        #[arg(long)]
+        yolo: bool,
+        #[command(flatten)]
+        select: Select,
+    },
+    /// Check GLF files for damage. Exits 1 if any fail, or 2 if any can't be opened.
+    Validate {
+        /// The GLF files.
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Skip decompressing the image payloads.
+        #[arg(long)]
+        quick: bool,
+    },
+    /// Compare two GLF files record by record. Exits 1 if they differ, or 2 if either can't be read.
+    Diff {
+        /// The original file.
+        left: PathBuf,
+        /// The file to compare with it.
+        right: PathBuf,
+        /// Pixels differing by this much or less count as the same.
+        #[arg(long, default_value_t = 0)]
+        tolerance: u8,
+        /// Milliseconds two timestamps may differ by and still be the same record.
+        #[arg(long, default_value_t = 1)]
+        time_tolerance: i64,
+        /// Only compare the metadata, not the pixels.
+        #[arg(long)]
+        no_pixels: bool,
+        /// A metadata field to leave out, such as `header.node_id`. May be repeated.
+        #[arg(long)]
+        ignore: Vec<String>,
+    },
+}
+
+/// Which frames to use, and in what form.
+#[derive(Args)]
+struct Select {
+    /// Only frames from this device.
+    #[arg(long)]
+    device: Option<u16>,
+    /// Only frames at or after this RFC3339 time.
+    #[arg(long)]
+    start: Option<DateTime<Utc>>,
+    /// Only frames at or before this RFC3339 time.
+    #[arg(long)]
+    end: Option<DateTime<Utc>>,
+    /// Scan convert the frames to a fan of this width in pixels.
+    #[arg(long)]
+    fan: Option<u32>,
+}
+
+impl Select {
+    fn frames(&self, glf: &GLF) -> Vec<usize> {
+        glf.images.iter().enumerate()
+            .filter(|(_, img_rec)| self.device.is_none_or(|d| img_rec.header.device_id == d))
+            .filter(|(_, img_rec)| self.start.is_none_or(|t| img_rec.header.time >= t))
+            .filter(|(_, img_rec)| self.end.is_none_or(|t| img_rec.header.time <= t))
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    fn kind(&self) -> FrameKind {
+        match self.fan {
+            Some(width) => FrameKind::Fan(width),
+            None => FrameKind::Raw,
+        }
+    }
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum ImageFormat {
+    Png,
+    Tiff,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum ConvertFormat {
+    /// NumPy archive of one device's frames.
+    Npz,
+    /// MCAP for Foxglove and ROS 2.
+    Mcap,
+    /// Record metadata as newline delimited JSON.
+    Ndjson,
+    /// Status telemetry, one CSV per device.
+    Csv,
+    /// Status telemetry, one Parquet file per device.
+    Parquet,
+    /// Images and COCO or YOLO annotations from the annotation sidecar.
+    Dataset,
+}
+
+fn time_str(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| "glf".to_string())
+}
+
+fn info(glf: &GLF) -> Value {
+    let mut devices: BTreeMap<u16, Vec<usize>> = BTreeMap::new();
+
+    for (idx, img_rec) in glf.images.iter().enumerate() {
+        devices.entry(img_rec.header.device_id).or_default().push(idx);
+    }
+
+    let devices: Vec<Value> = devices.iter().map(|(device_id, frames)| {
+        let first = &glf.images[frames[0]];
+        let last = &glf.images[frames[frames.len() - 1]];
+        let ranges: Vec<u32> = frames.iter().map(|idx| glf.images[*idx].range_end).collect();
+        let mut models: Vec<String> = frames.iter()
+            .map(|idx| match glf.images[*idx].model() {
+                Some(model) => model.name.to_string(),
+                None => glf.images[*idx].sonar_type.to_string(),
+            })
+            .collect();
+        models.sort();
+        models.dedup();
+
+        json!({
+            "device_id": device_id,
+            "frames": frames.len(),
+            "start": time_str(first.header.time),
+            "end": time_str(last.header.time),
+            "duration": (last.header.time - first.header.time).num_milliseconds() as f64 / 1000.0,
+            "range_min": ranges.iter().min(),
+            "range_max": ranges.iter().max(),
+            "models": models,
+        })
+    }).collect();
+
+    json!({
+        "file": glf.filepath.to_string_lossy(),
+        "entry": glf.entry_name,
+        "images": glf.images.len(),
+        "statuses": glf.statuses.len(),
+        "serials": glf.serials.len(),
+        "devices": devices,
+    })
+}
+
+fn print_info(value: &Value) {
+    println!("{}", value["file"].as_str().unwrap_or(""));
+    println!("  {} images, {} status records, {} serial records", value["images"], value["statuses"], value["serials"]);
+
+    for device in value["devices"].as_array().into_iter().flatten() {
+        let models: Vec<&str> = device["models"].as_array().into_iter().flatten().filter_map(|m| m.as_str()).collect();
+        println!("  device {}: {} frames, {} to {} ({}s), range {}-{}, {}",
+            device["device_id"], device["frames"], device["start"].as_str().unwrap_or(""), device["end"].as_str().unwrap_or(""),
+            device["duration"], device["range_min"], device["range_max"], models.join(", "));
+    }
+}
+
+fn list(glf: &GLF, as_json: bool) {
+    // Write through a lock and stop quietly if the reader goes away, as with `| head`.
+    let mut out = std::io::stdout().lock();
+
+    for (n, entry) in glf.records.iter().enumerate() {
+        let (header, detail) = match entry.record_type {
+            HeaderType::Image => {
+                let img_rec = &glf.images[entry.index];
+                (img_rec.header, json!({
+                    "width": img_rec.image_width,
+                    "height": img_rec.image_height,
+                    "range_end": img_rec.range_end,
+                    "gain": img_rec.percent_gain,
+                    "frequency": img_rec.modulation_frequency,
+                }))
+            },
+            HeaderType::GeminiStatus => (glf.statuses[entry.index].header, json!({})),
+            HeaderType::RawSerial => {
+                let serial_rec = &glf.serials[entry.index];
+                (serial_rec.header, json!({ "bytes": serial_rec.data.len() }))
+            },
+            _ => continue,
+        };
+
+        let line = if as_json {
+            json!({
+                "record": n,
+                "type": entry.record_type.to_string(),
+                "index": entry.index,
+                "device_id": header.device_id,
+                "time": time_str(header.time),
+                "offset": entry.offset,
+                "length": entry.length,
+                "detail": detail,
+            }).to_string()
+        } else {
+            let detail: Vec<String> = detail.as_object().into_iter().flatten().map(|(k, v)| format!("{}={}", k, v)).collect();
+            format!("{:>6} {:<14} {:>5} device {:<3} {} @{} +{} {}", n, entry.record_type.to_string(), entry.index,
+                header.device_id, time_str(header.time), entry.offset, entry.length, detail.join(" "))
+        };
+
+        if writeln!(out, "{}", line).is_err() {
+            return;
+        }
+    }
+}
+
+fn extract(glf: &GLF, output: &Path, format: ImageFormat, select: &Select) -> Result<Value, &'static str> {
+    fs::create_dir_all(output).map_err(|_| "Failed to create output directory.")?;
+    let stem = file_stem(&glf.filepath);
+    let ext = match format {
+        ImageFormat::Png => "png",
+        ImageFormat::Tiff => "tiff",
+    };
+    let mut written: Vec<Value> = vec![];
+
+    for idx in select.frames(glf) {
+        let img = match select.kind() {
+            FrameKind::Fan(width) => glf.extract_fan_image(idx, width)?,
+            FrameKind::Raw => glf.extract_image(idx)?,
+        };
+        let path = output.join(format!("{}_{:06}.{}", stem, idx, ext));
+        img.save(&path).map_err(|_| "Failed to save image.")?;
+        written.push(json!({ "frame": idx, "file": path.to_string_lossy() }));
+    }
+
+    Ok(json!({ "written": written }))
+}
+
+fn convert(glf: &GLF, output: &Path, to: ConvertFormat, yolo: bool, select: &Select) -> Result<Value, &'static str> {
+    let mut written: Vec<String> = vec![];
+    // Frames left out as they couldn't be decoded.
+    let mut skipped: Vec<usize> = vec![];
+
+    match to {
+        ConvertFormat::Npz => {
+            let frames = select.frames(glf);
+            let device_id = match (select.device, frames.first()) {
+                (Some(device_id), _) => device_id,
+                (None, Some(idx)) => glf.images[*idx].header.device_id,
+                (None, None) => return Err("No frames to export."),
+            };
+            let frames: Vec<usize> = frames.into_iter().filter(|idx| glf.images[*idx].header.device_id == device_id).collect();
+            let file = File::create(output).map_err(|_| "Failed to create npz file.")?;
+            glf::write_npz(glf, BufWriter::new(file), &frames, select.kind())?;
+            written.push(output.to_string_lossy().to_string());
+        },
+        ConvertFormat::Mcap => {
+            #[cfg(feature = "mcap")]
+            {
+                let options = glf::McapOptions { fan_width: select.fan, ..Default::default() };
+                skipped = glf.export_mcap(output, &options)?;
+                written.push(output.to_string_lossy().to_string());
+            }
+            #[cfg(not(feature = "mcap"))]
+            return Err("Built without the mcap feature.");
+        },
+        ConvertFormat::Ndjson => {
+            let mut file = BufWriter::new(File::create(output).map_err(|_| "Failed to create NDJSON file.")?);
+            glf.export_metadata_json(&mut file, false)?;
+            file.flush().map_err(|_| "Failed to write NDJSON file.")?;
+            written.push(output.to_string_lossy().to_string());
+        },
+        ConvertFormat::Csv | ConvertFormat::Parquet => {
+            fs::create_dir_all(output).map_err(|_| "Failed to create output directory.")?;
+            let stem = file_stem(&glf.filepath);
+
+            for series in glf.telemetry().iter().filter(|s| select.device.is_none_or(|d| s.device_id == d)) {
+                let ext = if matches!(to, ConvertFormat::Csv) { "csv" } else { "parquet" };
+                let path = output.join(format!("{}_{}.{}", stem, series.device_id, ext));
+                let file = File::create(&path).map_err(|_| "Failed to create telemetry file.")?;
+
+                if matches!(to, ConvertFormat::Csv) {
+                    let mut file = BufWriter::new(file);
+                    series.write_csv(&mut file).map_err(|_| "Failed to write CSV.")?;
+                    file.flush().map_err(|_| "Failed to write CSV.")?;
+                } else {
+                    #[cfg(feature = "parquet")]
+                    series.write_parquet(file)?;
+                    #[cfg(not(feature = "parquet"))]
+                    return Err("Built without the parquet feature.");
+                }
+
+                written.push(path.to_string_lossy().to_string());
+            }
+        },
+        ConvertFormat::Dataset => {
+            let options = DatasetOptions {
+                kind: select.kind(),
+                format: if yolo { AnnotationFormat::Yolo } else { AnnotationFormat::Coco },
+                ..Default::default()
+            };
+            let boxes = glf.load_annotations()?.boxes(glf, chrono::Duration::milliseconds(1));
+            skipped = glf.export_dataset(output, &select.frames(glf), boxes, options)?.1;
+            written.push(output.to_string_lossy().to_string());
+        },
+    }
+
+    Ok(json!({ "written": written, "skipped": skipped }))
+}
+
+/// Exit status for differences or damage found.
+const EXIT_DIFFERENT: u8 = 1;
+/// Exit status for files that can't be read or parsed.
+const EXIT_TROUBLE: u8 = 2;
+
+fn validate(files: &[PathBuf], quick: bool, as_json: bool) -> ExitCode {
+    let validator = Validator { check_images: !quick, ..Default::default() };
+    let mut status: u8 = 0;
+
+    for file in files {
+        let report = validator.validate_file(file);
+
+        if report.issues.iter().any(|i| i.kind == IssueKind::Open) {
+            status = EXIT_TROUBLE;
+        } else if !report.is_valid() {
+            status = status.max(EXIT_DIFFERENT);
+        }
+
+        if as_json {
+            println!("{}", serde_json::to_string(&report).unwrap_or_default());
+        } else {
+            let status = if report.is_valid() { "ok" } else { "FAILED" };
+            println!("{}: {} ({} records, {} images, {} errors, {} warnings)", file.display(), status,
+                report.records, report.images, report.count(Severity::Error), report.count(Severity::Warning));
+
+            for issue in &report.issues {
+                println!("  {}", issue);
+            }
+        }
+    }
+
+    ExitCode::from(status)
+}
+
+fn diff(left: &GLF, right: &GLF, options: &DiffOptions, as_json: bool) -> Result<bool, &'static str> {
+    let diff = glf::diff_glf(left, right, options)?;
+
+    if as_json {
+        println!("{}", serde_json::to_string(&diff).map_err(|_| "Failed to encode diff.")?);
+        return Ok(diff.is_identical());
+    }
+
+    println!("{:<14} {:>8} {:>9} {:>8} {:>6} {:>8}", "type", "matched", "identical", "changed", "added", "missing");
+
+    for (record_type, s) in &diff.summary {
+        println!("{:<14} {:>8} {:>9} {:>8} {:>6} {:>8}", record_type, s.matched, s.identical, s.changed, s.added, s.missing);
+    }
+
+    if diff.frames_compared > 0 {
+        println!("{} frames compared: max {}, mean {:.4}, rmse {:.4}", diff.frames_compared, diff.max_abs, diff.mean_abs, diff.rmse);
+    }
+
+    if diff.frames_skipped > 0 {
+        println!("{} frames skipped: they couldn't be decoded", diff.frames_skipped);
+    }
+
+    for record in &diff.records {
+        let index = match (record.left, record.right) {
+            (Some(l), Some(r)) => format!("{} -> {}", l, r),
+            (Some(l), None) => l.to_string(),
+            (None, Some(r)) => r.to_string(),
+            (None, None) => String::new(),
+        };
+        println!("{:?} {} device {} {} [{}]", record.change, record.record_type, record.device_id, time_str(record.time), index);
+
+        for field in &record.fields {
+            println!("  {}: {} -> {}", field.field, field.left, field.right);
+        }
+
+        if let Some(p) = record.pixels.filter(|p| !p.is_same()) {
+            if p.size_mismatch {
+                println!("  pixels: frame sizes differ");
+            } else {
+                println!("  pixels: {} of {} differ, max {}, mean {:.4}", p.differing, p.pixels, p.max_abs, p.mean_abs);
+            }
+        }
+    }
+
+    Ok(diff.is_identical())
+}
+
+fn run(cli: &Cli) -> Result<ExitCode, &'static str> {
+    let output = match &cli.command {
+        Command::Info { file } => {
+            let value = info(&GLF::new(file)?);
+
+            if !cli.json {
+                print_info(&value);
+                return Ok(ExitCode::SUCCESS);
+            }
+
+            value
+        },
+        Command::List { file } => {
+            list(&GLF::new(file)?, cli.json);
+            return Ok(ExitCode::SUCCESS);
+        },
+        Command::Validate { files, quick } => {
+            return Ok(validate(files, *quick, cli.json));
+        },
+        Command::Diff { left, right, tolerance, time_tolerance, no_pixels, ignore } => {
+            let mut options = DiffOptions {
+                time_tolerance: chrono::Duration::milliseconds(*time_tolerance),
+                compare_pixels: !no_pixels,
+                pixel_tolerance: *tolerance,
+                ..Default::default()
+            };
+            options.ignore_fields.extend(ignore.iter().cloned());
+            let same = diff(&GLF::new(left)?, &GLF::new(right)?, &options, cli.json)?;
+            return Ok(if same { ExitCode::SUCCESS } else { ExitCode::from(EXIT_DIFFERENT) });
+        },
+        Command::Extract { file, output, format, select } => extract(&GLF::new(file)?, output, *format, select)?,
+        Command::Convert { file, output, to, yolo, select } => convert(&GLF::new(file)?, output, *to, *yolo, select)?,
+    };
+
+    if cli.json {
+        println!("{}", output);
+    } else {
+        for file in output["written"].as_array().into_iter().flatten() {
+            println!("{}", file.get("file").unwrap_or(file).as_str().unwrap_or(""));
+        }
+
+        if let Some(skipped) = output["skipped"].as_array().filter(|s| !s.is_empty()) {
+            eprintln!("glf: skipped {} frames that couldn't be decoded", skipped.len());
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match run(&cli) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("glf: {}", e);
+            ExitCode::from(EXIT_TROUBLE)
+        },
+    }
+}
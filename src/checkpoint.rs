@@ -0,0 +1,804 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # Checkpoint
+//! Random access into a deflated .dat entry. Deflate streams can only be
+//! read from the start, so one pass over the entry records checkpoints -
+//! the bit offset of a block boundary and the 32KB of output before it -
+//! every few megabytes, and saves them beside the GLF. Reading a frame
+//! afterwards only inflates from the nearest checkpoint before it, which
+//! keeps multi-gigabyte files interactive.
+//!
+//! The inflater here is a plain one, as restarting mid-stream needs the
+//! block boundaries and bit offsets that general purpose inflaters hide.
+//!
+//! A stored .dat needs no checkpoints, and is read straight from the file.
+
+use crate::ciheader::parse_header;
+use crate::decoder::{decode_payload, frame_len};
+use crate::archive::entry_file_name;
+use crate::{EntryCompression, GlfArchive, HeaderType, ImageRecordRef, RecordEntry};
+use byteorder::{ByteOrder, LittleEndian};
+use image::GrayImage;
+use miniz_oxide::inflate::core::DecompressorOxide;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// The most a deflate back reference can reach.
+const WINDOW: usize = 32768;
+/// Checkpoints are this many bytes of output apart unless asked otherwise.
+pub const DEFAULT_CHECKPOINT_SPACING: u64 = 4 << 20;
+/// Identifies a checkpoint sidecar, and its format version.
+const MAGIC: &[u8; 8] = b"GLFZIDX2";
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Reads a deflate stream a few bits at a time, least significant bit first.
+struct BitReader<R: Read> {
+    reader: R,
+    chunk: Vec<u8>,
+    pos: usize,
+    len: usize,
+    buffer: u64,
+    count: u32,
+    /// Bytes taken from the reader into the bit buffer.
+    taken: u64,
+}
+
+impl<R: Read> BitReader<R> {
+    fn new(reader: R) -> BitReader<R> {
+        BitReader { reader, chunk: vec![0; 65536], pos: 0, len: 0, buffer: 0, count: 0, taken: 0 }
+    }
+
+    /// Top up the bit buffer. At the end of the stream it is left short.
+    fn refill(&mut self) -> Result<(), &'static str> {
+        while self.count <= 56 {
+            if self.pos == self.len {
+                self.len = self.reader.read(&mut self.chunk).map_err(|_| "Failed to read the dat entry.")?;
+                self.pos = 0;
+
+                if self.len == 0 {
+                    return Ok(());
+                }
+            }
+
+            self.buffer |= (self.chunk[self.pos] as u64) << self.count;
+            self.pos += 1;
+            self.count += 8;
+            self.taken += 1;
+        }
+
+        Ok(())
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32, &'static str> {
+        if self.count < n {
+            self.refill()?;
+
+            if self.count < n {
+                return Err("Deflate stream ends early.");
+            }
+        }
+
+        let value = (self.buffer & ((1u64 << n) - 1)) as u32;
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    /// Skip to the next byte boundary.
+    fn align(&mut self) {
+        let skip = self.count % 8;
+        self.buffer >>= skip;
+        self.count -= skip;
+    }
+
+    /// How many bits of the stream have been read.
+    fn position(&self) -> u64 {
+        self.taken * 8 - self.count as u64
+    }
+
+    fn decode(&mut self, huffman: &Huffman) -> Result<u16, &'static str> {
+        if self.count < huffman.bits {
+            self.refill()?;
+        }
+
+        let entry = huffman.table[(self.buffer & ((1u64 << huffman.bits) - 1)) as usize];
+        let len = (entry & 15) as u32;
+
+        if len == 0 || len > self.count {
+            return Err("Bad code in the deflate stream.");
+        }
+
+        self.buffer >>= len;
+        self.count -= len;
+        Ok(entry >> 4)
+    }
+}
+
+/// A canonical Huffman code as a lookup table. Each entry holds the symbol
+/// above the code length.
+struct Huffman {
+    table: Vec<u16>,
+    bits: u32,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman, &'static str> {
+        let mut count = [0u32; 16];
+
+        for len in lengths {
+            count[*len as usize] += 1;
+        }
+
+        count[0] = 0;
+        let mut next = [0u32; 16];
+
+        for len in 1..16 {
+            next[len] = (next[len - 1] + count[len - 1]) << 1;
+        }
+
+        let bits = lengths.iter().copied().max().unwrap_or(0).max(1) as u32;
+        let mut table = vec![0u16; 1 << bits];
+
+        for (symbol, len) in lengths.iter().enumerate() {
+            let len = *len as u32;
+
+            if len == 0 {
+                continue;
+            }
+
+            let code = next[len as usize];
+            next[len as usize] += 1;
+
+            if code >= 1 << len {
+                return Err("Oversubscribed Huffman code in the deflate stream.");
+            }
+
+            let mut i = (code.reverse_bits() >> (32 - len)) as usize;
+
+            while i < table.len() {
+                table[i] = ((symbol as u16) << 4) | len as u16;
+                i += 1 << len;
+            }
+        }
+
+        Ok(Huffman { table, bits })
+    }
+}
+
+/// Inflates a raw deflate stream a block at a time.
+struct Inflater<R: Read> {
+    bits: BitReader<R>,
+    fixed: Option<(Huffman, Huffman)>,
+    done: bool,
+}
+
+impl<R: Read> Inflater<R> {
+    fn new(reader: R) -> Inflater<R> {
+        Inflater { bits: BitReader::new(reader), fixed: None, done: false }
+    }
+
+    /// Inflate the next block onto the end of `out`, which must already hold
+    /// the output before it, up to a window's worth.
+    fn block(&mut self, out: &mut Vec<u8>) -> Result<(), &'static str> {
+        self.done = self.bits.bits(1)? == 1;
+
+        match self.bits.bits(2)? {
+            0 => {
+                self.bits.align();
+                let len = self.bits.bits(16)?;
+
+                if len != !self.bits.bits(16)? & 0xFFFF {
+                    return Err("Stored block length is corrupt.");
+                }
+
+                for _ in 0..len {
+                    out.push(self.bits.bits(8)? as u8);
+                }
+
+                Ok(())
+            },
+            1 => {
+                let (lit, dist) = match self.fixed.take() {
+                    Some(fixed) => fixed,
+                    None => {
+                        let mut lengths = [8u8; 288];
+                        lengths[144..256].fill(9);
+                        lengths[256..280].fill(7);
+                        (Huffman::new(&lengths)?, Huffman::new(&[5u8; 30])?)
+                    },
+                };
+
+                let result = self.codes(out, &lit, &dist);
+                self.fixed = Some((lit, dist));
+                result
+            },
+            2 => {
+                let (lit, dist) = self.dynamic_tables()?;
+                self.codes(out, &lit, &dist)
+            },
+            _ => Err("Invalid deflate block type."),
+        }
+    }
+
+    fn dynamic_tables(&mut self) -> Result<(Huffman, Huffman), &'static str> {
+        let nlit = self.bits.bits(5)? as usize + 257;
+        let ndist = self.bits.bits(5)? as usize + 1;
+        let ncode = self.bits.bits(4)? as usize + 4;
+        let mut code_lengths = [0u8; 19];
+
+        for i in 0..ncode {
+            code_lengths[CODE_LENGTH_ORDER[i]] = self.bits.bits(3)? as u8;
+        }
+
+        let code = Huffman::new(&code_lengths)?;
+        let mut lengths: Vec<u8> = Vec::with_capacity(nlit + ndist);
+
+        while lengths.len() < nlit + ndist {
+            let (value, repeat) = match self.bits.decode(&code)? {
+                len @ 0..=15 => (len as u8, 1),
+                16 => (*lengths.last().ok_or("Repeat with no previous length in the deflate stream.")?, 3 + self.bits.bits(2)?),
+                17 => (0, 3 + self.bits.bits(3)?),
+                _ => (0, 11 + self.bits.bits(7)?),
+            };
+
+            if lengths.len() + repeat as usize > nlit + ndist {
+                return Err("Too many code lengths in the deflate stream.");
+            }
+
+            lengths.resize(lengths.len() + repeat as usize, value);
+        }
+
+        if lengths[256] == 0 {
+            return Err("No end of block code in the deflate stream.");
+        }
+
+        Ok((Huffman::new(&lengths[..nlit])?, Huffman::new(&lengths[nlit..])?))
+    }
+
+    fn codes(&mut self, out: &mut Vec<u8>, lit: &Huffman, dist: &Huffman) -> Result<(), &'static str> {
+        loop {
+            let symbol = self.bits.decode(lit)? as usize;
+
+            if symbol < 256 {
+                out.push(symbol as u8);
+                continue;
+            }
+
+            if symbol == 256 {
+                return Ok(());
+            }
+
+            let i = symbol - 257;
+
+            if i >= LENGTH_BASE.len() {
+                return Err("Bad length code in the deflate stream.");
+            }
+
+            let len = LENGTH_BASE[i] as usize + self.bits.bits(LENGTH_EXTRA[i] as u32)? as usize;
+            let d = self.bits.decode(dist)? as usize;
+
+            if d >= DIST_BASE.len() {
+                return Err("Bad distance code in the deflate stream.");
+            }
+
+            let distance = DIST_BASE[d] as usize + self.bits.bits(DIST_EXTRA[d] as u32)? as usize;
+
+            if distance > out.len() {
+                return Err("Deflate distance reaches before the start of the output.");
+            }
+
+            let start = out.len() - distance;
+
+            for k in 0..len {
+                let byte = out[start + k];
+                out.push(byte);
+            }
+        }
+    }
+}
+
+/// Where the .dat entry lies in a GLF, and what it should inflate to.
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct DatEntry {
+    name: String,
+    data_start: u64,
+    compressed_size: u64,
+    size: u64,
+    crc32: u32,
+    stored: bool,
+}
+
+/// Find a .dat entry of a GLF, or the first if no name is given.
+///
+/// * `path` - the path to the GLF.
+/// * `name` - the full name of the .dat entry, or None for the first.
+fn dat_entry(path: &Path, name: Option<&str>) -> Result<DatEntry, &'static str> {
+    let archive = GlfArchive::open(path)?;
+    let entry = match name {
+        Some(name) => archive.entry(name).ok_or("No such entry in the GLF.")?,
+        None => archive.dat_entries().next().ok_or("No .dat entry in the GLF.")?,
+    };
+
+    if entry.compression == EntryCompression::Other {
+        return Err("The .dat entry is neither stored nor deflated.");
+    }
+
+    Ok(DatEntry {
+        name: entry.name.clone(),
+        data_start: entry.data_start,
+        compressed_size: entry.compressed_size,
+        size: entry.size,
+        crc32: entry.crc32,
+        stored: entry.compression == EntryCompression::Stored,
+    })
+}
+
+/// A place the deflate stream can be restarted from.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Checkpoint {
+    /// Offset in the inflated .dat.
+    pub out_offset: u64,
+    /// Offset in the deflated entry, in bits.
+    pub in_bits: u64,
+    /// The output just before the checkpoint, up to 32KB.
+    pub window: Vec<u8>,
+}
+
+/// Checkpoints through the deflated .dat entry of a GLF.
+#[derive(Clone, PartialEq, Debug)]
+pub struct InflateIndex {
+    /// Checkpoints are at least this many bytes of output apart.
+    pub spacing: u64,
+    /// The checkpoints, in order, the first at the start of the entry. None
+    /// for a stored entry.
+    pub checkpoints: Vec<Checkpoint>,
+    entry: DatEntry,
+}
+
+/// The path of the checkpoint sidecar for a GLF - the GLF path with `.inflate.idx` added.
+///
+/// * `path` - the path to the GLF.
+pub fn inflate_index_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".inflate.idx");
+    PathBuf::from(name)
+}
+
+/// The path of the checkpoint sidecar for a named .dat entry of a GLF - the
+/// GLF path with the entry name and `.inflate.idx` added.
+///
+/// * `path` - the path to the GLF.
+/// * `entry` - the full name of the .dat entry.
+pub fn inflate_entry_index_path(path: &Path, entry: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}.inflate.idx", entry_file_name(entry)));
+    PathBuf::from(name)
+}
+
+impl InflateIndex {
+    /// Inflate the first .dat entry of a GLF once, recording a checkpoint at
+    /// the first block boundary after every `spacing` bytes of output. A
+    /// stored entry is only read through to check its CRC.
+    ///
+    /// * `path` - the path to the GLF.
+    /// * `spacing` - bytes of output between checkpoints.
+    pub fn build(path: &Path, spacing: u64) -> Result<InflateIndex, &'static str> {
+        InflateIndex::build_from(path, dat_entry(path, None)?, spacing)
+    }
+
+    /// Build the index of a named .dat entry, as `build` does for the first.
+    ///
+    /// * `path` - the path to the GLF.
+    /// * `entry` - the full name of the .dat entry.
+    /// * `spacing` - bytes of output between checkpoints.
+    pub fn build_entry(path: &Path, entry: &str, spacing: u64) -> Result<InflateIndex, &'static str> {
+        InflateIndex::build_from(path, dat_entry(path, Some(entry))?, spacing)
+    }
+
+    fn build_from(path: &Path, entry: DatEntry, spacing: u64) -> Result<InflateIndex, &'static str> {
+        let mut file = File::open(path).map_err(|_| "Failed to open GLF File")?;
+        file.seek(SeekFrom::Start(entry.data_start)).map_err(|_| "Failed to read the dat entry.")?;
+
+        if entry.stored {
+            let mut reader = file.take(entry.size);
+            let mut crc = crc32fast::Hasher::new();
+            let mut chunk = vec![0u8; 1 << 16];
+            let mut len: u64 = 0;
+
+            loop {
+                let n = reader.read(&mut chunk).map_err(|_| "Failed to read the dat entry.")?;
+
+                if n == 0 {
+                    break;
+                }
+
+                crc.update(&chunk[..n]);
+                len += n as u64;
+            }
+
+            if len != entry.size || crc.finalize() != entry.crc32 {
+                return Err("The .dat entry fails its CRC.");
+            }
+
+            return Ok(InflateIndex { spacing, checkpoints: vec![], entry });
+        }
+
+        let mut inflater = Inflater::new(file.take(entry.compressed_size));
+        let mut checkpoints: Vec<Checkpoint> = vec![];
+        let mut crc = crc32fast::Hasher::new();
+        let mut out: Vec<u8> = vec![];
+        // The offset in the .dat of out[0].
+        let mut base: u64 = 0;
+
+        while !inflater.done {
+            let offset = base + out.len() as u64;
+
+            if checkpoints.last().is_none_or(|last| offset - last.out_offset >= spacing) {
+                checkpoints.push(Checkpoint {
+                    out_offset: offset,
+                    in_bits: inflater.bits.position(),
+                    window: out[out.len().saturating_sub(WINDOW)..].to_vec(),
+                });
+            }
+
+            let start = out.len();
+            inflater.block(&mut out)?;
+            crc.update(&out[start..]);
+
+            if out.len() > 4 * WINDOW {
+                let drop = out.len() - WINDOW;
+                out.drain(..drop);
+                base += drop as u64;
+            }
+        }
+
+        if base + out.len() as u64 != entry.size {
+            return Err("The .dat entry inflated to the wrong size.");
+        }
+
+        if crc.finalize() != entry.crc32 {
+            return Err("The .dat entry fails its CRC.");
+        }
+
+        Ok(InflateIndex { spacing, checkpoints, entry })
+    }
+
+    /// The name of the .dat entry this indexes.
+    pub fn entry_name(&self) -> &str {
+        &self.entry.name
+    }
+
+    /// The size of the inflated .dat.
+    pub fn len(&self) -> u64 {
+        self.entry.size
+    }
+
+    /// True if the .dat is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entry.size == 0
+    }
+
+    /// Does this index still describe the GLF? It goes stale if the GLF is rewritten.
+    ///
+    /// * `path` - the path to the GLF.
+    pub fn matches(&self, path: &Path) -> bool {
+        dat_entry(path, Some(&self.entry.name)).is_ok_and(|entry| entry == self.entry)
+    }
+
+    /// Write the index to a sidecar file, ending with a CRC32 of the rest.
+    /// Windows are stored deflated.
+    ///
+    /// * `path` - the sidecar path, usually from inflate_index_path.
+    pub fn save(&self, path: &Path) -> Result<(), &'static str> {
+        let mut buffer: Vec<u8> = MAGIC.to_vec();
+        let push_u64 = |buffer: &mut Vec<u8>, v: u64| buffer.extend_from_slice(&v.to_le_bytes());
+
+        buffer.extend_from_slice(&(self.entry.name.len() as u16).to_le_bytes());
+        buffer.extend_from_slice(self.entry.name.as_bytes());
+        push_u64(&mut buffer, self.entry.data_start);
+        push_u64(&mut buffer, self.entry.compressed_size);
+        push_u64(&mut buffer, self.entry.size);
+        buffer.extend_from_slice(&self.entry.crc32.to_le_bytes());
+        buffer.push(self.entry.stored as u8);
+        push_u64(&mut buffer, self.spacing);
+        push_u64(&mut buffer, self.checkpoints.len() as u64);
+
+        for checkpoint in &self.checkpoints {
+            let window = miniz_oxide::deflate::compress_to_vec(&checkpoint.window, 6);
+            push_u64(&mut buffer, checkpoint.out_offset);
+            push_u64(&mut buffer, checkpoint.in_bits);
+            buffer.extend_from_slice(&(window.len() as u32).to_le_bytes());
+            buffer.extend_from_slice(&window);
+        }
+
+        let crc = crc32fast::hash(&buffer);
+        buffer.extend_from_slice(&crc.to_le_bytes());
+        fs::write(path, buffer).map_err(|_| "Failed to write the checkpoint index.")
+    }
+
+    /// Read an index from a sidecar file.
+    ///
+    /// * `path` - the sidecar path, usually from inflate_index_path.
+    pub fn load(path: &Path) -> Result<InflateIndex, &'static str> {
+        let buffer = fs::read(path).map_err(|_| "Failed to read the checkpoint index.")?;
+        let bad = "Checkpoint index is corrupt.";
+
+        if buffer.get(..8) != Some(&MAGIC[..]) {
+            return Err("Not a checkpoint index.");
+        }
+
+        let (body, crc) = buffer.split_at(buffer.len().max(12) - 4);
+
+        if crc.len() != 4 || crc32fast::hash(body) != LittleEndian::read_u32(crc) {
+            return Err(bad);
+        }
+
+        let mut pos = 8;
+        let mut take = |n: usize| -> Result<&[u8], &'static str> {
+            let bytes = body.get(pos..pos + n).ok_or(bad)?;
+            pos += n;
+            Ok(bytes)
+        };
+
+        let name_len = LittleEndian::read_u16(take(2)?) as usize;
+        let entry = DatEntry {
+            name: String::from_utf8(take(name_len)?.to_vec()).map_err(|_| bad)?,
+            data_start: LittleEndian::read_u64(take(8)?),
+            compressed_size: LittleEndian::read_u64(take(8)?),
+            size: LittleEndian::read_u64(take(8)?),
+            crc32: LittleEndian::read_u32(take(4)?),
+            stored: take(1)?[0] != 0,
+        };
+        let spacing = LittleEndian::read_u64(take(8)?);
+        let count = LittleEndian::read_u64(take(8)?);
+        let mut checkpoints: Vec<Checkpoint> = vec![];
+
+        for _ in 0..count {
+            let out_offset = LittleEndian::read_u64(take(8)?);
+            let in_bits = LittleEndian::read_u64(take(8)?);
+            let len = LittleEndian::read_u32(take(4)?) as usize;
+            let window = miniz_oxide::inflate::decompress_to_vec_with_limit(take(len)?, WINDOW).map_err(|_| bad)?;
+
+            // Checkpoints must lie inside the entry, in order.
+            if window.len() as u64 > out_offset
+                || out_offset > entry.size
+                || in_bits / 8 > entry.compressed_size
+                || checkpoints.last().is_some_and(|last| last.out_offset >= out_offset)
+            {
+                return Err(bad);
+            }
+
+            checkpoints.push(Checkpoint { out_offset, in_bits, window });
+        }
+
+        // Deflated entries restart from the first checkpoint, stored ones need none.
+        let starts = match entry.stored {
+            true => checkpoints.is_empty(),
+            false => checkpoints.first().is_some_and(|first| first.out_offset == 0),
+        };
+
+        if !starts {
+            return Err(bad);
+        }
+
+        Ok(InflateIndex { spacing, checkpoints, entry })
+    }
+}
+
+/// The .dat of a GLF, read a piece at a time through an InflateIndex.
+pub struct IndexedDat {
+    file: File,
+    index: InflateIndex,
+}
+
+impl IndexedDat {
+    /// Open a GLF for random access, using its checkpoint sidecar. If the
+    /// sidecar is missing or stale, the index is built and the sidecar
+    /// written, though a sidecar that can't be written isn't an error.
+    ///
+    /// * `path` - the path to the GLF.
+    pub fn open(path: &Path) -> Result<IndexedDat, &'static str> {
+        IndexedDat::open_sidecar(path, &inflate_index_path(path), dat_entry(path, None)?)
+    }
+
+    /// Open a named .dat entry of a GLF for random access, as `open` does
+    /// for the first. Its sidecar is from inflate_entry_index_path.
+    ///
+    /// * `path` - the path to the GLF.
+    /// * `entry` - the full name of the .dat entry.
+    pub fn open_entry(path: &Path, entry: &str) -> Result<IndexedDat, &'static str> {
+        IndexedDat::open_sidecar(path, &inflate_entry_index_path(path, entry), dat_entry(path, Some(entry))?)
+    }
+
+    fn open_sidecar(path: &Path, sidecar: &Path, entry: DatEntry) -> Result<IndexedDat, &'static str> {
+        let index = match InflateIndex::load(sidecar) {
+            Ok(index) if index.entry == entry => index,
+            _ => {
+                let index = InflateIndex::build_from(path, entry, DEFAULT_CHECKPOINT_SPACING)?;
+                let _ = index.save(sidecar);
+                index
+            },
+        };
+
+        IndexedDat::with_index(path, index)
+    }
+
+    /// Open a GLF for random access with an index already to hand.
+    ///
+    /// * `path` - the path to the GLF.
+    /// * `index` - the index of its .dat entry.
+    pub fn with_index(path: &Path, index: InflateIndex) -> Result<IndexedDat, &'static str> {
+        if !index.matches(path) {
+            return Err("Checkpoint index does not match the GLF.");
+        }
+
+        let file = File::open(path).map_err(|_| "Failed to open GLF File")?;
+        Ok(IndexedDat { file, index })
+    }
+
+    /// The index in use.
+    pub fn index(&self) -> &InflateIndex {
+        &self.index
+    }
+
+    /// Fill `buffer` from the .dat, starting at `offset`, inflating from the
+    /// nearest checkpoint before it, or reading it directly if stored.
+    ///
+    /// * `offset` - offset in the inflated .dat.
+    /// * `buffer` - the bytes to fill.
+    pub fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+        let end = offset.checked_add(buffer.len() as u64).filter(|end| *end <= self.index.len()).ok_or("Read runs past the end of the dat.")?;
+        if self.index.entry.stored {
+            self.file.seek(SeekFrom::Start(self.index.entry.data_start + offset)).map_err(|_| "Failed to read the dat entry.")?;
+            return self.file.read_exact(buffer).map_err(|_| "The .dat entry ends early.");
+        }
+
+        let slot = self.index.checkpoints.partition_point(|c| c.out_offset <= offset);
+        let checkpoint = &self.index.checkpoints[slot.saturating_sub(1)];
+
+        let byte = checkpoint.in_bits / 8;
+        self.file.seek(SeekFrom::Start(self.index.entry.data_start + byte)).map_err(|_| "Failed to read the dat entry.")?;
+        let mut inflater = Inflater::new((&self.file).take(self.index.entry.compressed_size - byte));
+        inflater.bits.bits((checkpoint.in_bits % 8) as u32)?;
+
+        let mut out = checkpoint.window.clone();
+        let mut base = checkpoint.out_offset - out.len() as u64;
+
+        while base + (out.len() as u64) < end && !inflater.done {
+            inflater.block(&mut out)?;
+
+            // Keep a window for back references, and nothing before the read.
+            let drop = (out.len() - WINDOW.min(out.len())).min(offset.saturating_sub(base) as usize);
+
+            if drop > 4 * WINDOW {
+                out.drain(..drop);
+                base += drop as u64;
+            }
+        }
+
+        let start = (offset - base) as usize;
+        let available = out.get(start..start + buffer.len()).ok_or("The .dat entry ends early.")?;
+        buffer.copy_from_slice(available);
+        Ok(())
+    }
+
+    /// Read the bytes of one record, header included.
+    ///
+    /// * `entry` - where the record lies, as in GLF::records.
+    pub fn read_record(&mut self, entry: &RecordEntry) -> Result<Vec<u8>, &'static str> {
+        let mut record = vec![0u8; entry.length];
+        self.read_at(entry.offset as u64, &mut record)?;
+        Ok(record)
+    }
+
+    /// Read and decode one image record.
+    ///
+    /// * `entry` - where the image record lies, as in GLF::records.
+    pub fn read_image(&mut self, entry: &RecordEntry) -> Result<GrayImage, &'static str> {
+        let record = self.read_record(entry)?;
+
+        let header = parse_header(&record, &mut 0)?;
+
+        if header.header_type != HeaderType::Image {
+            return Err("Record is not an image.");
+        }
+
+        let view = ImageRecordRef::parse(&header, &record, 21)?;
+        let img_rec = view.to_record();
+        let mut pixels = vec![0u8; frame_len(&img_rec)?];
+        decode_payload(&mut DecompressorOxide::new(), &img_rec, view.data(), &mut pixels)?;
+        GrayImage::from_raw(img_rec.image_width, img_rec.image_height, pixels).ok_or("Failed to create image.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{build_dat, glf_from_dat, push_record, write_glf, TestImage};
+
+    #[test]
+    fn test_inflate_index() {
+        let images: Vec<TestImage> = (0..40).map(|i| TestImage::new(1, i as f64)).collect();
+        let mut dat = build_dat(&images);
+        let mut state: u32 = 1;
+
+        // Serial records of noise, so the entry deflates to many blocks.
+        for i in 0..24 {
+            let noise: Vec<u8> = (0..8192).map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            }).collect();
+            push_record(&mut dat, 98, 1, 40.0 + i as f64, &noise);
+            dat.extend_from_slice(&build_dat(&[TestImage::new(1, 40.5 + i as f64)]));
+        }
+
+        let glf = glf_from_dat(dat.clone());
+        let path = std::env::temp_dir().join(format!("glf_inflate_{}.glf", std::process::id()));
+        write_glf(&path, &dat, zip::CompressionMethod::Deflated);
+
+        let index = InflateIndex::build(&path, 16384).unwrap();
+        assert!(index.checkpoints.len() > 2);
+        assert_eq!(index.len(), dat.len() as u64);
+
+        let reader = IndexedDat::open(&path).unwrap();
+        let sidecar = inflate_index_path(&path);
+        assert_eq!(InflateIndex::load(&sidecar).unwrap().checkpoints.len(), reader.index().checkpoints.len());
+
+        let mut reader = IndexedDat::with_index(&path, InflateIndex::load(&sidecar).unwrap()).unwrap();
+        let mut reader_small = IndexedDat::with_index(&path, index).unwrap();
+
+        for entry in glf.records.iter().rev() {
+            assert_eq!(reader_small.read_record(entry).unwrap(), &dat[entry.offset..entry.offset + entry.length]);
+
+            if entry.record_type == HeaderType::Image {
+                assert_eq!(reader.read_image(entry).unwrap(), glf.extract_image(entry.index).unwrap());
+            }
+        }
+
+        assert!(reader.read_at(dat.len() as u64 - 1, &mut [0u8; 2]).is_err());
+
+        // A flipped bit in the sidecar fails its CRC.
+        let mut bytes = fs::read(&sidecar).unwrap();
+        bytes[60] ^= 0x01;
+        fs::write(&sidecar, &bytes).unwrap();
+        assert_eq!(InflateIndex::load(&sidecar), Err("Checkpoint index is corrupt."));
+
+        // Sidecars with checkpoints past the entry or out of order are refused.
+        let mut corrupt = reader_small.index().clone();
+        corrupt.checkpoints[1].in_bits = (corrupt.entry.compressed_size + 1) * 8;
+        corrupt.save(&sidecar).unwrap();
+        assert!(InflateIndex::load(&sidecar).is_err());
+
+        let mut corrupt = reader_small.index().clone();
+        corrupt.checkpoints[2].out_offset = corrupt.checkpoints[1].out_offset;
+        corrupt.save(&sidecar).unwrap();
+        assert!(InflateIndex::load(&sidecar).is_err());
+
+        // An entry whose CRC doesn't match is refused.
+        let mut bytes = fs::read(&path).unwrap();
+        let crc = crc32fast::hash(&dat).to_le_bytes();
+
+        for at in 0..bytes.len() - 4 {
+            if bytes[at..at + 4] == crc {
+                bytes[at] ^= 0xff;
+            }
+        }
+
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(InflateIndex::build(&path, 16384).unwrap_err(), "The .dat entry fails its CRC.");
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&sidecar).unwrap();
+    }
+}
@@ -8,13 +8,14 @@
 //! 
 //! <https://rust-lang-nursery.github.io/rust-cookbook/datetime/parse.html#examine-the-date-and-time>
  
-use crate::epoch_gem;
+use crate::{epoch_gem, HeaderType};
 use chrono::{DateTime, Utc};
 use core::time::Duration;
 use byteorder::{ByteOrder, LittleEndian};
 use std::fmt;
 
 #[derive(Copy, PartialEq, Eq, Debug, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[non_exhaustive]
 pub struct CIHeader {
     /// The size of the header in bytes.
@@ -24,7 +25,7 @@ pub struct CIHeader {
     /// The time in UTC.
     pub time: DateTime<Utc>,
     /// The type of the header.
-    pub header_type: u8,
+    pub header_type: HeaderType,
     /// The device ID (the sonar id).
     pub device_id: u16,
     /// Node ID.
@@ -39,7 +40,7 @@ impl CIHeader {
             header_size: 21,
             payload_length: 0,
             time: Utc::now(),
-            header_type: 0,
+            header_type: HeaderType::Image,
             device_id: 0,
             node_id: 0
         }
@@ -59,28 +60,60 @@ impl fmt::Display for CIHeader {
 
 
 /// Extract the header from this part of the dat_buffer. Change the file_offset
-/// as a result.
+/// as a result. Fails if there is no header at the offset, or it runs past
+/// the end of the buffer.
 /// 
 /// * `dat_buffer` - a vector of byte.
 /// * `file_offset` - current offset in the buffer. 
-pub fn parse_header(dat_buffer: &Vec<u8>, file_offset: &mut i64) -> CIHeader{
+pub fn parse_header(dat_buffer: &[u8], file_offset: &mut i64) -> Result<CIHeader, &'static str> {
     // Parse a header, moving the file_offset along.
-    let fp: usize = *file_offset as usize;
     let mut header = CIHeader::new();
-    assert!(dat_buffer[0] as char == '*');
+    let fp = usize::try_from(*file_offset).map_err(|_| "Bad CIHeader in the dat buffer.")?;
+    let raw = dat_buffer.get(fp..).and_then(|b| b.get(..header.header_size as usize)).ok_or("CIHeader runs past the end of the dat buffer.")?;
+
+    if raw[0] != b'*' {
+        return Err("Bad CIHeader in the dat buffer.");
+    }
+
     // missing byte here, for version, is ignored for now
-    header.payload_length = LittleEndian::read_u32(&dat_buffer[(fp + 2)..(fp + 6)]) - (header.header_size as u32);
-    let tts = LittleEndian::read_f64(&dat_buffer[(fp + 6)..(fp + 14)]);
+    header.payload_length = LittleEndian::read_u32(&raw[2..6]).saturating_sub(header.header_size as u32);
+    let tts = LittleEndian::read_f64(&raw[6..14]);
     let tmillis = (tts as f64 * 1000.0).round() as u64;
     let dur : Duration = Duration::from_millis(tmillis);
     let epoch: chrono::prelude::DateTime<chrono::prelude::Utc> = epoch_gem();
     header.time = epoch + dur;
 
-    header.header_type = dat_buffer[fp + 14];
-    header.device_id = LittleEndian::read_u16(&dat_buffer[(fp + 15)..(fp + 17)]);
-    header.node_id = LittleEndian::read_u16(&dat_buffer[(fp + 17)..(fp + 19)]);
+    header.header_type = HeaderType::from(raw[14]);
+    header.device_id = LittleEndian::read_u16(&raw[15..17]);
+    header.node_id = LittleEndian::read_u16(&raw[17..19]);
 
     *file_offset = *file_offset + (header.header_size as i64);
 
-    header
-}
\ No newline at end of file
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{build_dat, TestImage};
+
+    #[test]
+    fn test_parse_header() {
+        let mut dat = vec![0u8; 3];
+        dat.extend_from_slice(&build_dat(&[TestImage::new(7, 2.5)]));
+
+        // The header is checked where it starts, not at the start of the buffer.
+        let mut offset: i64 = 3;
+        let header = parse_header(&dat, &mut offset).unwrap();
+        assert_eq!(offset, 24);
+        assert_eq!(header.device_id, 7);
+        assert_eq!(header.header_type, HeaderType::Image);
+        assert_eq!(header.payload_length as usize, dat.len() - 24);
+        assert_eq!(header.time, epoch_gem() + Duration::from_millis(2500));
+
+        assert!(parse_header(&dat, &mut 0).is_err());
+        assert!(parse_header(&dat[..23], &mut 3).is_err());
+        assert!(parse_header(&dat, &mut -1).is_err());
+        assert!(parse_header(&dat, &mut (dat.len() as i64)).is_err());
+    }
+}
@@ -0,0 +1,224 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # Codes
+//! Typed versions of the raw codes and flag words found in the records,
+//! following 0716-SDS-00001-005 (Genesis Log File Format).pdf. Every enum
+//! keeps an `Unknown` variant holding the raw value, and every flag type
+//! retains bits we don't have a name for, so no information is lost.
+
+use bitflags::bitflags;
+use std::fmt;
+
+/// Declare a code enum, with conversions to and from its raw integer.
+macro_rules! code_enum {
+    (
+        $(#[$meta:meta])*
+        $name:ident : $raw:ty {
+            $($(#[$vmeta:meta])* $variant:ident = $value:literal => $label:literal,)*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        pub enum $name {
+            $($(#[$vmeta])* $variant,)*
+            /// A value not described by the spec.
+            Unknown($raw),
+        }
+
+        impl From<$raw> for $name {
+            fn from(value: $raw) -> Self {
+                match value {
+                    $($value => $name::$variant,)*
+                    other => $name::Unknown(other),
+                }
+            }
+        }
+
+        impl From<$name> for $raw {
+            fn from(value: $name) -> Self {
+                match value {
+                    $($name::$variant => $value,)*
+                    $name::Unknown(other) => other,
+                }
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                match self {
+                    $($name::$variant => write!(f, $label),)*
+                    $name::Unknown(other) => write!(f, "unknown ({})", other),
+                }
+            }
+        }
+    };
+}
+
+code_enum! {
+    /// The type of record that follows a CIHeader.
+    HeaderType: u8 {
+        /// A sonar image.
+        Image = 0 => "image",
+        /// A V4 protocol message.
+        V4Protocol = 1 => "v4 protocol",
+        /// Analog video.
+        AnalogVideo = 2 => "analog video",
+        /// A Gemini status record.
+        GeminiStatus = 3 => "gemini status",
+        /// Raw serial data, such as NMEA from a GPS.
+        RawSerial = 98 => "raw serial",
+        /// A generic record.
+        Generic = 99 => "generic",
+    }
+}
+
+code_enum! {
+    /// How the image payload is compressed.
+    CompressionType: u16 {
+        /// Zlib deflated.
+        Zlib = 0 => "zlib",
+        /// Stored without compression.
+        Uncompressed = 1 => "none",
+        /// H264 video.
+        H264 = 2 => "h264",
+    }
+}
+
+code_enum! {
+    /// The sonar head that produced the image.
+    SonarType: u8 {
+        /// Gemini 720i.
+        Gemini720i = 1 => "Gemini 720i",
+        /// Gemini 720ik.
+        Gemini720ik = 2 => "Gemini 720ik",
+        /// Gemini 720im.
+        Gemini720im = 3 => "Gemini 720im",
+        /// Gemini 720is.
+        Gemini720is = 4 => "Gemini 720is",
+        /// Gemini 720id.
+        Gemini720id = 5 => "Gemini 720id",
+        /// Gemini 1200ik.
+        Gemini1200ik = 6 => "Gemini 1200ik",
+        /// Gemini 1200id.
+        Gemini1200id = 7 => "Gemini 1200id",
+        /// Micron Gemini.
+        MicronGemini = 8 => "Micron Gemini",
+    }
+}
+
+code_enum! {
+    /// The hardware platform of the sonar.
+    Platform: u8 {
+        /// Original Gemini electronics.
+        Mk1 = 1 => "mk1",
+        /// MK2 electronics.
+        Mk2 = 2 => "mk2",
+    }
+}
+
+code_enum! {
+    /// The link between the sonar and the surface.
+    LinkType: u16 {
+        /// Ethernet.
+        Ethernet = 0 => "ethernet",
+        /// VDSL.
+        Vdsl = 1 => "vdsl",
+        /// Serial (RS485).
+        Serial = 2 => "serial",
+    }
+}
+
+bitflags! {
+    /// Flags describing how a ping was made.
+    #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct PingFlags: u16 {
+        /// Set when the ping was made in high frequency mode.
+        const HIGH_FREQUENCY = 1 << 0;
+        /// Set when the speed of sound came from a sensor rather than the user.
+        const SOS_FROM_SENSOR = 1 << 1;
+        /// Set when the gain was set manually.
+        const MANUAL_GAIN = 1 << 15;
+        const _ = !0;
+    }
+}
+
+bitflags! {
+    /// State flags of the sonar when an image was taken.
+    #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct StateFlags: u32 {
+        /// Set when the image uses the high range resolution.
+        const HIGH_RANGE_RESOLUTION = 1 << 0;
+        /// Set when CHIRP was in use.
+        const CHIRP = 1 << 1;
+        const _ = !0;
+    }
+}
+
+bitflags! {
+    /// The flags word of a status record.
+    #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct StatusFlags: u16 {
+        /// Set when the sonar is in high frequency mode.
+        const HIGH_FREQUENCY = 1 << 0;
+        /// Set when the sonar is using the high range resolution.
+        const HIGH_RANGE_RESOLUTION = 1 << 1;
+        /// Set when the sonar has shut down due to temperature.
+        const OVER_TEMPERATURE = 1 << 2;
+        /// Set when the transmitter is enabled.
+        const TRANSMIT_ENABLED = 1 << 3;
+        /// Set when the sonar has detected it is out of the water.
+        const OUT_OF_WATER = 1 << 15;
+        const _ = !0;
+    }
+}
+
+bitflags! {
+    /// The reasons a sonar shut down.
+    #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct ShutdownStatus: u16 {
+        /// Shut down because it was too hot.
+        const OVER_TEMPERATURE = 1 << 0;
+        /// Shut down because it was out of the water.
+        const OUT_OF_WATER = 1 << 1;
+        /// The out of water indicator was set.
+        const OUT_OF_WATER_INDICATOR = 1 << 2;
+        const _ = !0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_enum() {
+        assert_eq!(HeaderType::from(3), HeaderType::GeminiStatus);
+        assert_eq!(u8::from(HeaderType::RawSerial), 98);
+        assert_eq!(HeaderType::from(42), HeaderType::Unknown(42));
+        assert_eq!(u8::from(HeaderType::Unknown(42)), 42);
+        assert_eq!(HeaderType::Unknown(42).to_string(), "unknown (42)");
+        assert_eq!(SonarType::from(6).to_string(), "Gemini 1200ik");
+
+        for raw in 0..=u16::MAX {
+            assert_eq!(u16::from(CompressionType::from(raw)), raw);
+        }
+    }
+
+    #[test]
+    fn test_flags_retain_bits() {
+        let flags = PingFlags::from_bits_retain(0x8005);
+        assert!(flags.contains(PingFlags::HIGH_FREQUENCY | PingFlags::MANUAL_GAIN));
+        assert!(!flags.contains(PingFlags::SOS_FROM_SENSOR));
+        assert_eq!(flags.bits(), 0x8005);
+        assert_eq!(StatusFlags::from_bits_retain(0xffff).bits(), 0xffff);
+        assert_eq!(ShutdownStatus::from_bits_truncate(0xff).bits(), 0xff);
+    }
+}
@@ -0,0 +1,545 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # Dataset
+//! Builds machine learning datasets from a GLF. Selected frames are written
+//! as PNG images, raw or scan converted, alongside a manifest recording where
+//! each image came from. Bounding boxes, given in pixel or range/bearing
+//! coordinates, are written as COCO JSON or YOLO text files.
+//!
+//! Frames are grouped into blocks of time before being split into train,
+//! validation and test sets, and frames within a guard gap of a block in
+//! another split are left out, so neighbouring frames, which are nearly
+//! identical, never end up on both sides of a split. The layout is:
+//!
+//! * `images/<split>/<name>.png`
+//! * `labels/<split>/<name>.txt` and `classes.txt` for YOLO.
+//! * `annotations/<split>.json` for COCO.
+//! * `manifest.json`
+
+use crate::fan::nearest_beam;
+use crate::{FanConverter, FrameKind, ImageRecord, GLF};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// How annotations are written.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AnnotationFormat {
+    /// One COCO JSON file per split.
+    Coco,
+    /// One YOLO text file per image.
+    Yolo,
+}
+
+/// The split a frame is assigned to.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Split {
+    /// Training set.
+    Train,
+    /// Validation set.
+    Val,
+    /// Test set.
+    Test,
+}
+
+impl Split {
+    /// The directory name of this split.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Split::Train => "train",
+            Split::Val => "val",
+            Split::Test => "test",
+        }
+    }
+}
+
+/// A bounding box, in the coordinates it was drawn in.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "coords", rename_all = "snake_case")]
+pub enum BoxCoords {
+    /// Pixels in the exported image, from the top left corner.
+    Pixel {
+        x: f64,
+        y: f64,
+        width: f64,
+        height: f64,
+    },
+    /// Range and bearing. The ranges are in samples from the sonar, like
+    /// range_start and range_end, not metres; divide a range in metres by
+    /// the range resolution of the frame to get samples. The first row of
+    /// the raw image is sample range_start.
+    RangeBearing {
+        /// Nearest range, in samples.
+        range_min: f64,
+        /// Furthest range, in samples.
+        range_max: f64,
+        /// First bearing, in radians.
+        bearing_min: f64,
+        /// Last bearing, in radians.
+        bearing_max: f64,
+    },
+}
+
+/// A labelled bounding box on one frame.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct BoxAnnotation {
+    /// Index into GLF::images.
+    pub frame: usize,
+    /// The class label.
+    pub label: String,
+    /// The box itself.
+    #[serde(flatten)]
+    pub bbox: BoxCoords,
+}
+
+/// Read an annotation file - a JSON array of BoxAnnotations.
+///
+/// * `path` - the annotation file.
+pub fn load_box_annotations(path: &Path) -> Result<Vec<BoxAnnotation>, &'static str> {
+    let text = fs::read_to_string(path).map_err(|_| "Failed to read annotation file.")?;
+    serde_json::from_str(&text).map_err(|_| "Failed to parse annotation file.")
+}
+
+/// Options for the dataset export.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct DatasetOptions {
+    /// Raw polar or scan converted images.
+    pub kind: FrameKind,
+    /// How to write the annotations.
+    pub format: AnnotationFormat,
+    /// Fraction of the time blocks used for training.
+    pub train: f64,
+    /// Fraction of the time blocks used for validation. The rest are for testing.
+    pub val: f64,
+    /// The length of a time block in seconds. Frames in the same block share a split.
+    pub block_seconds: f64,
+    /// Frames closer than this, in seconds, to a block in a different split
+    /// are dropped, so no two frames either side of a split change are
+    /// closer than twice this.
+    pub guard_seconds: f64,
+    /// Seed for assigning blocks to splits.
+    pub seed: u64,
+}
+
+impl Default for DatasetOptions {
+    fn default() -> Self {
+        DatasetOptions {
+            kind: FrameKind::Raw,
+            format: AnnotationFormat::Coco,
+            train: 0.7,
+            val: 0.15,
+            block_seconds: 60.0,
+            guard_seconds: 2.0,
+            seed: 0,
+        }
+    }
+}
+
+/// One image written by the exporter.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Path of the image, relative to the dataset directory.
+    pub file: String,
+    /// The split it belongs to.
+    pub split: Split,
+    /// The GLF it came from.
+    pub source: String,
+    /// Index into GLF::images.
+    pub frame: usize,
+    /// Time of the frame.
+    pub timestamp: DateTime<Utc>,
+    /// The device ID (the sonar id).
+    pub device_id: u16,
+    /// Width of the image in pixels.
+    pub width: u32,
+    /// Height of the image in pixels.
+    pub height: u32,
+    /// "raw" or "fan".
+    pub kind: String,
+    /// The starting range of the frame.
+    pub range_start: u32,
+    /// End of the range of the frame.
+    pub range_end: u32,
+    /// First bearing in radians.
+    pub bearing_min: f64,
+    /// Last bearing in radians.
+    pub bearing_max: f64,
+}
+
+/// A box in the pixel coordinates of an exported image.
+struct PixelBox {
+    label: String,
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+}
+
+/// Mix the bits of a block index - splitmix64 - for a stable pseudo-random split.
+fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Writes frames from a GLF as a machine learning dataset.
+pub struct DatasetExporter<'a> {
+    glf: &'a GLF,
+    options: DatasetOptions,
+    annotations: Vec<BoxAnnotation>,
+}
+
+impl<'a> DatasetExporter<'a> {
+    /// Create a new exporter.
+    ///
+    /// * `glf` - the GLF to read frames from.
+    /// * `options` - how to write the dataset.
+    pub fn new(glf: &'a GLF, options: DatasetOptions) -> DatasetExporter<'a> {
+        DatasetExporter { glf, options, annotations: vec![] }
+    }
+
+    /// Add bounding boxes to write with the frames.
+    ///
+    /// * `annotations` - the boxes.
+    pub fn with_annotations(mut self, annotations: Vec<BoxAnnotation>) -> DatasetExporter<'a> {
+        self.annotations = annotations;
+        self
+    }
+
+    /// The split of a time block.
+    fn block_split(&self, block: i64) -> Split {
+        let draw = (mix(block as u64 ^ self.options.seed) >> 11) as f64 / (1u64 << 53) as f64;
+
+        if draw < self.options.train {
+            Split::Train
+        } else if draw < self.options.train + self.options.val {
+            Split::Val
+        } else {
+            Split::Test
+        }
+    }
+
+    /// Assign a frame to a split, from the time block it falls in.
+    ///
+    /// * `time` - the time of the frame.
+    pub fn split_for(&self, time: DateTime<Utc>) -> Split {
+        let seconds = time.timestamp_micros() as f64 / 1e6;
+        self.block_split((seconds / self.options.block_seconds.max(1e-3)).floor() as i64)
+    }
+
+    /// Assign a frame to a split, or None if it falls within the guard gap
+    /// of a neighbouring block in a different split and should be dropped.
+    ///
+    /// * `time` - the time of the frame.
+    pub fn guarded_split_for(&self, time: DateTime<Utc>) -> Option<Split> {
+        let block_seconds = self.options.block_seconds.max(1e-3);
+        let seconds = time.timestamp_micros() as f64 / 1e6;
+        let block = (seconds / block_seconds).floor() as i64;
+        let split = self.block_split(block);
+        let into_block = seconds - block as f64 * block_seconds;
+
+        let near_previous = into_block < self.options.guard_seconds && self.block_split(block - 1) != split;
+        let near_next = block_seconds - into_block < self.options.guard_seconds && self.block_split(block + 1) != split;
+
+        if near_previous || near_next {
+            None
+        } else {
+            Some(split)
+        }
+    }
+
+    /// Convert a box into pixel coordinates of the exported image.
+    fn pixel_box(&self, ann: &BoxAnnotation, img_rec: &ImageRecord, converter: Option<&FanConverter>) -> PixelBox {
+        // Ranges from the sonar, made relative to the first row of the image.
+        let bbox = match ann.bbox {
+            BoxCoords::RangeBearing { range_min, range_max, bearing_min, bearing_max } => BoxCoords::RangeBearing {
+                range_min: range_min - img_rec.range_start as f64,
+                range_max: range_max - img_rec.range_start as f64,
+                bearing_min,
+                bearing_max,
+            },
+            pixel => pixel,
+        };
+
+        let (x0, y0, x1, y1) = match (bbox, converter) {
+            (BoxCoords::Pixel { x, y, width, height }, _) => (x, y, x + width, y + height),
+            (BoxCoords::RangeBearing { range_min, range_max, bearing_min, bearing_max }, None) => {
+                let b0 = nearest_beam(&img_rec.bearing_table, bearing_min) as f64;
+                let b1 = nearest_beam(&img_rec.bearing_table, bearing_max) as f64;
+                (b0.min(b1), range_min, b0.max(b1) + 1.0, range_max)
+            },
+            (BoxCoords::RangeBearing { range_min, range_max, bearing_min, bearing_max }, Some(conv)) => {
+                // Trace the outline of the sector and take its extent.
+                let mut xs: Vec<f64> = vec![];
+                let mut ys: Vec<f64> = vec![];
+
+                for step in 0..=16 {
+                    let bearing = bearing_min + (bearing_max - bearing_min) * step as f64 / 16.0;
+
+                    for range in [range_min, range_max] {
+                        let (x, y) = conv.fan_point(range, bearing);
+                        xs.push(x);
+                        ys.push(y);
+                    }
+                }
+
+                let fold = |v: &Vec<f64>, f: fn(f64, f64) -> f64, init: f64| v.iter().cloned().fold(init, f);
+                (fold(&xs, f64::min, f64::MAX), fold(&ys, f64::min, f64::MAX), fold(&xs, f64::max, f64::MIN), fold(&ys, f64::max, f64::MIN))
+            },
+        };
+
+        PixelBox { label: ann.label.clone(), x: x0, y: y0, width: x1 - x0, height: y1 - y0 }
+    }
+
+    /// Write the dataset for the given frames into a directory. Frames in
+    /// a guard gap are skipped, and left out of the manifest, as are frames
+    /// that can't be decoded. Returns the manifest, and the frames that
+    /// couldn't be decoded.
+    ///
+    /// * `dir` - the dataset directory, created if needed.
+    /// * `frames` - indices into GLF::images.
+    pub fn export(&self, dir: &Path, frames: &[usize]) -> Result<(Vec<ManifestEntry>, Vec<usize>), &'static str> {
+        let source = self.glf.filepath.to_string_lossy().to_string();
+        let stem = self.glf.filepath.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| "glf".to_string());
+        let labels: Vec<String> = self.annotations.iter().map(|a| a.label.clone()).collect::<BTreeSet<String>>().into_iter().collect();
+        let splits = [Split::Train, Split::Val, Split::Test];
+        let mut manifest: Vec<ManifestEntry> = vec![];
+        let mut skipped: Vec<usize> = vec![];
+        let mut coco_images: Vec<Vec<serde_json::Value>> = vec![vec![]; 3];
+        let mut coco_annotations: Vec<Vec<serde_json::Value>> = vec![vec![]; 3];
+
+        for split in splits {
+            fs::create_dir_all(dir.join("images").join(split.name())).map_err(|_| "Failed to create dataset directory.")?;
+
+            if self.options.format == AnnotationFormat::Yolo {
+                fs::create_dir_all(dir.join("labels").join(split.name())).map_err(|_| "Failed to create dataset directory.")?;
+            }
+        }
+
+        // Scan conversion tables, by bearing table id and samples per beam.
+        let mut converters: HashMap<(u32, u32), FanConverter> = HashMap::new();
+
+        for idx in frames {
+            let img_rec = self.glf.images.get(*idx).ok_or("Frame index out of range.")?;
+            let split = match self.guarded_split_for(img_rec.header.time) {
+                Some(split) => split,
+                None => continue,
+            };
+            let polar = match self.glf.extract_image(*idx) {
+                Ok(polar) => polar,
+                Err(_) => {
+                    skipped.push(*idx);
+                    continue;
+                },
+            };
+            let converter = match self.options.kind {
+                FrameKind::Raw => None,
+                FrameKind::Fan(width) => {
+                    let conv = match converters.entry((img_rec.bearing_table_id, img_rec.image_height)) {
+                        Entry::Occupied(entry) => entry.into_mut(),
+                        Entry::Vacant(entry) => entry.insert(FanConverter::new(&img_rec.bearing_table, img_rec.image_height, width)?),
+                    };
+
+                    Some(&*conv)
+                },
+            };
+            let img = match converter {
+                Some(conv) => conv.convert(&polar)?,
+                None => polar,
+            };
+
+            let name = format!("{}_{:06}", stem, idx);
+            let file = format!("images/{}/{}.png", split.name(), name);
+            img.save(dir.join(&file)).map_err(|_| "Failed to save dataset image.")?;
+
+            let boxes: Vec<PixelBox> = self.annotations.iter()
+                .filter(|a| a.frame == *idx)
+                .map(|a| self.pixel_box(a, img_rec, converter))
+                .collect();
+
+            match self.options.format {
+                AnnotationFormat::Yolo => {
+                    let mut text = String::new();
+
+                    for b in &boxes {
+                        let class = labels.iter().position(|l| *l == b.label).unwrap_or(0);
+                        let (w, h) = (img.width() as f64, img.height() as f64);
+                        text.push_str(&format!("{} {:.6} {:.6} {:.6} {:.6}\n", class,
+                            (b.x + b.width / 2.0) / w, (b.y + b.height / 2.0) / h, b.width / w, b.height / h));
+                    }
+
+                    fs::write(dir.join("labels").join(split.name()).join(format!("{}.txt", name)), text)
+                        .map_err(|_| "Failed to write YOLO labels.")?;
+                },
+                AnnotationFormat::Coco => {
+                    let s = splits.iter().position(|x| *x == split).unwrap_or(0);
+                    let image_id = coco_images[s].len() + 1;
+                    coco_images[s].push(json!({
+                        "id": image_id,
+                        "file_name": file,
+                        "width": img.width(),
+                        "height": img.height(),
+                    }));
+
+                    for b in &boxes {
+                        let category = labels.iter().position(|l| *l == b.label).unwrap_or(0) + 1;
+                        let id = coco_annotations[s].len() + 1;
+                        coco_annotations[s].push(json!({
+                            "id": id,
+                            "image_id": image_id,
+                            "category_id": category,
+                            "bbox": [b.x, b.y, b.width, b.height],
+                            "area": b.width * b.height,
+                            "iscrowd": 0,
+                        }));
+                    }
+                },
+            }
+
+            let bearing_min = img_rec.bearing_table.iter().cloned().fold(f64::MAX, f64::min);
+            let bearing_max = img_rec.bearing_table.iter().cloned().fold(f64::MIN, f64::max);
+            manifest.push(ManifestEntry {
+                file,
+                split,
+                source: source.clone(),
+                frame: *idx,
+                timestamp: img_rec.header.time,
+                device_id: img_rec.header.device_id,
+                width: img.width(),
+                height: img.height(),
+                kind: if converter.is_some() { "fan".to_string() } else { "raw".to_string() },
+                range_start: img_rec.range_start,
+                range_end: img_rec.range_end,
+                bearing_min,
+                bearing_max,
+            });
+        }
+
+        match self.options.format {
+            AnnotationFormat::Yolo => {
+                fs::write(dir.join("classes.txt"), labels.join("\n") + "\n").map_err(|_| "Failed to write YOLO classes.")?;
+            },
+            AnnotationFormat::Coco => {
+                fs::create_dir_all(dir.join("annotations")).map_err(|_| "Failed to create dataset directory.")?;
+                let categories: Vec<serde_json::Value> = labels.iter().enumerate()
+                    .map(|(i, l)| json!({ "id": i + 1, "name": l }))
+                    .collect();
+
+                for (s, split) in splits.iter().enumerate() {
+                    let coco = json!({
+                        "images": coco_images[s],
+                        "annotations": coco_annotations[s],
+                        "categories": categories,
+                    });
+                    let text = serde_json::to_string_pretty(&coco).map_err(|_| "Failed to encode COCO annotations.")?;
+                    fs::write(dir.join("annotations").join(format!("{}.json", split.name())), text)
+                        .map_err(|_| "Failed to write COCO annotations.")?;
+                }
+            },
+        }
+
+        let text = serde_json::to_string_pretty(&manifest).map_err(|_| "Failed to encode manifest.")?;
+        fs::write(dir.join("manifest.json"), text).map_err(|_| "Failed to write manifest.")?;
+        Ok((manifest, skipped))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{build_dat, glf_from_dat, TestImage};
+
+    #[test]
+    fn test_export_yolo() {
+        let glf = glf_from_dat(build_dat(&[TestImage::new(1, 1.0), TestImage::new(1, 2.0)]));
+        let dir = std::env::temp_dir().join(format!("glf_dataset_{}", std::process::id()));
+        let options = DatasetOptions { format: AnnotationFormat::Yolo, train: 1.0, val: 0.0, ..Default::default() };
+        let annotations = vec![BoxAnnotation {
+            frame: 1,
+            label: "fish".to_string(),
+            bbox: BoxCoords::Pixel { x: 1.0, y: 2.0, width: 2.0, height: 4.0 },
+        }];
+
+        let (manifest, skipped) = glf.export_dataset(&dir, &[0, 1], annotations, options).unwrap();
+        assert_eq!(manifest.len(), 2);
+        assert!(skipped.is_empty());
+        assert!(manifest.iter().all(|m| m.split == Split::Train && m.width == 4 && m.height == 8));
+        assert!(dir.join(&manifest[0].file).exists());
+
+        let labels = fs::read_to_string(dir.join("labels/train").join("test_000001.txt")).unwrap();
+        assert_eq!(labels.trim(), "0 0.500000 0.500000 0.500000 0.500000");
+        assert_eq!(fs::read_to_string(dir.join("classes.txt")).unwrap(), "fish\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_export_skips_frames() {
+        let glf = glf_from_dat(build_dat(&[TestImage::new(1, 1.0), TestImage { h264: true, ..TestImage::new(1, 2.0) }, TestImage::new(1, 3.0)]));
+        let dir = std::env::temp_dir().join(format!("glf_dataset_skip_{}", std::process::id()));
+        let options = DatasetOptions { train: 1.0, val: 0.0, ..Default::default() };
+
+        let (manifest, skipped) = glf.export_dataset(&dir, &[0, 1, 2], vec![], options).unwrap();
+        assert_eq!(manifest.iter().map(|m| m.frame).collect::<Vec<_>>(), vec![0, 2]);
+        assert_eq!(skipped, vec![1]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_range_start() {
+        // Rows 0 to 5 of the image are samples 3 to 8 from the sonar.
+        let glf = glf_from_dat(build_dat(&[TestImage { range_start: 3, ..TestImage::new(1, 1.0) }]));
+        let dir = std::env::temp_dir().join(format!("glf_dataset_range_{}", std::process::id()));
+        let options = DatasetOptions { format: AnnotationFormat::Yolo, train: 1.0, val: 0.0, ..Default::default() };
+        let annotations = vec![BoxAnnotation {
+            frame: 0,
+            label: "fish".to_string(),
+            bbox: BoxCoords::RangeBearing { range_min: 4.0, range_max: 6.0, bearing_min: -0.25, bearing_max: 0.0 },
+        }];
+
+        let (manifest, _) = glf.export_dataset(&dir, &[0], annotations, options).unwrap();
+        assert_eq!((manifest[0].range_start, manifest[0].height), (3, 5));
+
+        // Beams 1 and 2, rows 1 to 3.
+        let labels = fs::read_to_string(dir.join("labels/train").join("test_000000.txt")).unwrap();
+        assert_eq!(labels.trim(), "0 0.500000 0.400000 0.500000 0.400000");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_split_guard() {
+        // Whole seconds from the epoch_gem land on block edges, as 1980 is a multiple of 10s from 1970.
+        let glf = glf_from_dat(build_dat(&[5.0, 9.5, 10.5, 15.0].map(|t| TestImage::new(1, t))));
+        let times: Vec<DateTime<Utc>> = glf.images.iter().map(|r| r.header.time).collect();
+        let options = |seed| DatasetOptions { train: 0.5, val: 0.5, block_seconds: 10.0, guard_seconds: 1.0, seed, ..Default::default() };
+        let exporter = |seed| DatasetExporter::new(&glf, options(seed));
+
+        // Find seeds where the blocks either side of the edge differ, and agree.
+        let differ = (0..64).find(|s| exporter(*s).split_for(times[0]) != exporter(*s).split_for(times[3])).unwrap();
+        let agree = (0..64).find(|s| exporter(*s).split_for(times[0]) == exporter(*s).split_for(times[3])).unwrap();
+
+        let split = exporter(differ);
+        assert_eq!(split.split_for(times[1]), split.split_for(times[0]));
+        assert_eq!(split.split_for(times[2]), split.split_for(times[3]));
+        assert_eq!(split.guarded_split_for(times[0]), Some(split.split_for(times[0])));
+        assert_eq!(split.guarded_split_for(times[1]), None);
+        assert_eq!(split.guarded_split_for(times[2]), None);
+        assert_eq!(split.guarded_split_for(times[3]), Some(split.split_for(times[3])));
+        assert!(exporter(agree).guarded_split_for(times[1]).is_some());
+        assert!(exporter(agree).guarded_split_for(times[2]).is_some());
+
+        let dir = std::env::temp_dir().join(format!("glf_dataset_guard_{}", std::process::id()));
+        let (manifest, _) = glf.export_dataset(&dir, &[0, 1, 2, 3], vec![], options(differ)).unwrap();
+        assert_eq!(manifest.iter().map(|m| m.frame).collect::<Vec<_>>(), vec![0, 3]);
+        assert_ne!(manifest[0].split, manifest[1].split);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
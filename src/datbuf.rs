@@ -0,0 +1,184 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # Dat buffer
+//! The bytes of the .dat entry. Usually the entry is deflated and has to be
+//! read into memory, but a GLF whose .dat is stored holds it verbatim, so
+//! with the `mmap` feature `GLF::new_mapped` maps the file and the records
+//! are read straight from it. Either way the buffer derefs to a byte slice.
+//!
+//! `GLF::dat` was a `Vec<u8>` before 0.3. Reading code works unchanged
+//! through the slice; code that grew or replaced the vector should use
+//! `to_mut` or `into_vec`.
+
+use std::ops::{Deref, DerefMut};
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
+#[cfg(feature = "mmap")]
+use std::fs::File;
+#[cfg(feature = "mmap")]
+use std::sync::Arc;
+
+/// The bytes of a .dat, held in memory or mapped from the GLF.
+#[derive(Clone)]
+pub enum DatBuffer {
+    /// Read, and if need be inflated, into memory.
+    Owned(Vec<u8>),
+    /// A stored entry, mapped from the GLF file.
+    #[cfg(feature = "mmap")]
+    Mapped {
+        /// The whole GLF file.
+        map: Arc<Mmap>,
+        /// Offset of the entry's data in the file.
+        start: usize,
+        /// Length of the entry.
+        len: usize,
+    },
+}
+
+impl DatBuffer {
+    /// True if the bytes are mapped from the file rather than held in memory.
+    pub fn is_mapped(&self) -> bool {
+        match self {
+            DatBuffer::Owned(_) => false,
+            #[cfg(feature = "mmap")]
+            DatBuffer::Mapped { .. } => true,
+        }
+    }
+
+    /// The bytes as a mutable vector, copying them into memory if they are mapped.
+    pub fn to_mut(&mut self) -> &mut Vec<u8> {
+        #[cfg(feature = "mmap")]
+        if self.is_mapped() {
+            *self = DatBuffer::Owned(self.to_vec());
+        }
+
+        match self {
+            DatBuffer::Owned(buffer) => buffer,
+            #[cfg(feature = "mmap")]
+            DatBuffer::Mapped { .. } => unreachable!(),
+        }
+    }
+
+    /// The bytes as a vector, copying them if they are mapped.
+    pub fn into_vec(self) -> Vec<u8> {
+        match self {
+            DatBuffer::Owned(buffer) => buffer,
+            #[cfg(feature = "mmap")]
+            DatBuffer::Mapped { .. } => self.to_vec(),
+        }
+    }
+}
+
+impl Default for DatBuffer {
+    fn default() -> Self {
+        DatBuffer::Owned(vec![])
+    }
+}
+
+impl Deref for DatBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            DatBuffer::Owned(buffer) => buffer,
+            #[cfg(feature = "mmap")]
+            DatBuffer::Mapped { map, start, len } => &map[*start..*start + *len],
+        }
+    }
+}
+
+impl DerefMut for DatBuffer {
+    /// Copies mapped bytes into memory first, so the file is never written.
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.to_mut()
+    }
+}
+
+impl AsRef<[u8]> for DatBuffer {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl From<Vec<u8>> for DatBuffer {
+    fn from(buffer: Vec<u8>) -> Self {
+        DatBuffer::Owned(buffer)
+    }
+}
+
+impl From<DatBuffer> for Vec<u8> {
+    fn from(buffer: DatBuffer) -> Self {
+        buffer.into_vec()
+    }
+}
+
+/// Map a stored entry of a GLF. Returns None if it can't be mapped.
+///
+/// # Safety
+///
+/// The file must not be changed or truncated while the buffer is alive.
+///
+/// * `file` - the open GLF file.
+/// * `start` - offset of the entry's data in the file.
+/// * `len` - length of the entry.
+#[cfg(feature = "mmap")]
+pub(crate) unsafe fn map_entry(file: &File, start: u64, len: u64) -> Option<DatBuffer> {
+    let start = usize::try_from(start).ok()?;
+    let len = usize::try_from(len).ok()?;
+    // Safety: passed on to the caller.
+    let map = unsafe { Mmap::map(file) }.ok()?;
+
+    if start.checked_add(len)? > map.len() {
+        return None;
+    }
+
+    Some(DatBuffer::Mapped { map: Arc::new(map), start, len })
+}
+
+#[cfg(all(test, feature = "mmap"))]
+mod tests {
+    use crate::testutil::{build_dat, write_glf, TestImage};
+    use crate::GLF;
+
+    #[test]
+    fn test_mapped_dat() {
+        let dat = build_dat(&[TestImage::new(1, 1.0), TestImage::new(1, 2.0)]);
+        let dir = std::env::temp_dir();
+        let stored = dir.join(format!("glf_stored_{}.glf", std::process::id()));
+        let deflated = dir.join(format!("glf_deflated_{}.glf", std::process::id()));
+        write_glf(&stored, &dat, zip::CompressionMethod::Stored);
+        write_glf(&deflated, &dat, zip::CompressionMethod::Deflated);
+
+        // Safety: the test files aren't changed until the GLFs are dropped.
+        let mapped = unsafe { GLF::new_mapped(&stored) }.unwrap();
+        let buffered = unsafe { GLF::new_mapped(&deflated) }.unwrap();
+        assert!(mapped.dat.is_mapped());
+        assert!(!buffered.dat.is_mapped());
+        assert!(!GLF::new(&stored).unwrap().dat.is_mapped());
+        assert_eq!(&*mapped.dat, &dat[..]);
+        assert_eq!(mapped.extract_image(1).unwrap(), buffered.extract_image(1).unwrap());
+
+        let mut copy = mapped.dat.clone();
+        copy.to_mut().push(0);
+        assert!(!copy.is_mapped());
+        assert_eq!(copy.len(), dat.len() + 1);
+        let mut copy = mapped.dat.clone();
+        copy[0] = b'#';
+        assert!(!copy.is_mapped());
+        assert_eq!(mapped.dat[0], b'*');
+
+        drop(mapped);
+
+        // A stored entry that fails its CRC is not mapped.
+        let mut bytes = std::fs::read(&stored).unwrap();
+        let at = bytes.windows(dat.len()).position(|w| w == &dat[..]).unwrap();
+        bytes[at + dat.len() - 1] ^= 0xff;
+        std::fs::write(&stored, &bytes).unwrap();
+        assert!(unsafe { GLF::new_mapped(&stored) }.is_err());
+        std::fs::remove_file(&stored).unwrap();
+        std::fs::remove_file(&deflated).unwrap();
+    }
+}
@@ -0,0 +1,179 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # Decoder
+//! Decodes frames into buffers the caller owns, for real-time playback and
+//! long exports where allocating a new image per frame adds up. The zlib
+//! payload is inflated straight into the output, so once a FrameDecoder's
+//! buffer has grown to the frame size, decoding allocates nothing.
+
+use crate::{CompressionType, ImageRecord, GLF};
+use miniz_oxide::inflate::core::inflate_flags::{TINFL_FLAG_PARSE_ZLIB_HEADER, TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF};
+use miniz_oxide::inflate::core::{decompress, DecompressorOxide};
+use miniz_oxide::inflate::TINFLStatus;
+
+/// Return an image record and its payload, checking the payload lies within the dat.
+///
+/// * `glf` - the GLF to read from.
+/// * `idx` - index into GLF::images.
+pub(crate) fn image_payload(glf: &GLF, idx: usize) -> Result<(&ImageRecord, &[u8]), &'static str> {
+    let img_rec = glf.images.get(idx).ok_or("Frame index out of range.")?;
+    let start = usize::try_from(img_rec.data_ptr).map_err(|_| "ptr exceeds image data length")?;
+    let end = start.checked_add(img_rec.data_size as usize).ok_or("ptr exceeds image data length")?;
+    let data = glf.dat.get(start..end).ok_or("ptr exceeds image data length")?;
+    Ok((img_rec, data))
+}
+
+/// The number of pixels in a frame.
+///
+/// * `img_rec` - the record of the frame.
+pub(crate) fn frame_len(img_rec: &ImageRecord) -> Result<usize, &'static str> {
+    (img_rec.image_width as usize).checked_mul(img_rec.image_height as usize).ok_or("Image is too large.")
+}
+
+/// Decode a payload into `out`, which must be exactly the frame size.
+///
+/// * `inflate` - the inflate state, reset before use.
+/// * `img_rec` - the record the payload belongs to.
+/// * `data` - the payload.
+/// * `out` - the output, `image_width * image_height` bytes.
+pub(crate) fn decode_payload(inflate: &mut DecompressorOxide, img_rec: &ImageRecord, data: &[u8], out: &mut [u8]) -> Result<(), &'static str> {
+    if out.len() != frame_len(img_rec)? {
+        return Err("Buffer is not image_width x image_height bytes.");
+    }
+
+    match img_rec.compression_type {
+        CompressionType::Zlib => {
+            inflate.init();
+            let flags = TINFL_FLAG_PARSE_ZLIB_HEADER | TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+            let (status, _, written) = decompress(inflate, data, out, 0, flags);
+
+            match status {
+                TINFLStatus::Done if written == out.len() => Ok(()),
+                TINFLStatus::Done => Err("Image decompressed to fewer bytes than the frame size."),
+                TINFLStatus::HasMoreOutput => Err("Image decompressed to more bytes than the frame size."),
+                _ => Err("Failed to decompress image."),
+            }
+        },
+        CompressionType::Uncompressed => {
+            if data.len() != out.len() {
+                return Err("Uncompressed image is not image_width x image_height bytes.");
+            }
+
+            out.copy_from_slice(data);
+            Ok(())
+        },
+        CompressionType::H264 => Err("H264 decompression not yet implemented."),
+        CompressionType::Unknown(_) => Err("Unknown image compression."),
+    }
+}
+
+/// Decodes frames, keeping its inflate state and output buffer between calls.
+pub struct FrameDecoder {
+    inflate: Box<DecompressorOxide>,
+    buffer: Vec<u8>,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        FrameDecoder::new()
+    }
+}
+
+impl FrameDecoder {
+    /// Create a decoder. The buffer grows to the largest frame decoded.
+    pub fn new() -> FrameDecoder {
+        FrameDecoder {
+            inflate: Box::default(),
+            buffer: vec![],
+        }
+    }
+
+    /// Decode a frame into a caller provided buffer.
+    ///
+    /// * `glf` - the GLF to read from.
+    /// * `idx` - index into GLF::images.
+    /// * `out` - the output, exactly `image_width * image_height` bytes.
+    pub fn decode_into(&mut self, glf: &GLF, idx: usize, out: &mut [u8]) -> Result<(), &'static str> {
+        let (img_rec, data) = image_payload(glf, idx)?;
+        decode_payload(&mut self.inflate, img_rec, data, out)
+    }
+
+    /// Decode a frame into the decoder's own buffer, returning the pixels row by
+    /// row. The slice is valid until the next call.
+    ///
+    /// * `glf` - the GLF to read from.
+    /// * `idx` - index into GLF::images.
+    pub fn decode(&mut self, glf: &GLF, idx: usize) -> Result<&[u8], &'static str> {
+        let (img_rec, data) = image_payload(glf, idx)?;
+        self.buffer.resize(frame_len(img_rec)?, 0);
+        decode_payload(&mut self.inflate, img_rec, data, &mut self.buffer)?;
+        Ok(&self.buffer)
+    }
+}
+
+impl GLF {
+    /// Extract an image into a caller provided buffer, rather than a new ImageBuffer.
+    /// Use a FrameDecoder to decode many frames.
+    ///
+    /// * `idx` - the index of the image we want.
+    /// * `out` - the output, exactly `image_width * image_height` bytes.
+    pub fn extract_image_into(&self, idx: usize, out: &mut [u8]) -> Result<(), &'static str> {
+        let (img_rec, data) = image_payload(self, idx)?;
+        decode_payload(&mut DecompressorOxide::new(), img_rec, data, out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{build_dat, glf_from_dat, TestImage};
+
+    #[test]
+    fn test_frame_decoder() {
+        let mut glf = glf_from_dat(build_dat(&[TestImage::new(1, 1.0), TestImage::new(1, 2.0)]));
+        let mut decoder = FrameDecoder::new();
+        let mut out = vec![0u8; 32];
+
+        for idx in 0..2 {
+            decoder.decode_into(&glf, idx, &mut out).unwrap();
+            assert_eq!(out, glf.extract_image(idx).unwrap().into_raw());
+            assert_eq!(decoder.decode(&glf, idx).unwrap(), &out[..]);
+        }
+
+        assert!(glf.extract_image_into(0, &mut [0u8; 31]).is_err());
+
+        // The same pixels, zlib compressed.
+        let pixels = glf.extract_image(1).unwrap().into_raw();
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&pixels, 6);
+        glf.images[1].data_ptr = glf.dat.len() as u64;
+        glf.images[1].data_size = compressed.len() as u32;
+        glf.images[1].compression_type = CompressionType::Zlib;
+        glf.dat.to_mut().extend_from_slice(&compressed);
+        glf.extract_image_into(1, &mut out).unwrap();
+        assert_eq!(out, pixels);
+        assert!(decoder.decode_into(&glf, 1, &mut [0u8; 33]).is_err());
+
+        // extract_image refuses what the decoder refuses: payloads bigger than
+        // the frame, raw or inflated, and unknown compression.
+        let mut oversized = pixels.clone();
+        oversized.push(0);
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&oversized, 6);
+        glf.images[1].data_ptr = glf.dat.len() as u64;
+        glf.images[1].data_size = compressed.len() as u32;
+        glf.dat.to_mut().extend_from_slice(&compressed);
+        glf.images[0].data_size += 1;
+
+        for idx in 0..2 {
+            assert!(glf.extract_image(idx).is_err());
+            assert!(glf.extract_image_into(idx, &mut out).is_err());
+        }
+
+        glf.images[0].data_size -= 1;
+        glf.images[0].compression_type = CompressionType::Unknown(9);
+        assert!(glf.extract_image(0).is_err());
+        assert!(decoder.decode(&glf, 0).is_err());
+    }
+}
@@ -0,0 +1,471 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # Diff
+//! Compares two GLFs record by record, to show that trimming, re-encoding or
+//! converting a log changed nothing that matters. Records are lined up by
+//! type, device and timestamp rather than by position, so a trimmed file
+//! only shows the records that were cut.
+//!
+//! Matched records have their metadata compared field by field, through
+//! their serialised form, and image records have their decoded frames
+//! compared pixel by pixel. While the pixels are compared, the fields that
+//! only describe how they are encoded are left out, so a re-encoded file
+//! with the same frames shows no change.
+
+use crate::{HeaderType, GLF};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Image record fields that depend on how the frame is encoded rather than
+/// what it holds. They are left out of the comparison whenever the decoded
+/// frames are compared instead.
+pub const ENCODING_FIELDS: &[&str] = &["data_size", "compression_type", "record_size", "header.payload_length"];
+
+/// Options for a comparison.
+#[derive(Clone, PartialEq, Debug)]
+pub struct DiffOptions {
+    /// How far apart two timestamps may be and still be the same record.
+    pub time_tolerance: Duration,
+    /// Decode and compare the image frames.
+    pub compare_pixels: bool,
+    /// Pixels differing by this much or less count as the same.
+    pub pixel_tolerance: u8,
+    /// Fields to leave out of the metadata comparison, as dotted paths such as
+    /// `header.node_id`. ENCODING_FIELDS are left out as well when comparing pixels.
+    pub ignore_fields: Vec<String>,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        DiffOptions {
+            time_tolerance: Duration::milliseconds(1),
+            compare_pixels: true,
+            pixel_tolerance: 0,
+            // Where the pixels sit in the dat changes whenever anything before them does,
+            // and table ids depend on which tables appear first. The tables themselves are compared.
+            ignore_fields: vec!["data_ptr".to_string(), "bearing_table_id".to_string()],
+        }
+    }
+}
+
+/// How a record differs between the two files.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    /// Only in the right hand file.
+    Added,
+    /// Only in the left hand file.
+    Missing,
+    /// In both, but with different metadata or pixels.
+    Changed,
+}
+
+/// A metadata field with different values.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct FieldDiff {
+    /// The dotted path of the field.
+    pub field: String,
+    /// The value in the left hand file.
+    pub left: Value,
+    /// The value in the right hand file.
+    pub right: Value,
+}
+
+/// How two decoded frames differ.
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct PixelDiff {
+    /// The frames are of different sizes, so the pixels weren't compared.
+    pub size_mismatch: bool,
+    /// The number of pixels compared.
+    pub pixels: usize,
+    /// The number of pixels differing by more than the tolerance.
+    pub differing: usize,
+    /// The largest absolute difference.
+    pub max_abs: u8,
+    /// The mean absolute difference over all pixels.
+    pub mean_abs: f64,
+    /// The root mean square difference over all pixels.
+    pub rmse: f64,
+}
+
+impl PixelDiff {
+    /// True if the frames match within the tolerance.
+    pub fn is_same(&self) -> bool {
+        !self.size_mismatch && self.differing == 0
+    }
+}
+
+/// A record that is added, missing or changed.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct RecordDiff {
+    /// What changed.
+    pub change: Change,
+    /// The type of the record.
+    pub record_type: HeaderType,
+    /// The device ID of the record.
+    pub device_id: u16,
+    /// The timestamp of the record.
+    pub time: DateTime<Utc>,
+    /// The index of the record in its list in the left hand GLF.
+    pub left: Option<usize>,
+    /// The index of the record in its list in the right hand GLF.
+    pub right: Option<usize>,
+    /// The metadata fields that differ.
+    pub fields: Vec<FieldDiff>,
+    /// How the frames differ, for image records whose pixels were compared.
+    pub pixels: Option<PixelDiff>,
+}
+
+/// Counts for one type of record.
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct TypeSummary {
+    /// Records found in both files.
+    pub matched: usize,
+    /// Matched records that are the same.
+    pub identical: usize,
+    /// Matched records that differ.
+    pub changed: usize,
+    /// Records only in the right hand file.
+    pub added: usize,
+    /// Records only in the left hand file.
+    pub missing: usize,
+}
+
+/// The result of comparing two GLFs.
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct GlfDiff {
+    /// The counts, keyed by record type name.
+    pub summary: BTreeMap<String, TypeSummary>,
+    /// Every record that isn't identical, in time order.
+    pub records: Vec<RecordDiff>,
+    /// The number of frame pairs compared pixel by pixel.
+    pub frames_compared: usize,
+    /// The number of frame pairs whose pixels weren't compared, as either
+    /// frame couldn't be decoded. Their metadata is still compared.
+    pub frames_skipped: usize,
+    /// The largest absolute pixel difference over all frames.
+    pub max_abs: u8,
+    /// The mean absolute pixel difference over all frames.
+    pub mean_abs: f64,
+    /// The root mean square pixel difference over all frames.
+    pub rmse: f64,
+}
+
+impl GlfDiff {
+    /// True if every record matched and none differ.
+    pub fn is_identical(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+/// A record of either file, by its list and index.
+#[derive(Copy, Clone)]
+struct Key {
+    record_type: HeaderType,
+    device_id: u16,
+    time: DateTime<Utc>,
+    index: usize,
+}
+
+/// The records of a GLF, by type, then device, in time order.
+fn keys(glf: &GLF) -> BTreeMap<(String, u16), Vec<Key>> {
+    let mut keys: BTreeMap<(String, u16), Vec<Key>> = BTreeMap::new();
+
+    for entry in &glf.records {
+        let header = match entry.record_type {
+            HeaderType::Image => glf.images[entry.index].header,
+            HeaderType::GeminiStatus => glf.statuses[entry.index].header,
+            HeaderType::RawSerial => glf.serials[entry.index].header,
+            _ => continue,
+        };
+
+        keys.entry((entry.record_type.to_string(), header.device_id)).or_default().push(Key {
+            record_type: entry.record_type,
+            device_id: header.device_id,
+            time: header.time,
+            index: entry.index,
+        });
+    }
+
+    for list in keys.values_mut() {
+        list.sort_by_key(|k| k.time);
+    }
+
+    keys
+}
+
+/// Serialise a record for field comparison.
+fn record_value(glf: &GLF, key: &Key) -> Result<Value, &'static str> {
+    let value = match key.record_type {
+        HeaderType::Image => serde_json::to_value(&glf.images[key.index]),
+        HeaderType::GeminiStatus => serde_json::to_value(&glf.statuses[key.index]),
+        _ => serde_json::to_value(&glf.serials[key.index]),
+    };
+
+    value.map_err(|_| "Failed to serialise record.")
+}
+
+/// Collect the differing leaves of two values, with their dotted paths.
+fn diff_values(path: &str, left: &Value, right: &Value, ignore: &[String], out: &mut Vec<FieldDiff>) {
+    if ignore.iter().any(|i| i == path) {
+        return;
+    }
+
+    match (left, right) {
+        (Value::Object(l), Value::Object(r)) => {
+            let mut names: Vec<&String> = l.keys().chain(r.keys()).collect();
+            names.sort();
+            names.dedup();
+
+            for name in names {
+                let child = if path.is_empty() { name.clone() } else { format!("{}.{}", path, name) };
+                let lv = l.get(name).unwrap_or(&Value::Null);
+                let rv = r.get(name).unwrap_or(&Value::Null);
+                diff_values(&child, lv, rv, ignore, out);
+            }
+        },
+        _ if left != right => out.push(FieldDiff { field: path.to_string(), left: left.clone(), right: right.clone() }),
+        _ => {},
+    }
+}
+
+/// Compare two frames pixel by pixel.
+///
+/// * `left` - the left hand frame.
+/// * `right` - the right hand frame.
+/// * `tolerance` - the largest difference that counts as the same.
+pub fn diff_pixels(left: &image::GrayImage, right: &image::GrayImage, tolerance: u8) -> PixelDiff {
+    if left.dimensions() != right.dimensions() {
+        return PixelDiff { size_mismatch: true, ..Default::default() };
+    }
+
+    let mut diff = PixelDiff { pixels: left.as_raw().len(), ..Default::default() };
+    let mut sum: f64 = 0.0;
+    let mut sum_sq: f64 = 0.0;
+
+    for (a, b) in left.as_raw().iter().zip(right.as_raw()) {
+        let d = a.abs_diff(*b);
+        diff.max_abs = diff.max_abs.max(d);
+        sum += d as f64;
+        sum_sq += (d as f64) * (d as f64);
+
+        if d > tolerance {
+            diff.differing += 1;
+        }
+    }
+
+    if diff.pixels > 0 {
+        diff.mean_abs = sum / diff.pixels as f64;
+        diff.rmse = (sum_sq / diff.pixels as f64).sqrt();
+    }
+
+    diff
+}
+
+/// Compare two GLFs record by record.
+///
+/// * `left` - the original file.
+/// * `right` - the file to compare with it.
+/// * `options` - tolerances and what to compare.
+pub fn diff_glf(left: &GLF, right: &GLF, options: &DiffOptions) -> Result<GlfDiff, &'static str> {
+    let mut result = GlfDiff::default();
+    let left_keys = keys(left);
+    let mut right_keys = keys(right);
+    let mut pixel_total: usize = 0;
+    let mut pixel_sum: f64 = 0.0;
+    let mut pixel_sum_sq: f64 = 0.0;
+    let mut image_ignore = options.ignore_fields.clone();
+
+    if options.compare_pixels {
+        image_ignore.extend(ENCODING_FIELDS.iter().map(|f| f.to_string()));
+    }
+
+    let mut groups: Vec<(String, u16)> = left_keys.keys().chain(right_keys.keys()).cloned().collect();
+    groups.sort();
+    groups.dedup();
+
+    for group in groups {
+        let summary = result.summary.entry(group.0.clone()).or_default();
+        let lk = left_keys.get(&group).cloned().unwrap_or_default();
+        let rk = right_keys.remove(&group).unwrap_or_default();
+        let (mut i, mut j) = (0, 0);
+
+        // Merge the two time ordered lists, pairing records within the tolerance.
+        while i < lk.len() || j < rk.len() {
+            let unmatched = |key: &Key, change: Change| RecordDiff {
+                change,
+                record_type: key.record_type,
+                device_id: key.device_id,
+                time: key.time,
+                left: if change == Change::Missing { Some(key.index) } else { None },
+                right: if change == Change::Added { Some(key.index) } else { None },
+                fields: vec![],
+                pixels: None,
+            };
+
+            let (l, r) = (lk.get(i), rk.get(j));
+
+            match (l, r) {
+                (Some(l), Some(r)) if (l.time - r.time).abs() <= options.time_tolerance => {
+                    let mut fields: Vec<FieldDiff> = vec![];
+                    let ignore = if l.record_type == HeaderType::Image { &image_ignore } else { &options.ignore_fields };
+                    diff_values("", &record_value(left, l)?, &record_value(right, r)?, ignore, &mut fields);
+                    let mut pixels: Option<PixelDiff> = None;
+
+                    if options.compare_pixels && l.record_type == HeaderType::Image {
+                        match (left.extract_image(l.index), right.extract_image(r.index)) {
+                            (Ok(l_img), Ok(r_img)) => {
+                                let p = diff_pixels(&l_img, &r_img, options.pixel_tolerance);
+                                result.frames_compared += 1;
+                                result.max_abs = result.max_abs.max(p.max_abs);
+                                pixel_total += p.pixels;
+                                pixel_sum += p.mean_abs * p.pixels as f64;
+                                pixel_sum_sq += p.rmse * p.rmse * p.pixels as f64;
+                                pixels = Some(p);
+                            },
+                            _ => result.frames_skipped += 1,
+                        }
+                    }
+
+                    summary.matched += 1;
+
+                    if fields.is_empty() && pixels.is_none_or(|p| p.is_same()) {
+                        summary.identical += 1;
+                    } else {
+                        summary.changed += 1;
+                        result.records.push(RecordDiff {
+                            change: Change::Changed,
+                            record_type: l.record_type,
+                            device_id: l.device_id,
+                            time: l.time,
+                            left: Some(l.index),
+                            right: Some(r.index),
+                            fields,
+                            pixels,
+                        });
+                    }
+
+                    i += 1;
+                    j += 1;
+                },
+                (Some(l), Some(r)) if l.time < r.time => {
+                    summary.missing += 1;
+                    result.records.push(unmatched(l, Change::Missing));
+                    i += 1;
+                },
+                (Some(l), None) => {
+                    summary.missing += 1;
+                    result.records.push(unmatched(l, Change::Missing));
+                    i += 1;
+                },
+                (_, Some(r)) => {
+                    summary.added += 1;
+                    result.records.push(unmatched(r, Change::Added));
+                    j += 1;
+                },
+                (None, None) => break,
+            }
+        }
+    }
+
+    if pixel_total > 0 {
+        result.mean_abs = pixel_sum / pixel_total as f64;
+        result.rmse = (pixel_sum_sq / pixel_total as f64).sqrt();
+    }
+
+    result.records.sort_by_key(|r| (r.time, r.device_id));
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{build_dat, glf_from_dat, TestImage};
+
+    #[test]
+    fn test_diff_glf() {
+        let left = glf_from_dat(build_dat(&[TestImage::new(1, 1.0), TestImage::new(2, 1.5), TestImage::new(1, 2.0)]));
+        let diff = diff_glf(&left, &left, &DiffOptions::default()).unwrap();
+        assert!(diff.is_identical());
+        assert_eq!(diff.summary["image"].identical, 3);
+        assert_eq!(diff.frames_compared, 3);
+
+        // Trim the first frame, change the gain of the last, and add one at the end.
+        let mut changed = TestImage::new(1, 2.0);
+        changed.percent_gain = 80;
+        let right = glf_from_dat(build_dat(&[TestImage::new(2, 1.5), changed, TestImage::new(1, 3.0)]));
+        let diff = diff_glf(&left, &right, &DiffOptions::default()).unwrap();
+        let changes: Vec<Change> = diff.records.iter().map(|r| r.change).collect();
+        assert_eq!(changes, vec![Change::Missing, Change::Changed, Change::Added]);
+        assert_eq!(diff.records[1].fields.iter().map(|f| f.field.as_str()).collect::<Vec<_>>(), vec!["percent_gain"]);
+        assert!(diff.records[1].pixels.unwrap().is_same());
+        assert_eq!((diff.summary["image"].matched, diff.summary["image"].added, diff.summary["image"].missing), (2, 1, 1));
+
+        // Re-encoding the frames changes nothing while the pixels are compared.
+        let zlib = |device_id: u16, time: f64| TestImage { zlib: true, ..TestImage::new(device_id, time) };
+        let encoded = glf_from_dat(build_dat(&[zlib(1, 1.0), zlib(2, 1.5), zlib(1, 2.0)]));
+        assert!(diff_glf(&left, &encoded, &DiffOptions::default()).unwrap().is_identical());
+        let options = DiffOptions { compare_pixels: false, ..DiffOptions::default() };
+        let diff = diff_glf(&left, &encoded, &options).unwrap();
+        assert!(diff.records[0].fields.iter().any(|f| f.field == "compression_type"));
+    }
+
+    #[test]
+    fn test_diff_pixels() {
+        let left = glf_from_dat(build_dat(&[TestImage::new(1, 1.0), TestImage::new(2, 1.5), TestImage::new(1, 2.0)]));
+        let mut right = left.clone();
+
+        // Four pixels of the first frame up by 3, and one by 10.
+        let start = right.images[0].data_ptr as usize;
+        let dat = right.dat.to_mut();
+
+        for (k, step) in [3, 3, 3, 3, 10].iter().enumerate() {
+            dat[start + k * 5] += step;
+        }
+
+        let diff = diff_glf(&left, &right, &DiffOptions::default()).unwrap();
+        assert_eq!(diff.records.len(), 1);
+        assert_eq!((diff.records[0].change, diff.records[0].left), (Change::Changed, Some(0)));
+        assert!(diff.records[0].fields.is_empty());
+
+        let pixels = diff.records[0].pixels.unwrap();
+        assert_eq!((pixels.pixels, pixels.differing, pixels.max_abs), (32, 5, 10));
+        assert_eq!(pixels.mean_abs, 22.0 / 32.0);
+        assert_eq!(pixels.rmse, (136.0f64 / 32.0).sqrt());
+
+        // The summary is over every frame compared.
+        assert_eq!((diff.frames_compared, diff.max_abs), (3, 10));
+        assert!((diff.mean_abs - 22.0 / 96.0).abs() < 1e-12);
+        assert!((diff.rmse - (136.0f64 / 96.0).sqrt()).abs() < 1e-12);
+
+        // Within the tolerance, only the pixel off by 10 differs.
+        let options = DiffOptions { pixel_tolerance: 3, ..DiffOptions::default() };
+        let diff = diff_glf(&left, &right, &options).unwrap();
+        assert_eq!(diff.records[0].pixels.unwrap().differing, 1);
+
+        let options = DiffOptions { pixel_tolerance: 10, ..DiffOptions::default() };
+        assert!(diff_glf(&left, &right, &options).unwrap().is_identical());
+    }
+
+    #[test]
+    fn test_diff_skips_frames() {
+        let h264 = TestImage { h264: true, ..TestImage::new(2, 1.5) };
+        let left = glf_from_dat(build_dat(&[TestImage::new(1, 1.0), h264.clone(), TestImage::new(1, 2.0)]));
+
+        // The H264 frame's metadata is compared, and the frames either side pixel by pixel.
+        let diff = diff_glf(&left, &left, &DiffOptions::default()).unwrap();
+        assert!(diff.is_identical());
+        assert_eq!((diff.frames_compared, diff.frames_skipped), (2, 1));
+
+        let right = glf_from_dat(build_dat(&[TestImage::new(1, 1.0), TestImage { percent_gain: 80, ..h264 }, TestImage::new(1, 2.0)]));
+        let diff = diff_glf(&left, &right, &DiffOptions::default()).unwrap();
+        assert_eq!(diff.records.len(), 1);
+        assert_eq!(diff.records[0].fields.iter().map(|f| f.field.as_str()).collect::<Vec<_>>(), vec!["percent_gain"]);
+        assert_eq!(diff.records[0].pixels, None);
+    }
+}
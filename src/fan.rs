@@ -0,0 +1,207 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # Fan
+//! Scan conversion of the raw polar images into the familiar fan shape.
+//! The polar image has one column per beam and one row per range sample,
+//! with the nearest sample in the first row. The bearing table gives the
+//! angle of each beam in radians, zero being straight ahead.
+//!
+//! A FanConverter precomputes the mapping from fan pixels back to polar
+//! pixels, so converting many frames with the same geometry is cheap.
+
+use image::{GrayImage, ImageBuffer, Luma};
+
+/// Maps polar sonar images onto a fan shaped, cartesian image.
+#[derive(Clone, PartialEq, Debug)]
+pub struct FanConverter {
+    /// The number of beams (polar width).
+    pub beams: u32,
+    /// The number of range samples (polar height).
+    pub samples: u32,
+    /// Width of the fan image in pixels.
+    pub width: u32,
+    /// Height of the fan image in pixels.
+    pub height: u32,
+    /// Fan pixels per range sample.
+    scale: f64,
+    /// For each fan pixel, the index into the polar image, or None if outside the fan.
+    lookup: Vec<Option<u32>>,
+}
+
+/// Find the beam whose bearing is closest to `theta`.
+///
+/// * `bearing_table` - the bearings in radians, ascending or descending.
+/// * `theta` - the bearing to look up.
+pub(crate) fn nearest_beam(bearing_table: &[f64], theta: f64) -> usize {
+    let ascending = bearing_table.first() <= bearing_table.last();
+    let pos = bearing_table.partition_point(|&b| if ascending { b < theta } else { b > theta });
+
+    if pos == 0 {
+        0
+    } else if pos >= bearing_table.len() {
+        bearing_table.len() - 1
+    } else if (bearing_table[pos] - theta).abs() < (bearing_table[pos - 1] - theta).abs() {
+        pos
+    } else {
+        pos - 1
+    }
+}
+
+impl FanConverter {
+    /// Create a converter for polar images with the given bearing table and
+    /// number of range samples. The fan image is `width` pixels wide, with the
+    /// height chosen to keep the pixels square.
+    ///
+    /// * `bearing_table` - the bearing of each beam in radians.
+    /// * `samples` - the number of range samples (the polar image height).
+    /// * `width` - the width of the fan image.
+    pub fn new(bearing_table: &[f64], samples: u32, width: u32) -> Result<FanConverter, &'static str> {
+        if bearing_table.is_empty() || samples == 0 || width == 0 {
+            return Err("Cannot scan convert an empty image.");
+        }
+
+        let min_bearing = bearing_table.iter().cloned().fold(f64::MAX, f64::min);
+        let max_bearing = bearing_table.iter().cloned().fold(f64::MIN, f64::max);
+        let half_angle = min_bearing.abs().max(max_bearing.abs()).min(std::f64::consts::FRAC_PI_2);
+        let max_range = samples as f64;
+        // Width of the fan in range samples, and so the number of pixels per sample.
+        let fan_width = (2.0 * max_range * half_angle.sin()).max(1.0);
+        let scale = width as f64 / fan_width;
+        let height = (max_range * scale).ceil().max(1.0) as u32;
+        // The lookup holds polar indices as u32, so the polar image must fit.
+        u32::try_from(bearing_table.len()).ok().and_then(|beams| beams.checked_mul(samples)).ok_or("Polar image is too large.")?;
+        let pixels = (width as usize).checked_mul(height as usize).ok_or("Fan image is too large.")?;
+        let mut lookup: Vec<Option<u32>> = vec![];
+        lookup.try_reserve_exact(pixels).map_err(|_| "Fan image is too large.")?;
+
+        for y in 0..height {
+            for x in 0..width {
+                let dx = (x as f64 + 0.5 - width as f64 / 2.0) / scale;
+                let dy = (height as f64 - y as f64 - 0.5) / scale;
+                let range = dx.hypot(dy);
+                let theta = dx.atan2(dy);
+
+                if range >= max_range || theta < min_bearing || theta > max_bearing {
+                    lookup.push(None);
+                } else {
+                    let beam = nearest_beam(bearing_table, theta) as u32;
+                    let sample = range as u32;
+                    lookup.push(Some(sample * bearing_table.len() as u32 + beam));
+                }
+            }
+        }
+
+        Ok(FanConverter {
+            beams: bearing_table.len() as u32,
+            samples,
+            width,
+            height,
+            scale,
+            lookup,
+        })
+    }
+
+    /// Return the fan image position of a point given in range samples and
+    /// bearing in radians. The point need not lie inside the image.
+    ///
+    /// * `range` - the range in samples.
+    /// * `bearing` - the bearing in radians.
+    pub fn fan_point(&self, range: f64, bearing: f64) -> (f64, f64) {
+        let x = self.width as f64 / 2.0 + range * bearing.sin() * self.scale;
+        let y = self.height as f64 - range * bearing.cos() * self.scale;
+        (x, y)
+    }
+
+    /// Convert a polar image into a fan image.
+    ///
+    /// * `polar` - the raw image, `beams` wide and `samples` high.
+    pub fn convert(&self, polar: &ImageBuffer<Luma<u8>, Vec<u8>>) -> Result<GrayImage, &'static str> {
+        let mut fan: Vec<u8> = vec![0; self.lookup.len()];
+        self.convert_into(polar.as_raw(), &mut fan)?;
+        GrayImage::from_vec(self.width, self.height, fan).ok_or("Fan buffer is the wrong size.")
+    }
+
+    /// Convert raw polar pixels into a caller provided fan buffer.
+    ///
+    /// * `polar` - the raw pixels, `beams` x `samples`, row by row.
+    /// * `fan` - the output, `width` x `height`, row by row.
+    pub fn convert_into(&self, polar: &[u8], fan: &mut [u8]) -> Result<(), &'static str> {
+        if polar.len() != self.beams as usize * self.samples as usize {
+            return Err("Polar image does not match the converter geometry.");
+        }
+
+        if fan.len() != self.lookup.len() {
+            return Err("Fan buffer is the wrong size.");
+        }
+
+        for (out, src) in fan.iter_mut().zip(self.lookup.iter()) {
+            *out = match src {
+                Some(idx) => polar[*idx as usize],
+                None => 0,
+            };
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nearest_beam() {
+        let ascending = [-0.5, -0.25, 0.0, 0.25, 0.5];
+        assert_eq!(nearest_beam(&ascending, 0.0), 2);
+        assert_eq!(nearest_beam(&ascending, 0.1), 2);
+        assert_eq!(nearest_beam(&ascending, 0.2), 3);
+        assert_eq!(nearest_beam(&ascending, -1.0), 0);
+        assert_eq!(nearest_beam(&ascending, 1.0), 4);
+
+        let descending = [0.5, 0.25, 0.0, -0.25, -0.5];
+        assert_eq!(nearest_beam(&descending, 0.2), 1);
+        assert_eq!(nearest_beam(&descending, -0.45), 4);
+        assert_eq!(nearest_beam(&descending, 1.0), 0);
+    }
+
+    #[test]
+    fn test_fan_lookup() {
+        let bearings = [-0.5, -0.25, 0.0, 0.25, 0.5];
+        let fan = FanConverter::new(&bearings, 20, 101).unwrap();
+        assert_eq!(fan.lookup.len(), (fan.width * fan.height) as usize);
+        assert!(FanConverter::new(&[], 20, 101).is_err());
+        assert!(FanConverter::new(&bearings, u32::MAX, 101).is_err());
+        assert!(FanConverter::new(&bearings, 20, u32::MAX).is_err());
+
+        // Every pixel in the centre column looks at the centre beam, with the
+        // nearest sample at the bottom. The top row may lie past the last sample.
+        let centre = fan.width / 2;
+        let column: Vec<u32> = (0..fan.height).filter_map(|y| fan.lookup[(y * fan.width + centre) as usize]).collect();
+        assert!(column.len() + 1 >= fan.height as usize);
+        assert!(column.iter().all(|idx| idx % fan.beams == 2));
+        assert_eq!(column.last().unwrap() / fan.beams, 0);
+        assert_eq!(column[0] / fan.beams, fan.samples - 1);
+
+        // Beam b, sample s has value s * beams + b + 1, so nothing inside the fan is 0.
+        let polar: Vec<u8> = (0..fan.beams * fan.samples).map(|i| i as u8 + 1).collect();
+        let polar = GrayImage::from_vec(fan.beams, fan.samples, polar).unwrap();
+        let out = fan.convert(&polar).unwrap();
+        assert_eq!(out.dimensions(), (fan.width, fan.height));
+        assert_eq!(out.get_pixel(centre, fan.height - 1)[0], 3);
+
+        // The top corners are outside the fan, and stay 0.
+        assert_eq!(out.get_pixel(0, 0)[0], 0);
+        assert_eq!(out.get_pixel(fan.width - 1, 0)[0], 0);
+
+        for (idx, (src, px)) in fan.lookup.iter().zip(out.as_raw()).enumerate() {
+            assert_eq!(src.is_none(), *px == 0, "pixel {}", idx);
+        }
+
+        let (x, y) = fan.fan_point(0.0, 0.0);
+        assert_eq!((x, y), (fan.width as f64 / 2.0, fan.height as f64));
+        assert!(fan.convert_into(&[0; 3], &mut [0; 3]).is_err());
+    }
+}
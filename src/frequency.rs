@@ -0,0 +1,168 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # FrequencyMode
+//! Dual frequency sonars such as the Gemini 1200ik interleave low and high
+//! frequency pings in the same stream. The two modes differ in their bearing
+//! table and range resolution, so they usually need to be processed apart.
+
+use crate::{ImageRecord, PingFlags, SonarModel};
+use std::fmt;
+
+/// Modulation frequencies at or above this (in Hz) are treated as high frequency,
+/// when neither the ping flags nor the sonar model settle the mode.
+/// It sits between the 720kHz and 1.2MHz modes of the Gemini range.
+pub const HIGH_FREQUENCY_THRESHOLD: u32 = 960_000;
+
+/// The frequency mode a frame was captured in.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FrequencyMode {
+    /// Low frequency, long range (720kHz on the 1200ik).
+    Low,
+    /// High frequency, high resolution (1.2MHz on the 1200ik).
+    High,
+    /// No modulation frequency was recorded.
+    Unknown,
+}
+
+impl FrequencyMode {
+    /// Decode the mode from a modulation frequency in Hz.
+    ///
+    /// * `modulation_frequency` - the frequency from the ImageRecord.
+    pub fn from_frequency(modulation_frequency: u32) -> FrequencyMode {
+        if modulation_frequency == 0 {
+            FrequencyMode::Unknown
+        } else if modulation_frequency >= HIGH_FREQUENCY_THRESHOLD {
+            FrequencyMode::High
+        } else {
+            FrequencyMode::Low
+        }
+    }
+
+    /// Decode the mode of a frame. The high frequency ping flag wins, then
+    /// the mode of the model whose nominal frequency is nearest the
+    /// modulation frequency, and the threshold is the last resort.
+    ///
+    /// * `ping_flags` - the ping flags from the ImageRecord.
+    /// * `model` - the model of the sonar, if it is known.
+    /// * `modulation_frequency` - the frequency from the ImageRecord.
+    pub fn decode(ping_flags: PingFlags, model: Option<&SonarModel>, modulation_frequency: u32) -> FrequencyMode {
+        if ping_flags.contains(PingFlags::HIGH_FREQUENCY) {
+            return FrequencyMode::High;
+        }
+
+        match model {
+            Some(model) if model.modes.len() == 1 => model.modes[0].mode,
+            Some(model) if !model.modes.is_empty() && modulation_frequency != 0 => model
+                .modes
+                .iter()
+                .min_by_key(|s| s.frequency.abs_diff(modulation_frequency))
+                .map_or(FrequencyMode::Unknown, |s| s.mode),
+            _ => FrequencyMode::from_frequency(modulation_frequency),
+        }
+    }
+}
+
+impl fmt::Display for FrequencyMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrequencyMode::Low => write!(f, "low"),
+            FrequencyMode::High => write!(f, "high"),
+            FrequencyMode::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// An iterator over the image records captured in one frequency mode.
+/// Yields the index into GLF::images along with the record.
+pub struct ModeFrames<'a> {
+    images: std::iter::Enumerate<std::slice::Iter<'a, ImageRecord>>,
+    mode: FrequencyMode,
+}
+
+impl<'a> ModeFrames<'a> {
+    /// Create a new iterator over the records matching `mode`.
+    ///
+    /// * `images` - the image records, in file order.
+    /// * `mode` - the frequency mode to keep.
+    pub fn new(images: &'a [ImageRecord], mode: FrequencyMode) -> ModeFrames<'a> {
+        ModeFrames {
+            images: images.iter().enumerate(),
+            mode,
+        }
+    }
+}
+
+impl<'a> Iterator for ModeFrames<'a> {
+    type Item = (usize, &'a ImageRecord);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mode = self.mode;
+        self.images.find(|(_, img_rec)| img_rec.frequency_mode() == mode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{build_dat, glf_from_dat, TestImage};
+    use crate::SonarType;
+
+    #[test]
+    fn test_from_frequency() {
+        assert_eq!(FrequencyMode::from_frequency(0), FrequencyMode::Unknown);
+        assert_eq!(FrequencyMode::from_frequency(720_000), FrequencyMode::Low);
+        assert_eq!(FrequencyMode::from_frequency(HIGH_FREQUENCY_THRESHOLD - 1), FrequencyMode::Low);
+        assert_eq!(FrequencyMode::from_frequency(HIGH_FREQUENCY_THRESHOLD), FrequencyMode::High);
+        assert_eq!(FrequencyMode::from_frequency(1_200_000), FrequencyMode::High);
+    }
+
+    #[test]
+    fn test_decode() {
+        let dual = SonarModel::lookup(SonarType::Gemini1200ik);
+        let single = SonarModel::lookup(SonarType::Gemini720ik);
+
+        // The flag beats the frequency and the model.
+        assert_eq!(FrequencyMode::decode(PingFlags::HIGH_FREQUENCY, single, 720_000), FrequencyMode::High);
+        // A single frequency model is always in its one mode.
+        assert_eq!(FrequencyMode::decode(PingFlags::empty(), single, 1_000_000), FrequencyMode::Low);
+        assert_eq!(FrequencyMode::decode(PingFlags::empty(), single, 0), FrequencyMode::Low);
+        // A dual frequency model picks the nearest nominal frequency.
+        assert_eq!(FrequencyMode::decode(PingFlags::empty(), dual, 1_000_000), FrequencyMode::High);
+        assert_eq!(FrequencyMode::decode(PingFlags::empty(), dual, 900_000), FrequencyMode::Low);
+        assert_eq!(FrequencyMode::decode(PingFlags::empty(), dual, 0), FrequencyMode::Unknown);
+        // Without a model, fall back to the threshold.
+        assert_eq!(FrequencyMode::decode(PingFlags::empty(), None, 1_000_000), FrequencyMode::High);
+    }
+
+    #[test]
+    fn test_mode_frames() {
+        let mut images: Vec<TestImage> = vec![];
+
+        for i in 0..6 {
+            let mut img = TestImage::new(1, i as f64);
+            img.sonar_type = u8::from(SonarType::Gemini1200ik);
+
+            if i % 2 == 1 {
+                img.modulation_frequency = 1_200_000;
+                img.ping_flags = PingFlags::HIGH_FREQUENCY.bits();
+            }
+
+            images.push(img);
+        }
+
+        // The last high frequency frame is only known by its flag.
+        images[5].modulation_frequency = 0;
+        let glf = glf_from_dat(build_dat(&images));
+
+        let low: Vec<usize> = ModeFrames::new(&glf.images, FrequencyMode::Low).map(|(i, _)| i).collect();
+        let high: Vec<usize> = ModeFrames::new(&glf.images, FrequencyMode::High).map(|(i, _)| i).collect();
+        assert_eq!(low, vec![0, 2, 4]);
+        assert_eq!(high, vec![1, 3, 5]);
+        assert_eq!(ModeFrames::new(&glf.images, FrequencyMode::Unknown).count(), 0);
+        assert!(ModeFrames::new(&glf.images, FrequencyMode::High).all(|(_, r)| r.frequency_mode() == FrequencyMode::High));
+    }
+}
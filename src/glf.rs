@@ -9,24 +9,37 @@
 use crate::ciheader::parse_header;
 use crate::imagerec::parse_image_record;
 use crate::statusrec::parse_status_record;
-use crate::{ImageRecord, StatusRecord};
+use crate::serialrec::parse_serial_record;
+use crate::segment::segment_images;
+use crate::network::summarise_network;
+use crate::telemetry::telemetry_series;
+use crate::npz::write_npz;
+use crate::decoder::{decode_payload, frame_len, image_payload};
+use crate::{BearingTableSet, DatBuffer, DeviceNetwork, GlfArchive, FanConverter, FrameKind, FrequencyMode, HeaderType, ImageRecord, ModeFrames, Segment, SerialRecord, StatusRecord, TelemetrySeries};
 use image::{GrayImage, ImageBuffer, Luma};
-use zune_inflate::DeflateDecoder;
+use miniz_oxide::inflate::core::DecompressorOxide;
 use std::fs::File;
 use std::path::{Path, PathBuf};
-use std::io::{Read, Seek};
 use std::vec;
 
 #[derive(Clone)]
 pub struct GLF {
     /// The path to the GLF file.
     pub filepath: PathBuf,
+    /// The name of the .dat entry in the GLF the records come from, as
+    /// archives may hold more than one.
+    pub entry_name: String,
     /// A vector of the ImageRecords in time order.
     pub images: Vec<ImageRecord>,
     /// A vector of StatusRecords in time order.
     pub statuses: Vec<StatusRecord>,
-    /// The raw data as a vector of bytes.
-    pub dat: Vec<u8>,
+    /// A vector of raw SerialRecords in time order.
+    pub serials: Vec<SerialRecord>,
+    /// Every record in the order it appears in the dat buffer.
+    pub records: Vec<RecordEntry>,
+    /// The raw bytes of the .dat, in memory or mapped from the file. A
+    /// `Vec<u8>` before 0.3; it derefs to `[u8]`, and `to_mut` gives the vector.
+    pub dat: DatBuffer,
 }
 
 /// A small struct that holds the Image but also it's frame number.
@@ -37,105 +50,118 @@ pub struct NidxImg {
     pub img: ImageBuffer<Luma<u8>, Vec<u8>>
 }
 
-/// GLF files are actually zip files (sort of), so we first perform an unzip
-/// with this function.
-/// 
-/// * `reader` - object that implements Read and Seek
-fn read_zip_dat(reader: impl Read + Seek) -> Option<Vec<u8>> {
-    match zip::ZipArchive::new(reader) {
-        Ok(mut zip) => {
-            for i in 0..zip.len() {
-                match zip.by_index(i) {
-                    Ok(mut file) => {
-                        // Should be three files inside the GLF - .cfg, .dat and .xml.
-                        if file.name().contains("dat") {
-                            let mut buffer: Vec<u8> = vec![];
-                           
-                            match  file.read_to_end(&mut buffer) {
-                                Ok(_) => return Some(buffer),
-                                Err(_) => return None,
-                            }
-                        }
-                    },
-                    Err(_) => return None,
-                }
-            }
-        }
-        Err(..) => {
-            return None
-        }
-    }
-    None
+/// The position of a record in the dat buffer, and where its parsed form lives.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RecordEntry {
+    /// The type of the record.
+    pub record_type: HeaderType,
+    /// Index into GLF::images, GLF::statuses or GLF::serials, depending on the type.
+    pub index: usize,
+    /// Offset of the CIHeader in the dat buffer.
+    pub offset: usize,
+    /// Length of the header and record in bytes.
+    pub length: usize,
+}
+
+/// The records parsed from a dat buffer.
+pub(crate) struct ParsedDat {
+    pub images: Vec<ImageRecord>,
+    pub statuses: Vec<StatusRecord>,
+    pub serials: Vec<SerialRecord>,
+    pub records: Vec<RecordEntry>,
 }
- 
+
 /// The main parse function that goes through the entire dat_buffer,
-/// and returns the records for use later.
+/// and returns the records for use later. Records of types we don't
+/// parse are skipped, and left out of the record table.
 /// 
 /// * `dat_buffer` - a vector of byte.
-fn parse_dat(dat_buffer: &Vec<u8>) -> (Vec<ImageRecord>, Vec<StatusRecord>) {
+pub(crate) fn parse_dat(dat_buffer: &[u8]) -> Result<ParsedDat, &'static str> {
     let mut file_offset: i64 = 0;
     let mut image_records: Vec<ImageRecord> = vec![];
     let mut status_records: Vec<StatusRecord> = vec![];
+    let mut serial_records: Vec<SerialRecord> = vec![];
+    let mut records: Vec<RecordEntry> = vec![];
+    let mut bearing_tables = BearingTableSet::new();
 
     while file_offset < dat_buffer.len() as i64 - 2 {
-        let header = parse_header(dat_buffer, &mut file_offset);
+        let offset = file_offset as usize;
+        let header = parse_header(dat_buffer, &mut file_offset)?;
 
-        if header.header_type == 0 {
-            // image record
-            let image_rec = parse_image_record(&header, dat_buffer, &mut file_offset);
-            image_records.push(image_rec);
-            
-        } else if header.header_type == 1 {
-            // V4 Protocol
-            assert!(false);
-        } else if header.header_type == 2 {
-            // analog video
-            assert!(false);
-        } else if header.header_type == 3 {
-            // Gemini Status
-            let status_rec = parse_status_record(&header, dat_buffer, &mut file_offset);
-            status_records.push(status_rec);
-        } else if header.header_type == 98 {
-            // Raw Serial
-            assert!(false);
-        } else if header.header_type == 99 {
-            // Generic
-            assert!(false);
-        } else {
-            // Incorrect
-            assert!(false);
-        }
+        let index = match header.header_type {
+            HeaderType::Image => {
+                let image_rec = parse_image_record(&header, dat_buffer, &mut file_offset, &mut bearing_tables)?;
+                image_records.push(image_rec);
+                image_records.len() - 1
+            },
+            HeaderType::GeminiStatus => {
+                let status_rec = parse_status_record(&header, dat_buffer, &mut file_offset)?;
+                status_records.push(status_rec);
+                status_records.len() - 1
+            },
+            HeaderType::RawSerial => {
+                let serial_rec = parse_serial_record(&header, dat_buffer, &mut file_offset)?;
+                serial_records.push(serial_rec);
+                serial_records.len() - 1
+            },
+            // V4 Protocol, analog video, generic and unknown records are not
+            // yet supported, so are skipped over by their payload length.
+            HeaderType::V4Protocol | HeaderType::AnalogVideo | HeaderType::Generic | HeaderType::Unknown(_) => {
+                file_offset += header.payload_length as i64;
+
+                if file_offset > dat_buffer.len() as i64 {
+                    return Err("Record runs past the end of the dat buffer.");
+                }
+
+                continue;
+            },
+        };
+
+        records.push(RecordEntry {
+            record_type: header.header_type,
+            index,
+            offset,
+            length: file_offset as usize - offset,
+        });
     }
 
-    (image_records, status_records)
+    Ok(ParsedDat {
+        images: image_records,
+        statuses: status_records,
+        serials: serial_records,
+        records,
+    })
 }
 
 impl GLF {
-    /// Create a new GLF object from the glf file on disk.
+    /// Create a new GLF object from the glf file on disk. If the file holds
+    /// more than one .dat, the others can be read with GlfArchive.
     /// 
     /// * `path` - the Path to the GLF file
     pub fn new(path: &Path) -> Result<GLF, &'static str>{
-        match File::open(path) {
-            Ok(f) => {
-                match read_zip_dat(f) {
-                    Some(dat_buffer) => {
-                        // Now create the GLF - just parse images more or less and return.
-                        let (images, statuses) = parse_dat(&dat_buffer);
-
-                        let glf = GLF {
-                            filepath: path.to_path_buf(),
-                            images: images,
-                            statuses: statuses,
-                            dat: dat_buffer,
-                        };
-                        // We now have a data buffer for the .dat file inside the glf zip.
-                        return Ok(glf);
-                    },
-                    None => { return Err("Error parsing GLF."); }
-                }
-            },
-            Err(_) => return Err("Failed to open GLF File")
-        }
+        // GLF files are zip files. Should be three files inside - .cfg, .dat
+        // and .xml - and the records come from the first .dat.
+        let mut archive = GlfArchive::open(path)?;
+        let name = archive.dat_entries().next().ok_or("Error parsing GLF.")?.name.clone();
+        archive.glf(&name)
+    }
+
+    /// Create a new GLF, mapping the .dat from the file rather than reading
+    /// it into memory when it is stored uncompressed.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be changed or truncated while the GLF, or any clone
+    /// of its dat, is alive. See `GlfArchive::glf_mapped`.
+    ///
+    /// * `path` - the Path to the GLF file
+    #[cfg(feature = "mmap")]
+    pub unsafe fn new_mapped(path: &Path) -> Result<GLF, &'static str> {
+        let mut archive = GlfArchive::open(path)?;
+        let name = archive.dat_entries().next().ok_or("Error parsing GLF.")?.name.clone();
+        // Safety: passed on to the caller.
+        unsafe { archive.glf_mapped(&name) }
     }
 
     pub fn len(&self) -> usize {
@@ -150,29 +176,12 @@ impl GLF {
         // Extract the image itself, given the idx of the record.
         // Return it as a image buffer.
         // We need to read the area of the dat file and potentially unzip it.
-        let img_rec = &self.images[idx];
-        let ptr = img_rec.data_ptr;
-        let dat_size = img_rec.data_size;
-        if ptr + dat_size < self.dat.len() as u32 {
-            let raw_img_data = self.dat.get(ptr as usize..((ptr + dat_size) as usize)).unwrap();
-            let width = img_rec.image_width;
-            let height = img_rec.image_height;
-
-            if img_rec.compression_type == 0 {
-                let mut decoder = DeflateDecoder::new(&raw_img_data);
-                let decompressed_data = decoder.decode_zlib().unwrap();
-                let img: ImageBuffer<Luma<u8>, Vec<u8>> = GrayImage::from_vec(width, height, decompressed_data).unwrap();
-                return Ok(img);
-        
-            } else if img_rec.compression_type == 2 {
-                return Err("H264 decompression not yet implemented.");
-            }
-
-            let img: ImageBuffer<Luma<u8>, Vec<u8>> = GrayImage::from_vec(width, height, raw_img_data.to_vec()).unwrap();
-            return Ok(img);
-        } 
-            
-        return Err("ptr exceeds image data length");
+        // The offsets are checked, so a bad record can't read the wrong bytes,
+        // and the payload must be exactly the frame, as for extract_image_into.
+        let (img_rec, raw_img_data) = image_payload(self, idx)?;
+        let mut pixels = vec![0u8; frame_len(img_rec)?];
+        decode_payload(&mut DecompressorOxide::new(), img_rec, raw_img_data, &mut pixels)?;
+        GrayImage::from_vec(img_rec.image_width, img_rec.image_height, pixels).ok_or("Failed to create image.")
     }
 
     /// Extract the image itself, given the idx of the record and a sonar_id. 
@@ -221,6 +230,97 @@ impl GLF {
         }
 
     }
+
+    /// Split the images into segments, one for each run of frames from a
+    /// device that share the same range, gain, chirp, frequency, bearing
+    /// table and sonar type.
+    pub fn segments(&self) -> Vec<Segment> {
+        segment_images(&self.images)
+    }
+
+    /// Iterate over the images captured in one frequency mode, so that
+    /// interleaved low and high frequency pings can be processed apart.
+    ///
+    /// * `mode` - the frequency mode to keep.
+    pub fn frames_by_mode(&self, mode: FrequencyMode) -> ModeFrames<'_> {
+        ModeFrames::new(&self.images, mode)
+    }
+
+    /// Return the distinct sonar IPs, surface IPs, MAC addresses and networks
+    /// seen for each device in the status records.
+    pub fn network_summary(&self) -> Vec<DeviceNetwork> {
+        summarise_network(&self.statuses)
+    }
+
+    /// Read every status record again with the given layout, overriding the
+    /// one chosen by length, such as Legacy for firmware known to pad the
+    /// record. Nothing is changed if any record doesn't fit.
+    ///
+    /// * `layout` - the layout to read the status records with.
+    pub fn set_status_layout(&mut self, layout: crate::StatusLayout) -> Result<(), &'static str> {
+        self.statuses = self.statuses.iter().map(|s| s.with_layout(layout)).collect::<Result<Vec<_>, _>>()?;
+        Ok(())
+    }
+
+    /// Flatten the status records into a telemetry time series per device,
+    /// with counters converted to per-second rates.
+    pub fn telemetry(&self) -> Vec<TelemetrySeries> {
+        telemetry_series(&self.statuses)
+    }
+
+    /// Extract an image and scan convert it into a fan image.
+    ///
+    /// * `idx` - the index of the image we want.
+    /// * `width` - the width of the fan image in pixels.
+    pub fn extract_fan_image(&self, idx: usize, width: u32) -> Result<ImageBuffer<Luma<u8>, Vec<u8>>, &'static str> {
+        let img_rec = self.images.get(idx).ok_or("Image index out of range.")?;
+        let converter = FanConverter::new(&img_rec.bearing_table, img_rec.image_height, width)?;
+        converter.convert(&self.extract_image(idx)?)
+    }
+
+    /// Return the indices of the images from one device, in file order.
+    ///
+    /// * `sonar_id` - the id of the sonar.
+    pub fn device_frames(&self, sonar_id: u16) -> Vec<usize> {
+        (0..self.images.len()).filter(|i| self.images[*i].header.device_id == sonar_id).collect()
+    }
+
+    /// Export the frames of one device to a NumPy .npz file. The device's frames
+    /// must share one geometry; if the settings change, export each segment with
+    /// `write_npz` instead.
+    ///
+    /// * `path` - the .npz file to write.
+    /// * `sonar_id` - the id of the sonar to export.
+    /// * `kind` - raw polar or scan converted frames.
+    pub fn export_npz(&self, path: &Path, sonar_id: u16, kind: FrameKind) -> Result<(), &'static str> {
+        let file = File::create(path).map_err(|_| "Failed to create npz file.")?;
+        write_npz(self, file, &self.device_frames(sonar_id), kind)
+    }
+
+    /// Convert this GLF to an MCAP file for Foxglove and ROS 2 tooling.
+    /// Returns the frames left out as they couldn't be decoded.
+    ///
+    /// * `path` - the .mcap file to write.
+    /// * `options` - which image channels to write.
+    #[cfg(feature = "mcap")]
+    pub fn export_mcap(&self, path: &Path, options: &crate::McapOptions) -> Result<Vec<usize>, &'static str> {
+        let file = File::create(path).map_err(|_| "Failed to create MCAP file.")?;
+        let (mut writer, skipped) = crate::write_mcap(self, std::io::BufWriter::new(file), options)?;
+        std::io::Write::flush(&mut writer).map_err(|_| "Failed to write MCAP file.")?;
+        Ok(skipped)
+    }
+
+    /// Write frames as a machine learning dataset, with optional bounding boxes.
+    /// Returns the manifest, and the frames left out as they couldn't be decoded.
+    ///
+    /// * `dir` - the dataset directory.
+    /// * `frames` - indices into GLF::images.
+    /// * `annotations` - boxes to write with the frames, possibly empty.
+    /// * `options` - image kind, annotation format and splits.
+    #[cfg(feature = "serde")]
+    pub fn export_dataset(&self, dir: &Path, frames: &[usize], annotations: Vec<crate::BoxAnnotation>, options: crate::DatasetOptions) -> Result<(Vec<crate::ManifestEntry>, Vec<usize>), &'static str> {
+        crate::DatasetExporter::new(self, options).with_annotations(annotations).export(dir, frames)
+    }
 }
 
 impl std::fmt::Display for GLF {
@@ -243,5 +343,56 @@ mod tests {
         let img = glf.extract_image(1).unwrap();
         img.save("test.png").unwrap();
     }
+
+    #[test]
+    fn test_unsupported_records_skipped() {
+        use crate::testutil::{build_dat, glf_from_dat, push_record, TestImage};
+
+        let mut dat = build_dat(&[TestImage::new(1, 1.0)]);
+        push_record(&mut dat, u8::from(crate::HeaderType::Generic), 1, 1.5, &[1, 2, 3]);
+        push_record(&mut dat, 42, 1, 1.6, &[]);
+        dat.extend(build_dat(&[TestImage::new(1, 2.0)]));
+
+        let glf = glf_from_dat(dat.clone());
+        assert_eq!(glf.images.len(), 2);
+        assert_eq!(glf.records.len(), 2);
+        assert_eq!(glf.extract_image(1).unwrap(), glf_from_dat(build_dat(&[TestImage::new(1, 2.0)])).extract_image(0).unwrap());
+
+        // A skipped record that runs past the end is an error, not a panic.
+        let mut short = build_dat(&[TestImage::new(1, 1.0)]);
+        push_record(&mut short, u8::from(crate::HeaderType::V4Protocol), 1, 1.5, &[0; 8]);
+        short.truncate(short.len() - 4);
+        assert!(parse_dat(&short).is_err());
+    }
+
+    #[test]
+    fn test_zip64() {
+        use crate::testutil::{build_dat, glf_from_dat, write_glf_with, TestImage};
+
+        let dat = build_dat(&[TestImage::new(1, 1.0), TestImage::new(1, 2.0)]);
+        let expected = glf_from_dat(dat.clone()).extract_image(1).unwrap();
+        let path = std::env::temp_dir().join(format!("glf_zip64_{}.glf", std::process::id()));
+
+        for method in [zip::CompressionMethod::Stored, zip::CompressionMethod::Deflated] {
+            let options = zip::write::FileOptions::default().compression_method(method).large_file(true);
+            write_glf_with(&path, &dat, options);
+            let mut glf = GLF::new(&path).unwrap();
+            assert_eq!(glf.extract_image(1).unwrap(), expected);
+
+            if method == zip::CompressionMethod::Deflated {
+                let mut reader = crate::IndexedDat::with_index(&path, crate::InflateIndex::build(&path, 1024).unwrap()).unwrap();
+                assert_eq!(reader.read_image(&glf.records[1]).unwrap(), expected);
+            }
+
+            // Offsets past 4 GiB, or that overflow, are errors rather than wrapping.
+            glf.images[1].data_ptr = u32::MAX as u64 + glf.images[0].data_ptr;
+            assert!(glf.extract_image(1).is_err());
+            glf.images[1].data_ptr = u64::MAX - 1;
+            assert!(glf.extract_image(1).is_err());
+            assert!(glf.image_ref(1).is_none());
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
 }
 
@@ -0,0 +1,269 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # Health
+//! Walks the StatusRecords of a GLF and raises alerts whenever the sonar
+//! goes outside a set of configurable limits. Consecutive records from the
+//! same device with the same problem are merged into a single, time-ranged
+//! alert.
+
+use crate::{ShutdownStatus, StatusRecord};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::fmt;
+
+/// The limits the status records are checked against.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HealthThresholds {
+    /// Maximum PSU temperature in degrees C.
+    pub max_psu_temp: f64,
+    /// Maximum die temperature in degrees C.
+    pub max_die_temp: f64,
+    /// Maximum transmit temperature in degrees C.
+    pub max_tx_temp: f64,
+    /// Maximum temperature of any AFE, top or bottom, in degrees C.
+    pub max_afe_temp: f64,
+    /// Minimum link quality as a percentage.
+    pub min_link_quality: u16,
+    /// The largest rise in the received error count allowed between two records.
+    pub max_recv_error_rise: u32,
+    /// The largest rise in the dropped packet count allowed between two records.
+    pub max_dropped_packet_rise: u32,
+}
+
+impl Default for HealthThresholds {
+    fn default() -> Self {
+        HealthThresholds {
+            max_psu_temp: 65.0,
+            max_die_temp: 85.0,
+            max_tx_temp: 65.0,
+            max_afe_temp: 65.0,
+            min_link_quality: 80,
+            max_recv_error_rise: 0,
+            max_dropped_packet_rise: 0,
+        }
+    }
+}
+
+/// The kind of problem an alert describes.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AlertKind {
+    /// PSU temperature over the limit.
+    PsuTemperature,
+    /// Die temperature over the limit.
+    DieTemperature,
+    /// Transmit temperature over the limit.
+    TxTemperature,
+    /// An AFE temperature over the limit.
+    AfeTemperature,
+    /// Link quality below the limit.
+    LinkQuality,
+    /// The received error count is rising.
+    RecvErrors,
+    /// The dropped packet count is rising.
+    DroppedPackets,
+    /// The sonar reported a shutdown.
+    Shutdown(ShutdownStatus),
+}
+
+impl fmt::Display for AlertKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlertKind::PsuTemperature => write!(f, "PSU temperature"),
+            AlertKind::DieTemperature => write!(f, "die temperature"),
+            AlertKind::TxTemperature => write!(f, "TX temperature"),
+            AlertKind::AfeTemperature => write!(f, "AFE temperature"),
+            AlertKind::LinkQuality => write!(f, "link quality"),
+            AlertKind::RecvErrors => write!(f, "receive errors"),
+            AlertKind::DroppedPackets => write!(f, "dropped packets"),
+            AlertKind::Shutdown(status) => write!(f, "shutdown ({:?})", status),
+        }
+    }
+}
+
+/// A problem seen on one device over a span of time.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HealthAlert {
+    /// The device ID (the sonar id).
+    pub device_id: u16,
+    /// What went wrong.
+    pub kind: AlertKind,
+    /// Time of the first record showing the problem.
+    pub start: DateTime<Utc>,
+    /// Time of the last record showing the problem.
+    pub end: DateTime<Utc>,
+    /// The number of records showing the problem.
+    pub count: usize,
+    /// The worst value seen - the highest temperature or rise, or the lowest link quality.
+    pub worst: f64,
+}
+
+impl fmt::Display for HealthAlert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "device {}: {} from {} to {} ({} records, worst {})",
+            self.device_id, self.kind, self.start, self.end, self.count, self.worst)
+    }
+}
+
+/// Checks status records against a set of thresholds.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HealthMonitor {
+    /// The limits to check against.
+    pub thresholds: HealthThresholds,
+}
+
+impl HealthMonitor {
+    /// Create a new monitor with the given thresholds.
+    ///
+    /// * `thresholds` - the limits to check against.
+    pub fn new(thresholds: HealthThresholds) -> HealthMonitor {
+        HealthMonitor { thresholds }
+    }
+
+    /// Find the problems in a single record. `prev` is the previous record
+    /// from the same device, used for the rising counters.
+    fn violations(&self, stat_rec: &StatusRecord, prev: Option<&StatusRecord>) -> Vec<(AlertKind, f64)> {
+        let t = &self.thresholds;
+        let mut found: Vec<(AlertKind, f64)> = vec![];
+
+        if stat_rec.psu_t > t.max_psu_temp {
+            found.push((AlertKind::PsuTemperature, stat_rec.psu_t));
+        }
+
+        if stat_rec.die_t > t.max_die_temp {
+            found.push((AlertKind::DieTemperature, stat_rec.die_t));
+        }
+
+        if stat_rec.tx_t > t.max_tx_temp {
+            found.push((AlertKind::TxTemperature, stat_rec.tx_t));
+        }
+
+        let afe_t = [
+            stat_rec.afe0_top_temp, stat_rec.afe0_bot_temp,
+            stat_rec.afe1_top_temp, stat_rec.afe1_bot_temp,
+            stat_rec.afe2_top_temp, stat_rec.afe2_bot_temp,
+            stat_rec.afe3_top_temp, stat_rec.afe3_bot_temp,
+        ].into_iter().fold(f64::MIN, f64::max);
+
+        if afe_t > t.max_afe_temp {
+            found.push((AlertKind::AfeTemperature, afe_t));
+        }
+
+        if stat_rec.link_quality < t.min_link_quality {
+            found.push((AlertKind::LinkQuality, stat_rec.link_quality as f64));
+        }
+
+        if let Some(prev) = prev {
+            // Counters that go down have been reset, so only rises count.
+            let recv_rise = stat_rec.recv_error.saturating_sub(prev.recv_error);
+
+            if recv_rise > t.max_recv_error_rise {
+                found.push((AlertKind::RecvErrors, recv_rise as f64));
+            }
+
+            let dropped_rise = stat_rec.dropped_packet_count.saturating_sub(prev.dropped_packet_count);
+
+            if dropped_rise > t.max_dropped_packet_rise {
+                found.push((AlertKind::DroppedPackets, dropped_rise as f64));
+            }
+        }
+
+        if !stat_rec.shutdown_status.is_empty() {
+            found.push((AlertKind::Shutdown(stat_rec.shutdown_status), stat_rec.shutdown_status.bits() as f64));
+        }
+
+        found
+    }
+
+    /// Check the status records, returning the alerts ordered by their start
+    /// time. The records should be in time order, as they are in a GLF.
+    ///
+    /// * `statuses` - the status records to check.
+    pub fn check(&self, statuses: &[StatusRecord]) -> Vec<HealthAlert> {
+        let mut alerts: Vec<HealthAlert> = vec![];
+        let mut open: Vec<HealthAlert> = vec![];
+        let mut previous: HashMap<u16, &StatusRecord> = HashMap::new();
+
+        for stat_rec in statuses {
+            let device_id = stat_rec.device_id;
+            let time = stat_rec.header.time;
+            let found = self.violations(stat_rec, previous.get(&device_id).copied());
+            previous.insert(device_id, stat_rec);
+
+            // Close any alerts for this device that are no longer happening.
+            let (closed, still_open): (Vec<HealthAlert>, Vec<HealthAlert>) = open.into_iter()
+                .partition(|a| a.device_id == device_id && !found.iter().any(|(k, _)| *k == a.kind));
+            alerts.extend(closed);
+            open = still_open;
+
+            for (kind, value) in found {
+                match open.iter_mut().find(|a| a.device_id == device_id && a.kind == kind) {
+                    Some(alert) => {
+                        alert.end = time;
+                        alert.count += 1;
+                        alert.worst = match kind {
+                            AlertKind::LinkQuality => alert.worst.min(value),
+                            _ => alert.worst.max(value),
+                        };
+                    },
+                    None => open.push(HealthAlert {
+                        device_id,
+                        kind,
+                        start: time,
+                        end: time,
+                        count: 1,
+                        worst: value,
+                    }),
+                }
+            }
+        }
+
+        alerts.extend(open);
+        alerts.sort_by_key(|a| (a.start, a.device_id));
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glf::parse_dat;
+    use crate::testutil::{push_status, TestStatus};
+
+    #[test]
+    fn test_health_alerts() {
+        let mut dat: Vec<u8> = vec![];
+
+        for i in 0..6 {
+            let mut stat = TestStatus::new(1, i as f64);
+            stat.temperature = if (1..3).contains(&i) { 70.0 + i as f64 } else { 30.0 };
+            stat.recv_error = if i >= 4 { 5 } else { 0 };
+            push_status(&mut dat, &stat);
+        }
+
+        let mut stat = TestStatus::new(2, 6.0);
+        stat.shutdown_status = 2;
+        push_status(&mut dat, &stat);
+
+        let statuses = parse_dat(&dat).unwrap().statuses;
+        let alerts = HealthMonitor::default().check(&statuses);
+        let psu: Vec<&HealthAlert> = alerts.iter().filter(|a| a.kind == AlertKind::PsuTemperature).collect();
+        assert_eq!(psu.len(), 1);
+        assert_eq!(psu[0].count, 2);
+        assert_eq!(psu[0].worst, 72.0);
+        assert!(psu[0].start < psu[0].end);
+
+        let recv: Vec<&HealthAlert> = alerts.iter().filter(|a| a.kind == AlertKind::RecvErrors).collect();
+        assert_eq!(recv.len(), 1);
+        assert_eq!(recv[0].count, 1);
+
+        let shutdown = alerts.iter().find(|a| a.device_id == 2).unwrap();
+        assert_eq!(shutdown.kind, AlertKind::Shutdown(ShutdownStatus::OUT_OF_WATER));
+    }
+}
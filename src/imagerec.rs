@@ -8,15 +8,16 @@
 
 use chrono::{DateTime, Utc};
 use core::time::Duration;
-use std::vec;
 use byteorder::{ByteOrder, LittleEndian};
-use crate::{CIHeader, epoch_gem};
+use std::sync::Arc;
+use crate::{BearingTableSet, CIHeader, CompressionType, FrequencyMode, Platform, PingFlags, SonarModel, SonarType, StateFlags, epoch_gem};
 
 
 /// The image record holds all the information on a single frame / image
 /// from the sonar, including the starting position in the byte array
 /// for this image.
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ImageRecord {
     /// The CIHeader
     pub header: CIHeader,
@@ -35,15 +36,20 @@ pub struct ImageRecord {
     /// Ending bearing in degrees.
     pub bearing_end: u32,
     /// Compression type.
-    pub compression_type: u16,
+    pub compression_type: CompressionType,
     /// Pointer into the data buffer.
-    pub data_ptr: u32,
+    pub data_ptr: u64,
     /// The number of bytes to read.
     pub data_size: u32,
     /// The bearing table for this image.
-    pub bearing_table: Vec<f64>,
+    /// Shared with every other image in the file that has the same table.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub bearing_table: Arc<[f64]>,
+    /// The id of the bearing table, the same for every image sharing it.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub bearing_table_id: u32,
     /// Any state flags.
-    pub state_flags: u32,
+    pub state_flags: StateFlags,
     /// Modulation frequency.
     pub modulation_frequency: u32,
     /// Beam forming
@@ -51,7 +57,7 @@ pub struct ImageRecord {
     /// The transmission time in UTC
     pub db_tx_time: DateTime<Utc>,
     /// Any ping flags.
-    pub ping_flags: u16,
+    pub ping_flags: PingFlags,
     /// sos at xd.
     pub sos_at_xd: f32,
     /// Percentage gain.
@@ -59,9 +65,9 @@ pub struct ImageRecord {
     /// CHIRP mode on?
     pub chirp: u8,
     /// The type of the sonar.
-    pub sonar_type: u8,
+    pub sonar_type: SonarType,
     /// The platform id.
-    pub platform: u8,
+    pub platform: Platform,
     /// Size of the record.
     pub record_size: u32, 
     /// The width of the image in pixels.
@@ -70,120 +76,302 @@ pub struct ImageRecord {
     pub image_height: u32,
 }
 
+impl ImageRecord {
+    /// Return the frequency mode this frame was captured in, from the ping
+    /// flags, the sonar model or the modulation frequency, in that order.
+    pub fn frequency_mode(&self) -> FrequencyMode {
+        FrequencyMode::decode(self.ping_flags, self.model(), self.modulation_frequency)
+    }
+
+    /// Return the model of the sonar that took this frame, with its
+    /// nominal specifications. None if the sonar type is not known.
+    pub fn model(&self) -> Option<&'static SonarModel> {
+        SonarModel::lookup(self.sonar_type)
+    }
+}
+
+
+/// Offset of the compression type field, present from image version 3.
+const COMPRESSION_OFFSET: usize = 24;
+
+/// Bytes from the end of the bearing table to the end of the end tag.
+const TAIL_SIZE: usize = 34;
+
+/// The errors of ImageRecordRef::parse, named so the validator can tell them apart.
+pub(crate) const IMAGE_SHORT: &str = "Image record runs past the end of the dat buffer.";
+pub(crate) const IMAGE_MAGIC: &str = "Image record has the wrong rtype or version.";
+pub(crate) const IMAGE_NEGATIVE: &str = "Image record has a negative width or height.";
+pub(crate) const IMAGE_END_TAG: &str = "Image record end tag is not 0xDEDE.";
+
+/// A borrowed view of an image record in the dat buffer. Only the offsets of
+/// its variable length parts are worked out up front; the fields are decoded
+/// when asked for, and the bearing table and pixels are never copied.
+#[derive(Copy, Clone, Debug)]
+pub struct ImageRecordRef<'a> {
+    /// The CIHeader.
+    pub header: CIHeader,
+    /// The record, from just after the CIHeader to the end tag.
+    payload: &'a [u8],
+    /// Where the payload starts in the dat buffer.
+    payload_offset: usize,
+    /// Offset of the data size field in the payload.
+    data_size_offset: usize,
+    /// Offset of the state flags, just after the bearing table.
+    tail_offset: usize,
+}
+
+impl<'a> ImageRecordRef<'a> {
+    /// Check the structure of an image record and return a view of it.
+    ///
+    /// * `header` - the CI Header for this record.
+    /// * `dat_buffer` - the bytes buffer we are reading from.
+    /// * `offset` - the offset of the record, just after the CIHeader.
+    pub fn parse(header: &CIHeader, dat_buffer: &'a [u8], offset: usize) -> Result<ImageRecordRef<'a>, &'static str> {
+        let rest = dat_buffer.get(offset..).ok_or("Image record starts past the end of the dat buffer.")?;
+        let short = IMAGE_SHORT;
+
+        if rest.len() < COMPRESSION_OFFSET + 2 {
+            return Err(short);
+        }
+
+        if LittleEndian::read_u16(&rest[0..2]) != 1 || LittleEndian::read_u16(&rest[2..4]) != 0xEFEF {
+            return Err(IMAGE_MAGIC);
+        }
+
+        let mut view = ImageRecordRef {
+            header: *header,
+            payload: rest,
+            payload_offset: offset,
+            data_size_offset: COMPRESSION_OFFSET,
+            tail_offset: 0,
+        };
+
+        if view.bearing_end() < view.bearing_start() || view.range_end() < view.range_start() {
+            return Err(IMAGE_NEGATIVE);
+        }
+
+        if view.image_version() == 3 {
+            view.data_size_offset += 2;
+        }
+
+        let data_size = rest.get(view.data_size_offset..view.data_size_offset + 4).map(LittleEndian::read_u32).ok_or(short)?;
+        view.tail_offset = (view.data_size_offset + 4)
+            .checked_add(data_size as usize)
+            .and_then(|o| o.checked_add(view.image_width() as usize * 8))
+            .ok_or(short)?;
+
+        let size = view.tail_offset.checked_add(TAIL_SIZE).ok_or(short)?;
+        view.payload = rest.get(..size).ok_or(short)?;
+
+        if LittleEndian::read_u16(&view.payload[size - 2..]) != 0xDEDE {
+            return Err(IMAGE_END_TAG);
+        }
+
+        Ok(view)
+    }
+
+    fn u16_at(&self, offset: usize) -> u16 {
+        LittleEndian::read_u16(&self.payload[offset..offset + 2])
+    }
+
+    fn u32_at(&self, offset: usize) -> u32 {
+        LittleEndian::read_u32(&self.payload[offset..offset + 4])
+    }
+
+    /// Version number.
+    pub fn version(&self) -> u16 {
+        self.u16_at(2)
+    }
+
+    /// Image version number.
+    pub fn image_version(&self) -> u16 {
+        self.u16_at(4)
+    }
+
+    /// The starting range.
+    pub fn range_start(&self) -> u32 {
+        self.u32_at(6)
+    }
+
+    /// End of the range.
+    pub fn range_end(&self) -> u32 {
+        self.u32_at(10)
+    }
+
+    /// Range compression.
+    pub fn range_compression(&self) -> u16 {
+        self.u16_at(14)
+    }
+
+    /// Starting bearing.
+    pub fn bearing_start(&self) -> u32 {
+        self.u32_at(16)
+    }
+
+    /// Ending bearing.
+    pub fn bearing_end(&self) -> u32 {
+        self.u32_at(20)
+    }
+
+    /// The width of the image in pixels.
+    pub fn image_width(&self) -> u32 {
+        self.bearing_end() - self.bearing_start()
+    }
+
+    /// The height of the image in pixels.
+    pub fn image_height(&self) -> u32 {
+        self.range_end() - self.range_start()
+    }
 
-/// Extract the image itself, given the idx of the record and a sonar_id. 
+    /// The number of bytes of pixel data.
+    pub fn data_size(&self) -> u32 {
+        self.u32_at(self.data_size_offset)
+    }
+
+    /// Compression type. Before image version 3 there is no field, and a
+    /// payload smaller than the image means zlib.
+    pub fn compression_type(&self) -> CompressionType {
+        if self.image_version() == 3 {
+            CompressionType::from(self.u16_at(COMPRESSION_OFFSET))
+        } else if self.data_size() as u64 != self.image_width() as u64 * self.image_height() as u64 {
+            CompressionType::Zlib
+        } else {
+            CompressionType::Uncompressed
+        }
+    }
+
+    /// Offset of the pixel data in the dat buffer.
+    pub fn data_ptr(&self) -> usize {
+        self.payload_offset + self.data_size_offset + 4
+    }
+
+    /// The pixel data, still compressed if the record is.
+    pub fn data(&self) -> &'a [u8] {
+        let start = self.data_size_offset + 4;
+        &self.payload[start..start + self.data_size() as usize]
+    }
+
+    /// The bearing of a beam in radians.
+    ///
+    /// * `beam` - the beam, from 0 to image_width.
+    pub fn bearing(&self, beam: usize) -> Option<f64> {
+        let start = self.tail_offset - self.image_width() as usize * 8 + beam * 8;
+        (beam < self.image_width() as usize).then(|| LittleEndian::read_f64(&self.payload[start..start + 8]))
+    }
+
+    /// The bearing table, decoded as it is iterated.
+    pub fn bearings(&self) -> impl Iterator<Item = f64> + Clone + 'a {
+        let start = self.tail_offset - self.image_width() as usize * 8;
+        self.payload[start..self.tail_offset].chunks_exact(8).map(LittleEndian::read_f64)
+    }
+
+    /// Any state flags.
+    pub fn state_flags(&self) -> StateFlags {
+        StateFlags::from_bits_retain(self.u32_at(self.tail_offset))
+    }
+
+    /// Modulation frequency.
+    pub fn modulation_frequency(&self) -> u32 {
+        self.u32_at(self.tail_offset + 4)
+    }
+
+    /// Beam forming.
+    pub fn beam_form_app(&self) -> f32 {
+        LittleEndian::read_f32(&self.payload[self.tail_offset + 8..self.tail_offset + 12])
+    }
+
+    /// The transmission time in UTC.
+    pub fn db_tx_time(&self) -> DateTime<Utc> {
+        let tts = LittleEndian::read_f64(&self.payload[self.tail_offset + 12..self.tail_offset + 20]);
+        epoch_gem() + Duration::from_millis((tts * 1000.0).round() as u64)
+    }
+
+    /// Any ping flags.
+    pub fn ping_flags(&self) -> PingFlags {
+        PingFlags::from_bits_retain(self.u16_at(self.tail_offset + 20))
+    }
+
+    /// sos at xd.
+    pub fn sos_at_xd(&self) -> f32 {
+        LittleEndian::read_f32(&self.payload[self.tail_offset + 22..self.tail_offset + 26])
+    }
+
+    /// Percentage gain.
+    pub fn percent_gain(&self) -> u16 {
+        self.u16_at(self.tail_offset + 26)
+    }
+
+    /// CHIRP mode on?
+    pub fn chirp(&self) -> u8 {
+        self.payload[self.tail_offset + 28]
+    }
+
+    /// The type of the sonar.
+    pub fn sonar_type(&self) -> SonarType {
+        SonarType::from(self.payload[self.tail_offset + 29])
+    }
+
+    /// The platform id.
+    pub fn platform(&self) -> Platform {
+        Platform::from(self.payload[self.tail_offset + 30])
+    }
+
+    /// Size of the record after the CIHeader.
+    pub fn record_size(&self) -> u32 {
+        self.payload.len() as u32
+    }
+
+    /// Copy the record into an owned ImageRecord, with a bearing table of its own.
+    pub fn to_record(&self) -> ImageRecord {
+        self.to_record_with(&mut BearingTableSet::new())
+    }
+
+    /// Copy the record into an owned ImageRecord, sharing its bearing table
+    /// with earlier records interned into the same set.
+    ///
+    /// * `tables` - the bearing tables seen so far.
+    pub fn to_record_with(&self, tables: &mut BearingTableSet) -> ImageRecord {
+        let (bearing_table_id, bearing_table) = tables.intern(self.bearings());
+
+        ImageRecord {
+            header: self.header,
+            version: self.version(),
+            image_version: self.image_version(),
+            range_start: self.range_start(),
+            range_end: self.range_end(),
+            range_compression: self.range_compression(),
+            bearing_start: self.bearing_start(),
+            bearing_end: self.bearing_end(),
+            compression_type: self.compression_type(),
+            data_ptr: self.data_ptr() as u64,
+            data_size: self.data_size(),
+            bearing_table,
+            bearing_table_id,
+            state_flags: self.state_flags(),
+            modulation_frequency: self.modulation_frequency(),
+            beam_form_app: self.beam_form_app(),
+            db_tx_time: self.db_tx_time(),
+            ping_flags: self.ping_flags(),
+            sos_at_xd: self.sos_at_xd(),
+            percent_gain: self.percent_gain(),
+            chirp: self.chirp(),
+            sonar_type: self.sonar_type(),
+            platform: self.platform(),
+            record_size: self.record_size(),
+            image_width: self.image_width(),
+            image_height: self.image_height(),
+        }
+    }
+}
+
+/// Parse an image record, moving the file_offset past it.
 ///
 /// * `header` - the CI Header for this record.
 /// * `dat_buffer` - the bytes buffer we are reading from.
 /// * `file_offset` - the offset in the dat_buffer.
-pub fn parse_image_record(header: &CIHeader, dat_buffer: &Vec<u8>, file_offset: &mut i64) -> ImageRecord {
-    // Parse a record - an image one for now.
-    let mut fp: usize = *file_offset as usize;
-
-    let rtype = LittleEndian::read_u16(&dat_buffer[fp..(fp + 2)]);
-    assert!(rtype == 1);
-    let version = LittleEndian::read_u16(&dat_buffer[(fp + 2)..(fp + 4)]);
-    assert!(version == 0xEFEF);
-    fp = fp + 4; // Advance the FP.
-
-    let image_version = LittleEndian::read_u16(&dat_buffer[fp..(fp + 2)]);
-    let range_start = LittleEndian::read_u32(&dat_buffer[(fp + 2)..(fp + 6)]);
-    let range_end = LittleEndian::read_u32(&dat_buffer[(fp + 6)..(fp + 10)]);
-    let range_compression = LittleEndian::read_u16(&dat_buffer[(fp + 10)..(fp + 12)]);
-    let bearing_start = LittleEndian::read_u32(&dat_buffer[(fp + 12)..(fp + 16)]);
-    let bearing_end = LittleEndian::read_u32(&dat_buffer[(fp + 16)..(fp + 20)]);
-
-    fp = fp + 20; // Advance fp again.
-
-    let mut compression_type: u16 = 1;
-    
-    if image_version == 3 {
-        compression_type = LittleEndian::read_u16(&dat_buffer[fp..(fp + 2)]);
-        fp = fp + 2;
-    }
-
-    let dat_size = LittleEndian::read_u32(&dat_buffer[fp..(fp + 4)]);
-    let dat_ptr = fp + 4;
-    fp = fp + 4 + dat_size as usize;
-
-    let bsize = bearing_end - bearing_start;
-    let mut btable: Vec<f64> = vec![];
-
-    for i in 0..bsize {
-        let bearing = LittleEndian::read_f64(&dat_buffer[fp + (i * 8) as usize..fp + ((i + 1) * 8) as usize]);
-        btable.push(bearing);
-    }
-
-    fp = fp + (bsize * 8) as usize;
-
-    let state_flags = LittleEndian::read_u32(&dat_buffer[fp..(fp + 4)]);
-    let modulation_frequency = LittleEndian::read_u32(&dat_buffer[(fp + 4)..(fp + 8)]);
-
-    fp = fp + 8;
-
-    let beam_form = LittleEndian::read_f32(&dat_buffer[fp..(fp + 4)]);
-
-    // Get the timing
-    let tts = LittleEndian::read_f64(&dat_buffer[(fp + 4)..(fp + 12)]);
-    let tmillis = (tts as f64 * 1000.0).round() as u64;
-    let dur : Duration = Duration::from_millis(tmillis);
-    let epoch: chrono::prelude::DateTime<chrono::prelude::Utc> = epoch_gem();
-    let db_tx_time = epoch + dur;
-
-    let ping_flags = LittleEndian::read_u16(&dat_buffer[(fp + 12)..(fp + 14)]);
-    let sos_at_xd = LittleEndian::read_f32(&dat_buffer[(fp + 14)..(fp + 18)]);
-    let percent_gain = LittleEndian::read_u16(&dat_buffer[(fp + 18)..(fp + 20)]);
-    let chirp = dat_buffer[fp + 20];
-    let sonar_type = dat_buffer[fp + 21];
-    let platform = dat_buffer[fp + 22];
-
-    // Note the extra byte pad!
-    let end_tag = LittleEndian::read_u16(&dat_buffer[(fp + 24)..(fp + 26)]);
-    assert!(end_tag == 0xDEDE);
-
-    fp = fp + 26;
-    let record_size = fp - *file_offset as usize;
-    let image_width = bearing_end - bearing_start;
-    let image_height = range_end - range_start;
-
-    // Deal with potential compression.
-    if image_version != 3 {
-        let exp_size = (bearing_end - bearing_start) * (range_end - range_start);
-        
-        if exp_size != dat_size {
-            compression_type = 0;
-        }
-    }
-
-    // Setup the beginning of our ImageRecord
-    let img_rec : ImageRecord = ImageRecord {
-        header: *header,
-        version: version,
-        image_version: image_version,
-        range_start: range_start,
-        range_end: range_end,
-        range_compression: range_compression,
-        bearing_start: bearing_start,
-        bearing_end: bearing_end,
-        compression_type: compression_type,
-        data_ptr: dat_ptr as u32,
-        data_size: dat_size,
-        bearing_table: btable,
-        state_flags: state_flags,
-        modulation_frequency: modulation_frequency,
-        beam_form_app: beam_form,
-        db_tx_time: db_tx_time,
-        ping_flags: ping_flags,
-        sos_at_xd: sos_at_xd,
-        percent_gain: percent_gain,
-        chirp: chirp,
-        sonar_type: sonar_type,
-        platform: platform,
-        record_size: record_size as u32,
-        image_width: image_width,
-        image_height: image_height,
-    };
-
-    *file_offset = *file_offset + (record_size as i64);
-    img_rec
+/// * `tables` - the bearing tables seen so far in this dat_buffer.
+pub fn parse_image_record(header: &CIHeader, dat_buffer: &[u8], file_offset: &mut i64, tables: &mut BearingTableSet) -> Result<ImageRecord, &'static str> {
+    let view = ImageRecordRef::parse(header, dat_buffer, *file_offset as usize)?;
+    *file_offset += view.record_size() as i64;
+    Ok(view.to_record_with(tables))
 }
@@ -22,9 +22,67 @@ mod glf;
 mod imagerec;
 mod epochgem;
 mod statusrec;
+mod serialrec;
+mod segment;
+mod frequency;
+mod codes;
+mod models;
+mod network;
+mod health;
+mod telemetry;
+mod fan;
+mod npz;
+mod validate;
+mod decoder;
+mod views;
+mod bearing;
+mod datbuf;
+mod checkpoint;
+mod recindex;
+mod archive;
+#[cfg(feature = "rayon")]
+mod parallel;
+#[cfg(feature = "mcap")]
+mod mcap;
+#[cfg(feature = "serde")]
+mod metadata;
+#[cfg(feature = "serde")]
+mod dataset;
+#[cfg(feature = "serde")]
+mod annotation;
+#[cfg(feature = "serde")]
+mod diff;
+#[cfg(test)]
+mod testutil;
 
-pub use crate::imagerec::ImageRecord;
-pub use crate::statusrec::StatusRecord;
+pub use crate::imagerec::{ImageRecord, ImageRecordRef};
+pub use crate::statusrec::{StatusRecord, StatusRecordRef, StatusLayout, STATUS_LEGACY_SIZE, STATUS_EXTENDED_SIZE};
+pub use crate::serialrec::{SerialRecord, NmeaFix, parse_nmea};
 pub use crate::ciheader::CIHeader;
-pub use crate::glf::GLF;
+pub use crate::glf::{GLF, RecordEntry};
+pub use crate::datbuf::DatBuffer;
+pub use crate::archive::{GlfArchive, ArchiveEntry, EntryCompression};
 pub use crate::epochgem::epoch_gem;
+pub use crate::segment::{Segment, SegmentSettings};
+pub use crate::frequency::{FrequencyMode, ModeFrames};
+pub use crate::codes::{HeaderType, CompressionType, SonarType, Platform, LinkType, PingFlags, StateFlags, StatusFlags, ShutdownStatus};
+pub use crate::models::{SonarModel, ModeSpec, SONAR_MODELS};
+pub use crate::network::{MacAddr, Ipv4Network, DeviceNetwork};
+pub use crate::health::{HealthMonitor, HealthThresholds, HealthAlert, AlertKind};
+pub use crate::telemetry::{TelemetrySeries, TelemetryColumn, ColumnKind};
+pub use crate::fan::FanConverter;
+pub use crate::bearing::{BearingTableSet, BearingTableUsage};
+pub use crate::npz::{write_npz, FrameKind};
+pub use crate::decoder::FrameDecoder;
+pub use crate::recindex::{RecordIndex, IndexedRecord, ImageSummary, record_index_path, record_entry_index_path, RECORD_INDEX_VERSION};
+pub use crate::checkpoint::{InflateIndex, Checkpoint, IndexedDat, inflate_index_path, inflate_entry_index_path, DEFAULT_CHECKPOINT_SPACING};
+pub use crate::views::{RecordRef, RecordRefs, record_refs, index_records};
+pub use crate::validate::{Validator, ValidationReport, ValidationIssue, IssueKind, Severity};
+#[cfg(feature = "mcap")]
+pub use crate::mcap::{write_mcap, McapOptions};
+#[cfg(feature = "serde")]
+pub use crate::dataset::{DatasetExporter, DatasetOptions, AnnotationFormat, BoxAnnotation, BoxCoords, ManifestEntry, Split, load_box_annotations};
+#[cfg(feature = "serde")]
+pub use crate::annotation::{Annotation, AnnotationSet, ResolvedAnnotation, CoordSpace, Shape, annotation_sidecar_path, ANNOTATION_FORMAT_VERSION};
+#[cfg(feature = "serde")]
+pub use crate::diff::{diff_glf, diff_pixels, DiffOptions, ENCODING_FIELDS, GlfDiff, RecordDiff, FieldDiff, PixelDiff, TypeSummary, Change};
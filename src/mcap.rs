@@ -0,0 +1,427 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # Mcap
+//! Converts a GLF into an [MCAP](https://mcap.dev) file for Foxglove and ROS 2
+//! tooling. Messages are JSON encoded against the Foxglove schemas:
+//!
+//! * `/sonar/<device>/image` - `foxglove.RawImage` or `foxglove.CompressedImage` (PNG).
+//! * `/sonar/<device>/fan` - the scan converted image, if asked for.
+//! * `/sonar/<device>/status` - the StatusRecord as JSON.
+//! * `/serial/<device>/fix` - `foxglove.LocationFix` from NMEA sentences.
+//!
+//! Every message is timestamped from its CIHeader. The file is written
+//! unchunked, without a summary section, which all MCAP readers accept.
+//! Frames that can't be decoded, such as H264 frames, are left out and
+//! reported rather than ending the conversion.
+
+use crate::{FanConverter, HeaderType, GLF};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use image::{GrayImage, ImageOutputFormat};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{Cursor, Write};
+
+const MAGIC: &[u8] = b"\x89MCAP0\r\n";
+const OP_HEADER: u8 = 0x01;
+const OP_FOOTER: u8 = 0x02;
+const OP_SCHEMA: u8 = 0x03;
+const OP_CHANNEL: u8 = 0x04;
+const OP_MESSAGE: u8 = 0x05;
+const OP_DATA_END: u8 = 0x0F;
+
+const TIME_SCHEMA: &str = r#"{"type":"object","properties":{"sec":{"type":"integer"},"nsec":{"type":"integer"}}}"#;
+
+/// Options for the MCAP conversion.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct McapOptions {
+    /// Write the images as PNG `foxglove.CompressedImage` rather than `foxglove.RawImage`.
+    pub compressed: bool,
+    /// Also write a scan converted fan image of this width, on its own channel.
+    pub fan_width: Option<u32>,
+}
+
+impl Default for McapOptions {
+    fn default() -> Self {
+        McapOptions {
+            compressed: true,
+            fan_width: None,
+        }
+    }
+}
+
+/// A minimal MCAP writer - records go straight to the output.
+struct McapWriter<W: Write> {
+    writer: W,
+    schemas: HashMap<&'static str, u16>,
+    channels: HashMap<String, u16>,
+    sequence: u32,
+}
+
+fn push_str(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+impl<W: Write> McapWriter<W> {
+    fn new(mut writer: W) -> Result<McapWriter<W>, &'static str> {
+        writer.write_all(MAGIC).map_err(|_| "Failed to write MCAP.")?;
+        let mut mcap = McapWriter { writer, schemas: HashMap::new(), channels: HashMap::new(), sequence: 0 };
+        let mut header: Vec<u8> = vec![];
+        push_str(&mut header, "");
+        push_str(&mut header, concat!("glf ", env!("CARGO_PKG_VERSION")));
+        mcap.record(OP_HEADER, &header)?;
+        Ok(mcap)
+    }
+
+    fn record(&mut self, opcode: u8, content: &[u8]) -> Result<(), &'static str> {
+        self.writer.write_all(&[opcode]).map_err(|_| "Failed to write MCAP.")?;
+        self.writer.write_all(&(content.len() as u64).to_le_bytes()).map_err(|_| "Failed to write MCAP.")?;
+        self.writer.write_all(content).map_err(|_| "Failed to write MCAP.")
+    }
+
+    /// Return the id of a schema, writing it the first time it is used.
+    fn schema(&mut self, name: &'static str, schema: &str) -> Result<u16, &'static str> {
+        if let Some(id) = self.schemas.get(name) {
+            return Ok(*id);
+        }
+
+        // Schema ids start at one, zero means no schema.
+        let id = self.schemas.len() as u16 + 1;
+        let mut content: Vec<u8> = id.to_le_bytes().to_vec();
+        push_str(&mut content, name);
+        push_str(&mut content, "jsonschema");
+        push_str(&mut content, schema);
+        self.record(OP_SCHEMA, &content)?;
+        self.schemas.insert(name, id);
+        Ok(id)
+    }
+
+    /// Return the id of a channel, writing it the first time it is used.
+    fn channel(&mut self, topic: &str, schema_id: u16) -> Result<u16, &'static str> {
+        if let Some(id) = self.channels.get(topic) {
+            return Ok(*id);
+        }
+
+        let id = self.channels.len() as u16;
+        let mut content: Vec<u8> = id.to_le_bytes().to_vec();
+        content.extend_from_slice(&schema_id.to_le_bytes());
+        push_str(&mut content, topic);
+        push_str(&mut content, "json");
+        // An empty metadata map.
+        content.extend_from_slice(&0u32.to_le_bytes());
+        self.record(OP_CHANNEL, &content)?;
+        self.channels.insert(topic.to_string(), id);
+        Ok(id)
+    }
+
+    fn message(&mut self, channel_id: u16, time: DateTime<Utc>, message: &Value) -> Result<(), &'static str> {
+        let nanos = time.timestamp_nanos_opt().unwrap_or(0).max(0) as u64;
+        let mut content: Vec<u8> = channel_id.to_le_bytes().to_vec();
+        content.extend_from_slice(&self.sequence.to_le_bytes());
+        content.extend_from_slice(&nanos.to_le_bytes());
+        content.extend_from_slice(&nanos.to_le_bytes());
+        serde_json::to_writer(&mut content, message).map_err(|_| "Failed to encode MCAP message.")?;
+        self.sequence = self.sequence.wrapping_add(1);
+        self.record(OP_MESSAGE, &content)
+    }
+
+    fn finish(mut self) -> Result<W, &'static str> {
+        // Zero CRCs and offsets mean "not present".
+        self.record(OP_DATA_END, &0u32.to_le_bytes())?;
+        self.record(OP_FOOTER, &[0u8; 20])?;
+        self.writer.write_all(MAGIC).map_err(|_| "Failed to write MCAP.")?;
+        Ok(self.writer)
+    }
+}
+
+fn timestamp(time: DateTime<Utc>) -> Value {
+    json!({ "sec": time.timestamp(), "nsec": time.timestamp_subsec_nanos() })
+}
+
+fn image_schema(compressed: bool) -> (&'static str, String) {
+    if compressed {
+        ("foxglove.CompressedImage", format!(
+            r#"{{"title":"foxglove.CompressedImage","type":"object","properties":{{"timestamp":{},"frame_id":{{"type":"string"}},"data":{{"type":"string","contentEncoding":"base64"}},"format":{{"type":"string"}}}}}}"#,
+            TIME_SCHEMA))
+    } else {
+        ("foxglove.RawImage", format!(
+            r#"{{"title":"foxglove.RawImage","type":"object","properties":{{"timestamp":{},"frame_id":{{"type":"string"}},"width":{{"type":"integer"}},"height":{{"type":"integer"}},"encoding":{{"type":"string"}},"step":{{"type":"integer"}},"data":{{"type":"string","contentEncoding":"base64"}}}}}}"#,
+            TIME_SCHEMA))
+    }
+}
+
+fn image_message(img: &GrayImage, time: DateTime<Utc>, frame_id: &str, compressed: bool) -> Result<Value, &'static str> {
+    let b64 = base64::engine::general_purpose::STANDARD;
+
+    if compressed {
+        let mut png: Vec<u8> = vec![];
+        img.write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png).map_err(|_| "Failed to encode PNG.")?;
+        Ok(json!({
+            "timestamp": timestamp(time),
+            "frame_id": frame_id,
+            "data": b64.encode(&png),
+            "format": "png",
+        }))
+    } else {
+        Ok(json!({
+            "timestamp": timestamp(time),
+            "frame_id": frame_id,
+            "width": img.width(),
+            "height": img.height(),
+            "encoding": "mono8",
+            "step": img.width(),
+            "data": b64.encode(img.as_raw()),
+        }))
+    }
+}
+
+/// Write a GLF as an MCAP file, with messages in file order. Returns the
+/// writer, and the frames left out as they couldn't be decoded.
+///
+/// * `glf` - the GLF to convert.
+/// * `writer` - where to write the MCAP file.
+/// * `options` - which image channels to write.
+pub fn write_mcap<W: Write>(glf: &GLF, writer: W, options: &McapOptions) -> Result<(W, Vec<usize>), &'static str> {
+    let mut mcap = McapWriter::new(writer)?;
+    let mut skipped: Vec<usize> = vec![];
+    let (image_name, image_def) = image_schema(options.compressed);
+    // The fan converter of each device, with the bearing table it was built for.
+    let mut converters: HashMap<u16, (u32, FanConverter)> = HashMap::new();
+
+    for entry in &glf.records {
+        match entry.record_type {
+            HeaderType::Image => {
+                let img_rec = &glf.images[entry.index];
+                let device_id = img_rec.header.device_id;
+                let time = img_rec.header.time;
+                let frame_id = format!("sonar_{}", device_id);
+                let img = match glf.extract_image(entry.index) {
+                    Ok(img) => img,
+                    Err(_) => {
+                        skipped.push(entry.index);
+                        continue;
+                    },
+                };
+                let schema_id = mcap.schema(image_name, &image_def)?;
+                let channel_id = mcap.channel(&format!("/sonar/{}/image", device_id), schema_id)?;
+                mcap.message(channel_id, time, &image_message(&img, time, &frame_id, options.compressed)?)?;
+
+                if let Some(width) = options.fan_width {
+                    let rebuild = match converters.get(&device_id) {
+                        Some((table_id, conv)) => *table_id != img_rec.bearing_table_id || conv.samples != img_rec.image_height,
+                        None => true,
+                    };
+
+                    if rebuild {
+                        let conv = FanConverter::new(&img_rec.bearing_table, img_rec.image_height, width)?;
+                        converters.insert(device_id, (img_rec.bearing_table_id, conv));
+                    }
+
+                    let fan = converters[&device_id].1.convert(&img)?;
+                    let channel_id = mcap.channel(&format!("/sonar/{}/fan", device_id), schema_id)?;
+                    mcap.message(channel_id, time, &image_message(&fan, time, &frame_id, options.compressed)?)?;
+                }
+            },
+            HeaderType::GeminiStatus => {
+                let stat_rec = &glf.statuses[entry.index];
+                let schema_id = mcap.schema("glf.StatusRecord", r#"{"title":"glf.StatusRecord","type":"object"}"#)?;
+                let channel_id = mcap.channel(&format!("/sonar/{}/status", stat_rec.device_id), schema_id)?;
+                let message = serde_json::to_value(stat_rec).map_err(|_| "Failed to encode status record.")?;
+                mcap.message(channel_id, stat_rec.header.time, &message)?;
+            },
+            HeaderType::RawSerial => {
+                let serial_rec = &glf.serials[entry.index];
+                let time = serial_rec.header.time;
+
+                for fix in serial_rec.nmea_fixes() {
+                    let schema_id = mcap.schema("foxglove.LocationFix", &format!(
+                        r#"{{"title":"foxglove.LocationFix","type":"object","properties":{{"timestamp":{},"frame_id":{{"type":"string"}},"latitude":{{"type":"number"}},"longitude":{{"type":"number"}},"altitude":{{"type":"number"}},"position_covariance":{{"type":"array","items":{{"type":"number"}}}},"position_covariance_type":{{"type":"integer"}}}}}}"#,
+                        TIME_SCHEMA))?;
+                    let channel_id = mcap.channel(&format!("/serial/{}/fix", serial_rec.header.device_id), schema_id)?;
+                    mcap.message(channel_id, time, &json!({
+                        "timestamp": timestamp(time),
+                        "frame_id": format!("serial_{}", serial_rec.header.device_id),
+                        "latitude": fix.latitude,
+                        "longitude": fix.longitude,
+                        "altitude": fix.altitude.unwrap_or(0.0),
+                        "position_covariance": vec![0.0; 9],
+                        "position_covariance_type": 0,
+                    }))?;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    Ok((mcap.finish()?, skipped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{glf_from_dat, push_image, push_record, push_status, TestImage, TestStatus};
+    use std::convert::TryInto;
+
+    struct Message {
+        channel_id: u16,
+        sequence: u32,
+        log_time: u64,
+        publish_time: u64,
+        data: Value,
+    }
+
+    fn u16_at(buf: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes(buf[at..at + 2].try_into().unwrap())
+    }
+
+    fn u32_at(buf: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(buf[at..at + 4].try_into().unwrap())
+    }
+
+    fn u64_at(buf: &[u8], at: usize) -> u64 {
+        u64::from_le_bytes(buf[at..at + 8].try_into().unwrap())
+    }
+
+    fn str_at(buf: &[u8], at: &mut usize) -> String {
+        let len = u32_at(buf, *at) as usize;
+        let s = std::str::from_utf8(&buf[*at + 4..*at + 4 + len]).unwrap().to_string();
+        *at += 4 + len;
+        s
+    }
+
+    /// What the reader found in an MCAP file.
+    struct Contents {
+        /// Schema names by id.
+        schemas: HashMap<u16, String>,
+        /// Channel topics and schema ids by id.
+        channels: HashMap<u16, (String, u16)>,
+        messages: Vec<Message>,
+        /// The opcode of every record, in order.
+        opcodes: Vec<u8>,
+    }
+
+    /// A minimal MCAP reader.
+    fn read_mcap(buf: &[u8]) -> Contents {
+        assert_eq!(&buf[..MAGIC.len()], MAGIC);
+        assert_eq!(&buf[buf.len() - MAGIC.len()..], MAGIC);
+        let (mut schemas, mut channels, mut messages, mut opcodes) = (HashMap::new(), HashMap::new(), vec![], vec![]);
+        let mut at = MAGIC.len();
+
+        while at < buf.len() - MAGIC.len() {
+            let opcode = buf[at];
+            let len = u64_at(buf, at + 1) as usize;
+            let content = &buf[at + 9..at + 9 + len];
+            at += 9 + len;
+            opcodes.push(opcode);
+
+            match opcode {
+                OP_SCHEMA => {
+                    let mut pos = 2;
+                    let name = str_at(content, &mut pos);
+                    assert_eq!(str_at(content, &mut pos), "jsonschema");
+                    let schema: Value = serde_json::from_str(&str_at(content, &mut pos)).unwrap();
+                    assert_eq!(schema["title"], name.as_str());
+                    schemas.insert(u16_at(content, 0), name);
+                },
+                OP_CHANNEL => {
+                    let mut pos = 4;
+                    let topic = str_at(content, &mut pos);
+                    assert_eq!(str_at(content, &mut pos), "json");
+                    channels.insert(u16_at(content, 0), (topic, u16_at(content, 2)));
+                },
+                OP_MESSAGE => messages.push(Message {
+                    channel_id: u16_at(content, 0),
+                    sequence: u32_at(content, 2),
+                    log_time: u64_at(content, 6),
+                    publish_time: u64_at(content, 14),
+                    data: serde_json::from_slice(&content[22..]).unwrap(),
+                }),
+                _ => {},
+            }
+        }
+
+        assert_eq!(at, buf.len() - MAGIC.len());
+        Contents { schemas, channels, messages, opcodes }
+    }
+
+    #[test]
+    fn test_write_mcap() {
+        let mut dat: Vec<u8> = vec![];
+        push_image(&mut dat, &TestImage::new(1, 1.0));
+        push_status(&mut dat, &TestStatus::new(1, 1.2));
+        push_record(&mut dat, 98, 3, 1.4, b"$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47\r\n");
+        push_image(&mut dat, &TestImage::new(2, 2.0));
+        push_image(&mut dat, &TestImage::new(1, 3.0));
+        let glf = glf_from_dat(dat);
+
+        let options = McapOptions { compressed: false, fan_width: Some(16) };
+        let (buf, skipped) = write_mcap(&glf, Vec::new(), &options).unwrap();
+        assert!(skipped.is_empty());
+        let Contents { schemas, channels, messages, opcodes } = read_mcap(&buf);
+
+        assert_eq!(opcodes.first(), Some(&OP_HEADER));
+        assert_eq!(&opcodes[opcodes.len() - 2..], &[OP_DATA_END, OP_FOOTER]);
+
+        let mut names: Vec<&str> = schemas.values().map(|s| s.as_str()).collect();
+        names.sort();
+        assert_eq!(names, vec!["foxglove.LocationFix", "foxglove.RawImage", "glf.StatusRecord"]);
+
+        let topic = |m: &Message| channels[&m.channel_id].0.clone();
+        let mut topics: Vec<String> = channels.values().map(|c| c.0.clone()).collect();
+        topics.sort();
+        assert_eq!(topics, vec!["/serial/3/fix", "/sonar/1/fan", "/sonar/1/image", "/sonar/1/status", "/sonar/2/fan", "/sonar/2/image"]);
+
+        for (name, schema_id) in channels.values() {
+            let expected = if name.ends_with("status") { "glf.StatusRecord" } else if name.ends_with("fix") { "foxglove.LocationFix" } else { "foxglove.RawImage" };
+            assert_eq!(schemas[schema_id], expected);
+        }
+
+        // Each image gives an image and a fan message, in file order.
+        let order: Vec<String> = messages.iter().map(topic).collect();
+        assert_eq!(order, vec!["/sonar/1/image", "/sonar/1/fan", "/sonar/1/status", "/serial/3/fix", "/sonar/2/image", "/sonar/2/fan", "/sonar/1/image", "/sonar/1/fan"]);
+        assert!(messages.iter().enumerate().all(|(i, m)| m.sequence == i as u32));
+
+        let times = [glf.images[0].header.time, glf.statuses[0].header.time, glf.serials[0].header.time];
+        let nanos: Vec<u64> = times.iter().map(|t| t.timestamp_nanos_opt().unwrap() as u64).collect();
+        assert_eq!((messages[0].log_time, messages[0].publish_time), (nanos[0], nanos[0]));
+        assert_eq!(messages[2].log_time, nanos[1]);
+        assert_eq!(messages[3].log_time, nanos[2]);
+        assert!(messages.windows(2).all(|w| w[0].log_time <= w[1].log_time));
+
+        let image = &messages[0].data;
+        assert_eq!((image["width"].as_u64(), image["height"].as_u64()), (Some(4), Some(8)));
+        assert_eq!(image["encoding"], "mono8");
+        assert_eq!(image["timestamp"]["sec"], times[0].timestamp());
+        let pixels = base64::engine::general_purpose::STANDARD.decode(image["data"].as_str().unwrap()).unwrap();
+        assert_eq!(pixels, glf.extract_image(0).unwrap().into_raw());
+        assert_eq!(messages[1].data["width"], 16);
+        assert_eq!(messages[2].data["device_id"], 1);
+        assert!((messages[3].data["latitude"].as_f64().unwrap() - 48.1173).abs() < 1e-4);
+
+        let (buf, _) = write_mcap(&glf, Vec::new(), &McapOptions::default()).unwrap();
+        let Contents { schemas, messages, .. } = read_mcap(&buf);
+        assert!(schemas.values().any(|s| s == "foxglove.CompressedImage"));
+        assert_eq!(messages.len(), 5);
+        assert_eq!(messages[0].data["format"], "png");
+    }
+
+    #[test]
+    fn test_write_mcap_skips_frames() {
+        let mut dat: Vec<u8> = vec![];
+        push_image(&mut dat, &TestImage::new(1, 1.0));
+        push_image(&mut dat, &TestImage { h264: true, ..TestImage::new(1, 2.0) });
+        push_image(&mut dat, &TestImage::new(1, 3.0));
+        let glf = glf_from_dat(dat);
+
+        // The H264 frame is left out, and the frames either side still written.
+        let (buf, skipped) = write_mcap(&glf, Vec::new(), &McapOptions::default()).unwrap();
+        assert_eq!(skipped, vec![1]);
+        let nanos = |idx: usize| glf.images[idx].header.time.timestamp_nanos_opt().unwrap() as u64;
+        let times: Vec<u64> = read_mcap(&buf).messages.iter().map(|m| m.log_time).collect();
+        assert_eq!(times, vec![nanos(0), nanos(2)]);
+    }
+}
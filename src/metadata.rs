@@ -0,0 +1,97 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # Metadata
+//! Writes the metadata of every record as newline delimited JSON (NDJSON),
+//! one line per record in file order. Each line is the serialised record,
+//! with a `record_type` field naming which kind of record it is.
+
+use crate::{HeaderType, GLF};
+use serde::Serialize;
+use serde_json::Value;
+use std::io::Write;
+
+/// Serialise a record and tag it with its type.
+///
+/// * `record_type` - the name to put in the `record_type` field.
+/// * `record` - the record to serialise.
+fn tagged<T: Serialize>(record_type: &str, record: &T) -> Result<Value, &'static str> {
+    let mut value = serde_json::to_value(record).map_err(|_| "Failed to serialise record.")?;
+
+    if let Value::Object(map) = &mut value {
+        map.insert("record_type".to_string(), Value::from(record_type));
+    }
+
+    Ok(value)
+}
+
+impl GLF {
+    /// Write the metadata of every record as NDJSON, in file order.
+    ///
+    /// * `writer` - where to write the lines.
+    /// * `include_bearing_table` - keep the bearing table in the image records. It is
+    ///   often the bulk of the output, so can be left out.
+    pub fn export_metadata_json<W: Write>(&self, writer: &mut W, include_bearing_table: bool) -> Result<(), &'static str> {
+        for entry in &self.records {
+            let value = match entry.record_type {
+                HeaderType::Image => {
+                    let mut value = tagged("image", &self.images[entry.index])?;
+
+                    if !include_bearing_table {
+                        if let Value::Object(map) = &mut value {
+                            map.remove("bearing_table");
+                        }
+                    }
+
+                    value
+                },
+                HeaderType::GeminiStatus => tagged("status", &self.statuses[entry.index])?,
+                HeaderType::RawSerial => tagged("serial", &self.serials[entry.index])?,
+                _ => continue,
+            };
+
+            serde_json::to_writer(&mut *writer, &value).map_err(|_| "Failed to write metadata.")?;
+            writer.write_all(b"\n").map_err(|_| "Failed to write metadata.")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::{glf_from_dat, push_image, push_record, push_status, TestImage, TestStatus};
+
+    #[test]
+    fn test_export_metadata_json() {
+        let mut dat: Vec<u8> = vec![];
+        push_status(&mut dat, &TestStatus::new(1, 1.0));
+        push_image(&mut dat, &TestImage::new(1, 1.5));
+        push_record(&mut dat, 98, 1, 1.7, b"$GPGGA");
+        push_image(&mut dat, &TestImage::new(2, 2.0));
+        let glf = glf_from_dat(dat);
+
+        for include_bearing_table in [false, true] {
+            let mut out: Vec<u8> = vec![];
+            glf.export_metadata_json(&mut out, include_bearing_table).unwrap();
+            let text = String::from_utf8(out).unwrap();
+            assert!(text.ends_with('\n'));
+
+            let lines: Vec<serde_json::Value> = text.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+            let types: Vec<&str> = lines.iter().map(|l| l["record_type"].as_str().unwrap()).collect();
+            assert_eq!(types, vec!["status", "image", "serial", "image"]);
+            assert_eq!(lines[3]["header"]["device_id"], 2);
+            assert_eq!(lines[1]["modulation_frequency"], 720_000);
+
+            for line in [&lines[1], &lines[3]] {
+                assert_eq!(line.get("bearing_table").is_some(), include_bearing_table);
+            }
+
+            if include_bearing_table {
+                assert_eq!(lines[1]["bearing_table"].as_array().unwrap().len(), 4);
+            }
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # Models
+//! A catalogue of the Gemini sonar models, with their nominal specifications
+//! taken from the Tritech datasheets. These are useful for choosing processing
+//! parameters and display defaults for a given head.
+
+use crate::{FrequencyMode, SonarType};
+
+/// The nominal specification of a sonar when operating at one frequency.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ModeSpec {
+    /// The frequency mode this spec applies to.
+    pub mode: FrequencyMode,
+    /// Nominal operating frequency in Hz.
+    pub frequency: u32,
+    /// Horizontal field of view of the whole fan in degrees. Divide by the
+    /// number of beams, as `SonarModel::beam_spacing` does, for the spacing
+    /// of one beam.
+    pub horizontal_fov: f64,
+    /// Vertical beam width in degrees.
+    pub vertical_beam_width: f64,
+    /// Maximum range in metres.
+    pub max_range: f64,
+    /// Range resolution in metres.
+    pub range_resolution: f64,
+}
+
+/// A sonar model and its specifications.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SonarModel {
+    /// The sonar type code this model is recorded as.
+    pub sonar_type: SonarType,
+    /// Human readable name.
+    pub name: &'static str,
+    /// The number of beams.
+    pub beams: u16,
+    /// The specification for each supported frequency, low frequency first.
+    pub modes: &'static [ModeSpec],
+}
+
+const LF_720: ModeSpec = ModeSpec {
+    mode: FrequencyMode::Low,
+    frequency: 720_000,
+    horizontal_fov: 130.0,
+    vertical_beam_width: 20.0,
+    max_range: 120.0,
+    range_resolution: 0.008,
+};
+
+const HF_1200: ModeSpec = ModeSpec {
+    mode: FrequencyMode::High,
+    frequency: 1_200_000,
+    horizontal_fov: 65.0,
+    vertical_beam_width: 12.0,
+    max_range: 50.0,
+    range_resolution: 0.0024,
+};
+
+const MICRON_720: ModeSpec = ModeSpec {
+    mode: FrequencyMode::Low,
+    frequency: 720_000,
+    horizontal_fov: 90.0,
+    vertical_beam_width: 20.0,
+    max_range: 50.0,
+    range_resolution: 0.013,
+};
+
+/// Every model we know about.
+pub static SONAR_MODELS: [SonarModel; 8] = [
+    SonarModel { sonar_type: SonarType::Gemini720i, name: "Gemini 720i", beams: 256, modes: &[LF_720] },
+    SonarModel { sonar_type: SonarType::Gemini720ik, name: "Gemini 720ik", beams: 512, modes: &[LF_720] },
+    SonarModel { sonar_type: SonarType::Gemini720im, name: "Gemini 720im", beams: 512, modes: &[LF_720] },
+    SonarModel { sonar_type: SonarType::Gemini720is, name: "Gemini 720is", beams: 512, modes: &[LF_720] },
+    SonarModel { sonar_type: SonarType::Gemini720id, name: "Gemini 720id", beams: 512, modes: &[LF_720] },
+    SonarModel { sonar_type: SonarType::Gemini1200ik, name: "Gemini 1200ik", beams: 512, modes: &[LF_720, HF_1200] },
+    SonarModel { sonar_type: SonarType::Gemini1200id, name: "Gemini 1200id", beams: 512, modes: &[LF_720, HF_1200] },
+    SonarModel { sonar_type: SonarType::MicronGemini, name: "Micron Gemini", beams: 256, modes: &[MICRON_720] },
+];
+
+impl SonarModel {
+    /// Look up the model for a sonar type. Returns None for unknown types.
+    ///
+    /// * `sonar_type` - the sonar type from the ImageRecord.
+    pub fn lookup(sonar_type: SonarType) -> Option<&'static SonarModel> {
+        SONAR_MODELS.iter().find(|m| m.sonar_type == sonar_type)
+    }
+
+    /// Return the specification for a frequency mode, if this model supports it.
+    ///
+    /// * `mode` - the frequency mode we want.
+    pub fn spec(&self, mode: FrequencyMode) -> Option<&'static ModeSpec> {
+        self.modes.iter().find(|s| s.mode == mode)
+    }
+
+    /// The nominal frequencies of this model, in Hz.
+    pub fn frequencies(&self) -> Vec<u32> {
+        self.modes.iter().map(|s| s.frequency).collect()
+    }
+
+    /// The angle between neighbouring beams in degrees, for a frequency mode
+    /// this model supports.
+    ///
+    /// * `mode` - the frequency mode we want.
+    pub fn beam_spacing(&self, mode: FrequencyMode) -> Option<f64> {
+        self.spec(mode).map(|s| s.horizontal_fov / self.beams as f64)
+    }
+
+    /// Is this a dual frequency model?
+    pub fn is_dual_frequency(&self) -> bool {
+        self.modes.len() > 1
+    }
+}
+
+impl std::fmt::Display for SonarModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{build_dat, glf_from_dat, TestImage};
+
+    #[test]
+    fn test_model_per_sonar_type() {
+        let expected = [
+            (1, "Gemini 720i", 256, false),
+            (2, "Gemini 720ik", 512, false),
+            (3, "Gemini 720im", 512, false),
+            (4, "Gemini 720is", 512, false),
+            (5, "Gemini 720id", 512, false),
+            (6, "Gemini 1200ik", 512, true),
+            (7, "Gemini 1200id", 512, true),
+            (8, "Micron Gemini", 256, false),
+        ];
+
+        let images: Vec<TestImage> = (0..=9u8).map(|sonar_type| {
+            let mut img = TestImage::new(1, sonar_type as f64);
+            img.sonar_type = sonar_type;
+            img
+        }).collect();
+        let glf = glf_from_dat(build_dat(&images));
+
+        // Codes outside the catalogue have no model.
+        assert!(glf.images[0].model().is_none());
+        assert!(glf.images[9].model().is_none());
+        assert_eq!(glf.images[9].sonar_type, SonarType::Unknown(9));
+
+        for (code, name, beams, dual) in expected {
+            let model = glf.images[code as usize].model().unwrap();
+            assert_eq!(model.sonar_type, SonarType::from(code));
+            assert_eq!(model.to_string(), name);
+            assert_eq!(model.beams, beams);
+            assert_eq!(model.is_dual_frequency(), dual);
+            assert!(model.spec(FrequencyMode::Low).is_some());
+            assert_eq!(model.spec(FrequencyMode::High).is_some(), dual);
+        }
+
+        let hf = SonarModel::lookup(SonarType::Gemini1200ik).unwrap();
+        assert_eq!(hf.frequencies(), vec![720_000, 1_200_000]);
+        assert_eq!(hf.spec(FrequencyMode::High).unwrap().vertical_beam_width, 12.0);
+        assert_eq!(hf.beam_spacing(FrequencyMode::High), Some(65.0 / 512.0));
+        assert!((hf.beam_spacing(FrequencyMode::Low).unwrap() - 0.254).abs() < 1e-3);
+        assert_eq!(SonarModel::lookup(SonarType::Gemini720ik).unwrap().beam_spacing(FrequencyMode::High), None);
+    }
+}
@@ -0,0 +1,217 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # Network
+//! Typed views of the network settings held in each StatusRecord, and a
+//! per-device summary of the addresses seen across a file.
+//!
+//! The IP addresses are stored in the dat file in network byte order, so
+//! the first byte on disk is the first octet of the dotted quad. As the
+//! StatusRecord reads them as little endian u32s, the octets are recovered
+//! with `to_le_bytes`.
+
+use crate::StatusRecord;
+use std::fmt;
+use std::net::Ipv4Addr;
+
+/// An Ethernet MAC address.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+    /// Return the six bytes of the address.
+    pub fn octets(&self) -> [u8; 6] {
+        self.0
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = self.0;
+        write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", b[0], b[1], b[2], b[3], b[4], b[5])
+    }
+}
+
+/// An IPv4 network, given as its base address and prefix length.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ipv4Network {
+    /// The network address (host bits cleared).
+    pub addr: Ipv4Addr,
+    /// The number of leading one bits in the subnet mask.
+    pub prefix_len: u8,
+}
+
+impl Ipv4Network {
+    /// Derive the network from an address and its subnet mask.
+    ///
+    /// * `addr` - any address on the network.
+    /// * `mask` - the subnet mask.
+    pub fn new(addr: Ipv4Addr, mask: Ipv4Addr) -> Ipv4Network {
+        let mask_bits = u32::from(mask);
+        Ipv4Network {
+            addr: Ipv4Addr::from(u32::from(addr) & mask_bits),
+            prefix_len: mask_bits.leading_ones() as u8,
+        }
+    }
+
+    /// Does this network contain the given address?
+    ///
+    /// * `addr` - the address to test.
+    pub fn contains(&self, addr: Ipv4Addr) -> bool {
+        let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0);
+        (u32::from(addr) & mask) == u32::from(self.addr)
+    }
+}
+
+impl fmt::Display for Ipv4Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl StatusRecord {
+    /// The alternative IP address of the sonar.
+    pub fn sonar_alt_ipv4(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.sonar_alt_ip.to_le_bytes())
+    }
+
+    /// The IP address of the surface PC the sonar is connected to.
+    pub fn surface_ipv4(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.surface_ip.to_le_bytes())
+    }
+
+    /// The subnet mask of the sonar.
+    pub fn subnet_mask_ipv4(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.subnet_mask)
+    }
+
+    /// The MAC address of the sonar.
+    pub fn mac_address(&self) -> MacAddr {
+        MacAddr(self.mac_addr)
+    }
+
+    /// The network of the sonar, from its alternative IP and subnet mask.
+    pub fn network(&self) -> Ipv4Network {
+        Ipv4Network::new(self.sonar_alt_ipv4(), self.subnet_mask_ipv4())
+    }
+}
+
+/// The distinct network settings seen for one device across a file.
+/// Each list is in the order the values were first seen.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceNetwork {
+    /// The device ID (the sonar id).
+    pub device_id: u16,
+    /// Alternative IP addresses of the sonar.
+    pub sonar_ips: Vec<Ipv4Addr>,
+    /// Surface PC IP addresses.
+    pub surface_ips: Vec<Ipv4Addr>,
+    /// MAC addresses of the sonar.
+    pub mac_addrs: Vec<MacAddr>,
+    /// Networks of the sonar.
+    pub networks: Vec<Ipv4Network>,
+}
+
+fn push_distinct<T: PartialEq>(values: &mut Vec<T>, value: T) {
+    if !values.contains(&value) {
+        values.push(value);
+    }
+}
+
+/// Summarise the network settings of each device, ordered by device id.
+///
+/// * `statuses` - the status records to summarise.
+pub fn summarise_network(statuses: &[StatusRecord]) -> Vec<DeviceNetwork> {
+    let mut devices: Vec<DeviceNetwork> = vec![];
+
+    for stat_rec in statuses {
+        let pos = match devices.iter().position(|d| d.device_id == stat_rec.device_id) {
+            Some(pos) => pos,
+            None => {
+                devices.push(DeviceNetwork {
+                    device_id: stat_rec.device_id,
+                    sonar_ips: vec![],
+                    surface_ips: vec![],
+                    mac_addrs: vec![],
+                    networks: vec![],
+                });
+                devices.len() - 1
+            }
+        };
+
+        let device = &mut devices[pos];
+        push_distinct(&mut device.sonar_ips, stat_rec.sonar_alt_ipv4());
+        push_distinct(&mut device.surface_ips, stat_rec.surface_ipv4());
+        push_distinct(&mut device.mac_addrs, stat_rec.mac_address());
+        push_distinct(&mut device.networks, stat_rec.network());
+    }
+
+    devices.sort_by_key(|d| d.device_id);
+    devices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{glf_from_dat, push_status, TestStatus};
+
+    #[test]
+    fn test_status_addresses() {
+        let mut dat: Vec<u8> = vec![];
+        push_status(&mut dat, &TestStatus::new(1, 1.0));
+        let glf = glf_from_dat(dat);
+        let stat_rec = &glf.statuses[0];
+
+        // On disk 192.168.2.201, read as a little endian u32.
+        assert_eq!(stat_rec.sonar_alt_ip, u32::from_le_bytes([192, 168, 2, 201]));
+        assert_eq!(stat_rec.sonar_alt_ipv4(), Ipv4Addr::new(192, 168, 2, 201));
+        assert_eq!(stat_rec.surface_ipv4(), Ipv4Addr::new(192, 168, 2, 10));
+        assert_eq!(stat_rec.subnet_mask_ipv4(), Ipv4Addr::new(255, 255, 255, 0));
+        assert_eq!(stat_rec.mac_address().to_string(), "00:01:02:03:04:05");
+        assert_eq!(MacAddr([0xde, 0xad, 0xbe, 0xef, 0x0a, 0xff]).to_string(), "de:ad:be:ef:0a:ff");
+        assert_eq!(stat_rec.network().to_string(), "192.168.2.0/24");
+    }
+
+    #[test]
+    fn test_ipv4_network() {
+        let net = Ipv4Network::new(Ipv4Addr::new(10, 1, 130, 7), Ipv4Addr::new(255, 255, 128, 0));
+        assert_eq!(net.addr, Ipv4Addr::new(10, 1, 128, 0));
+        assert_eq!(net.prefix_len, 17);
+        assert!(net.contains(Ipv4Addr::new(10, 1, 255, 255)));
+        assert!(!net.contains(Ipv4Addr::new(10, 1, 127, 255)));
+
+        let any = Ipv4Network::new(Ipv4Addr::new(1, 2, 3, 4), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(any.to_string(), "0.0.0.0/0");
+        assert!(any.contains(Ipv4Addr::new(8, 8, 8, 8)));
+        let host = Ipv4Network::new(Ipv4Addr::new(1, 2, 3, 4), Ipv4Addr::BROADCAST);
+        assert_eq!(host.to_string(), "1.2.3.4/32");
+        assert!(!host.contains(Ipv4Addr::new(1, 2, 3, 5)));
+    }
+
+    #[test]
+    fn test_summarise_network() {
+        let mut dat: Vec<u8> = vec![];
+        push_status(&mut dat, &TestStatus::new(2, 1.0));
+        push_status(&mut dat, &TestStatus::new(1, 1.5));
+        push_status(&mut dat, &TestStatus::new(2, 2.0));
+        let mut glf = glf_from_dat(dat);
+
+        // Device 2 moves to another address and network part way through.
+        glf.statuses[2].sonar_alt_ip = u32::from_le_bytes([10, 0, 0, 5]);
+        glf.statuses[2].subnet_mask = [255, 0, 0, 0];
+
+        let summary = summarise_network(&glf.statuses);
+        assert_eq!(summary.iter().map(|d| d.device_id).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(summary[0].sonar_ips, vec![Ipv4Addr::new(192, 168, 2, 201)]);
+        assert_eq!(summary[1].sonar_ips, vec![Ipv4Addr::new(192, 168, 2, 201), Ipv4Addr::new(10, 0, 0, 5)]);
+        assert_eq!(summary[1].surface_ips, vec![Ipv4Addr::new(192, 168, 2, 10)]);
+        assert_eq!(summary[1].mac_addrs, vec![MacAddr([0, 1, 2, 3, 4, 5])]);
+        assert_eq!(summary[1].networks.iter().map(|n| n.to_string()).collect::<Vec<_>>(), vec!["192.168.2.0/24", "10.0.0.0/8"]);
+        assert_eq!(glf.network_summary(), summary);
+    }
+}
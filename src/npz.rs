@@ -0,0 +1,165 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # Npz
+//! Writes frames and their metadata into a NumPy `.npz` archive, without
+//! needing Python. The archive holds:
+//!
+//! * `frames` - uint8, N x H x W, raw polar or scan converted.
+//! * `timestamps` - float64 seconds since the Unix epoch, from the CIHeader.
+//! * `ranges` - uint32 `range_end` of each frame.
+//! * `gains` - uint16 `percent_gain` of each frame.
+//! * `bearing_table` - float64 bearing of each beam, shared by every frame.
+//!
+//! Frames are decoded and written one at a time, so memory use does not
+//! grow with the number of frames.
+
+use crate::{FanConverter, FrameDecoder, GLF};
+use std::io::{Seek, Write};
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+/// Which form of the frames to export.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum FrameKind {
+    /// The raw polar image, one column per beam.
+    Raw,
+    /// The scan converted fan image, with the given width in pixels.
+    Fan(u32),
+}
+
+/// Build the header of a version 1.0 .npy file.
+///
+/// * `descr` - the numpy dtype string, such as `<f8`.
+/// * `shape` - the array dimensions.
+fn npy_header(descr: &str, shape: &[usize]) -> Vec<u8> {
+    let dims: Vec<String> = shape.iter().map(|d| d.to_string()).collect();
+    let shape_str = if dims.len() == 1 { format!("({},)", dims[0]) } else { format!("({})", dims.join(", ")) };
+    let mut dict = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}", descr, shape_str);
+    // Magic (6), version (2) and length (2), then pad so the data is 64 byte aligned.
+    let total = 10 + dict.len() + 1;
+    dict.push_str(&" ".repeat((64 - total % 64) % 64));
+    dict.push('\n');
+
+    let mut header: Vec<u8> = b"\x93NUMPY\x01\x00".to_vec();
+    header.extend_from_slice(&(dict.len() as u16).to_le_bytes());
+    header.extend_from_slice(dict.as_bytes());
+    header
+}
+
+/// Write a small, one dimensional array as its own .npy entry.
+///
+/// * `zip` - the archive being written.
+/// * `name` - the array name, without the .npy extension.
+/// * `descr` - the numpy dtype string.
+/// * `len` - the number of elements.
+/// * `data` - the little endian bytes of the elements.
+fn write_array<W: Write + Seek>(zip: &mut ZipWriter<W>, name: &str, descr: &str, len: usize, data: &[u8]) -> Result<(), &'static str> {
+    zip.start_file(format!("{}.npy", name), FileOptions::default()).map_err(|_| "Failed to start npz entry.")?;
+    zip.write_all(&npy_header(descr, &[len])).map_err(|_| "Failed to write npz entry.")?;
+    zip.write_all(data).map_err(|_| "Failed to write npz entry.")?;
+    Ok(())
+}
+
+/// Write the given frames into an .npz archive. All the frames must share
+/// the same size and bearing table - a Segment's frames always do.
+///
+/// * `glf` - the GLF to read from.
+/// * `writer` - where to write the archive.
+/// * `frames` - indices into GLF::images, in the order to write them.
+/// * `kind` - raw polar or scan converted frames.
+pub fn write_npz<W: Write + Seek>(glf: &GLF, writer: W, frames: &[usize], kind: FrameKind) -> Result<(), &'static str> {
+    let first = match frames.first() {
+        Some(idx) => glf.images.get(*idx).ok_or("Frame index out of range.")?,
+        None => return Err("No frames to export."),
+    };
+
+    for idx in frames {
+        let img_rec = glf.images.get(*idx).ok_or("Frame index out of range.")?;
+
+        if img_rec.image_width != first.image_width || img_rec.image_height != first.image_height
+            || img_rec.bearing_table != first.bearing_table {
+            return Err("Frames differ in size or bearing table - export each segment separately.");
+        }
+    }
+
+    let converter = match kind {
+        FrameKind::Raw => None,
+        FrameKind::Fan(width) => Some(FanConverter::new(&first.bearing_table, first.image_height, width)?),
+    };
+
+    let (height, width) = match &converter {
+        Some(conv) => (conv.height, conv.width),
+        None => (first.image_height, first.image_width),
+    };
+
+    let mut zip = ZipWriter::new(writer);
+    let options = FileOptions::default().large_file(true);
+    zip.start_file("frames.npy", options).map_err(|_| "Failed to start npz entry.")?;
+    zip.write_all(&npy_header("|u1", &[frames.len(), height as usize, width as usize])).map_err(|_| "Failed to write npz entry.")?;
+    let mut fan: Vec<u8> = vec![0; (width as usize).checked_mul(height as usize).ok_or("Image is too large.")?];
+    let mut decoder = FrameDecoder::new();
+
+    for idx in frames {
+        let polar = decoder.decode(glf, *idx)?;
+
+        match &converter {
+            Some(conv) => {
+                conv.convert_into(polar, &mut fan)?;
+                zip.write_all(&fan).map_err(|_| "Failed to write npz entry.")?;
+            },
+            None => zip.write_all(polar).map_err(|_| "Failed to write npz entry.")?,
+        }
+    }
+
+    let mut timestamps: Vec<u8> = vec![];
+    let mut ranges: Vec<u8> = vec![];
+    let mut gains: Vec<u8> = vec![];
+
+    for idx in frames {
+        let img_rec = &glf.images[*idx];
+        let seconds = img_rec.header.time.timestamp_micros() as f64 / 1e6;
+        timestamps.extend_from_slice(&seconds.to_le_bytes());
+        ranges.extend_from_slice(&img_rec.range_end.to_le_bytes());
+        gains.extend_from_slice(&img_rec.percent_gain.to_le_bytes());
+    }
+
+    let bearings: Vec<u8> = first.bearing_table.iter().flat_map(|b| b.to_le_bytes()).collect();
+    write_array(&mut zip, "timestamps", "<f8", frames.len(), &timestamps)?;
+    write_array(&mut zip, "ranges", "<u4", frames.len(), &ranges)?;
+    write_array(&mut zip, "gains", "<u2", frames.len(), &gains)?;
+    write_array(&mut zip, "bearing_table", "<f8", first.bearing_table.len(), &bearings)?;
+    zip.finish().map_err(|_| "Failed to finish npz archive.")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{build_dat, glf_from_dat, TestImage};
+    use std::io::{Cursor, Read};
+
+    #[test]
+    fn test_write_npz() {
+        let glf = glf_from_dat(build_dat(&[TestImage::new(1, 1.0), TestImage::new(2, 1.5), TestImage::new(1, 2.0)]));
+        let frames = glf.device_frames(1);
+        let mut cursor = Cursor::new(Vec::new());
+        write_npz(&glf, &mut cursor, &frames, FrameKind::Raw).unwrap();
+
+        let mut zip = zip::ZipArchive::new(cursor).unwrap();
+        let mut npy: Vec<u8> = vec![];
+        zip.by_name("frames.npy").unwrap().read_to_end(&mut npy).unwrap();
+        let header_len = u16::from_le_bytes([npy[8], npy[9]]) as usize;
+        let header = std::str::from_utf8(&npy[10..10 + header_len]).unwrap();
+        assert_eq!((10 + header_len) % 64, 0);
+        assert!(header.contains("'shape': (2, 8, 4)"));
+        assert_eq!(&npy[10 + header_len..], [glf.extract_image(0).unwrap().into_raw(), glf.extract_image(2).unwrap().into_raw()].concat());
+
+        let mut timestamps: Vec<u8> = vec![];
+        zip.by_name("timestamps.npy").unwrap().read_to_end(&mut timestamps).unwrap();
+        assert_eq!((timestamps.len() - 16) % 64, 0);
+        assert!(zip.by_name("bearing_table.npy").is_ok());
+    }
+}
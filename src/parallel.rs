@@ -0,0 +1,68 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # Parallel
+//! Decodes many frames at once across all cores with rayon. Inflating the
+//! zlib payloads is most of the cost of reading a GLF, and every frame is
+//! independent, so this scales with the number of cores. Results always
+//! come back in the order the frames were asked for.
+
+use crate::GLF;
+use image::GrayImage;
+use rayon::prelude::*;
+
+impl GLF {
+    /// Extract many images in parallel, returned in the order of `indices`.
+    /// Fails if any frame fails.
+    ///
+    /// * `indices` - indices into GLF::images.
+    pub fn extract_images_par(&self, indices: &[usize]) -> Result<Vec<GrayImage>, &'static str> {
+        self.par_images(indices).collect()
+    }
+
+    /// A parallel iterator decoding the given frames. Being indexed, it keeps
+    /// the order of `indices` through `collect`, `zip` and `enumerate`.
+    ///
+    /// * `indices` - indices into GLF::images.
+    pub fn par_images<'a>(&'a self, indices: &'a [usize]) -> impl IndexedParallelIterator<Item = Result<GrayImage, &'static str>> + 'a {
+        indices.par_iter().map(move |idx| self.extract_image(*idx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::{build_dat, glf_from_dat, TestImage};
+    use crate::CompressionType;
+    use rayon::prelude::*;
+
+    #[test]
+    fn test_extract_images_par() {
+        let images: Vec<TestImage> = (0..32).map(|i| TestImage::new(1, i as f64)).collect();
+        let glf = glf_from_dat(build_dat(&images));
+        let indices: Vec<usize> = (0..32).rev().collect();
+        let par = glf.extract_images_par(&indices).unwrap();
+        let seq: Vec<_> = indices.iter().map(|idx| glf.extract_image(*idx).unwrap()).collect();
+        assert_eq!(par, seq);
+        assert!(glf.extract_images_par(&[0, 32]).is_err());
+    }
+
+    #[test]
+    fn test_extract_images_par_zlib() {
+        let images: Vec<TestImage> = (0..16).map(|i| TestImage { zlib: i % 2 == 0, ..TestImage::new(1, i as f64) }).collect();
+        let mut glf = glf_from_dat(build_dat(&images));
+        let plain = glf_from_dat(build_dat(&images.iter().map(|img| TestImage { zlib: false, ..img.clone() }).collect::<Vec<_>>()));
+        let indices: Vec<usize> = (0..16).collect();
+        assert_eq!(glf.images[0].compression_type, CompressionType::Zlib);
+        assert_eq!(glf.extract_images_par(&indices).unwrap(), plain.extract_images_par(&indices).unwrap());
+
+        // Corrupt one zlib payload; only that frame fails, in its place.
+        let start = glf.images[4].data_ptr as usize;
+        glf.dat.to_mut()[start..start + 4].copy_from_slice(&[0xff; 4]);
+        let results: Vec<_> = glf.par_images(&indices).collect();
+        assert!(results[4].is_err());
+        assert_eq!(results.iter().filter(|r| r.is_err()).count(), 1);
+        assert!(glf.extract_images_par(&indices).is_err());
+    }
+}
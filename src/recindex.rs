@@ -0,0 +1,503 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # Record index
+//! Saves the record table of a GLF - offsets, types, times, devices and the
+//! image settings most often searched on - to a sidecar file, next to the
+//! GLF or in a cache directory. Reopening the GLF loads the sidecar instead
+//! of parsing every record again, as long as the GLF's size and modification
+//! time still match. Together with an IndexedDat, frames can then be read
+//! without inflating or parsing the whole .dat, and with the .dat to hand
+//! a GLF is built from the index without scanning it for records.
+
+use crate::ciheader::parse_header;
+use crate::imagerec::parse_image_record;
+use crate::serialrec::parse_serial_record;
+use crate::statusrec::parse_status_record;
+use crate::archive::entry_file_name;
+use crate::{BearingTableSet, CompressionType, DatBuffer, GlfArchive, HeaderType, RecordEntry, SonarType, GLF};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
+use chrono::{DateTime, Utc};
+use std::fs;
+use std::io::{Cursor, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// The version of the sidecar format written by this crate.
+pub const RECORD_INDEX_VERSION: u32 = 1;
+/// Identifies a record index sidecar.
+const MAGIC: &[u8; 8] = b"GLFRIDX\0";
+
+/// The settings of an image record, enough to choose frames without parsing them.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ImageSummary {
+    /// Number of beams.
+    pub image_width: u32,
+    /// Number of samples per beam.
+    pub image_height: u32,
+    /// The starting range in metres.
+    pub range_start: u32,
+    /// End of the range in metres.
+    pub range_end: u32,
+    /// Compression type.
+    pub compression_type: CompressionType,
+    /// The number of bytes of pixel data.
+    pub data_size: u32,
+    /// The id of the bearing table.
+    pub bearing_table_id: u32,
+    /// Modulation frequency.
+    pub modulation_frequency: u32,
+    /// Percentage gain.
+    pub percent_gain: u16,
+    /// CHIRP mode on?
+    pub chirp: u8,
+    /// The type of the sonar.
+    pub sonar_type: SonarType,
+}
+
+/// One record of the index.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct IndexedRecord {
+    /// Where the record lies in the .dat.
+    pub entry: RecordEntry,
+    /// The time in UTC.
+    pub time: DateTime<Utc>,
+    /// The device ID (the sonar id).
+    pub device_id: u16,
+    /// The settings, for image records.
+    pub image: Option<ImageSummary>,
+}
+
+/// The size and modification time of a GLF, to tell if an index is stale.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct FileStamp {
+    len: u64,
+    secs: u64,
+    nanos: u32,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> Result<FileStamp, &'static str> {
+        let meta = fs::metadata(path).map_err(|_| "Failed to read the size and modification time of the GLF.")?;
+        let modified = meta.modified().ok().and_then(|m| m.duration_since(UNIX_EPOCH).ok()).unwrap_or_default();
+        Ok(FileStamp { len: meta.len(), secs: modified.as_secs(), nanos: modified.subsec_nanos() })
+    }
+}
+
+/// The record table of a GLF, as saved in a sidecar.
+#[derive(Clone, PartialEq, Debug)]
+pub struct RecordIndex {
+    /// Every record in the order it appears in the .dat.
+    pub records: Vec<IndexedRecord>,
+    entry: String,
+    stamp: FileStamp,
+}
+
+/// The path of the record index sidecar for a GLF. Without a cache directory
+/// it is the GLF path with `.records.idx` added. In a cache directory, the
+/// name also carries a hash of the GLF's full path, so GLFs with the same
+/// name in different directories don't collide.
+///
+/// * `path` - the path to the GLF.
+/// * `cache_dir` - a directory for sidecars, or None to keep them beside the GLFs.
+pub fn record_index_path(path: &Path, cache_dir: Option<&Path>) -> PathBuf {
+    sidecar_path(path, None, cache_dir)
+}
+
+/// The path of the record index sidecar for a named .dat entry of a GLF, as
+/// record_index_path but with the entry name before `.records.idx`.
+///
+/// * `path` - the path to the GLF.
+/// * `entry` - the full name of the .dat entry.
+/// * `cache_dir` - a directory for sidecars, or None to keep them beside the GLFs.
+pub fn record_entry_index_path(path: &Path, entry: &str, cache_dir: Option<&Path>) -> PathBuf {
+    sidecar_path(path, Some(entry), cache_dir)
+}
+
+fn sidecar_path(path: &Path, entry: Option<&str>, cache_dir: Option<&Path>) -> PathBuf {
+    let suffix = match entry {
+        Some(entry) => format!(".{}.records.idx", entry_file_name(entry)),
+        None => ".records.idx".to_string(),
+    };
+
+    match cache_dir {
+        None => {
+            let mut name = path.as_os_str().to_owned();
+            name.push(suffix);
+            PathBuf::from(name)
+        },
+        Some(dir) => {
+            let full = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+            // FNV-1a, as it must stay the same between builds.
+            let hash = full.to_string_lossy().bytes().fold(0xcbf29ce484222325u64, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3));
+            let stem = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            dir.join(format!("{}-{:016x}{}", stem, hash, suffix))
+        },
+    }
+}
+
+impl RecordIndex {
+    /// Build the index of a parsed GLF, stamped with the size and
+    /// modification time of its file.
+    ///
+    /// * `glf` - the GLF, read from GLF::filepath.
+    pub fn from_glf(glf: &GLF) -> Result<RecordIndex, &'static str> {
+        let stamp = FileStamp::of(&glf.filepath)?;
+        let records = glf.records.iter().map(|entry| {
+            let (header, image) = match entry.record_type {
+                HeaderType::Image => {
+                    let img_rec = &glf.images[entry.index];
+                    let summary = ImageSummary {
+                        image_width: img_rec.image_width,
+                        image_height: img_rec.image_height,
+                        range_start: img_rec.range_start,
+                        range_end: img_rec.range_end,
+                        compression_type: img_rec.compression_type,
+                        data_size: img_rec.data_size,
+                        bearing_table_id: img_rec.bearing_table_id,
+                        modulation_frequency: img_rec.modulation_frequency,
+                        percent_gain: img_rec.percent_gain,
+                        chirp: img_rec.chirp,
+                        sonar_type: img_rec.sonar_type,
+                    };
+                    (img_rec.header, Some(summary))
+                },
+                HeaderType::GeminiStatus => (glf.statuses[entry.index].header, None),
+                _ => (glf.serials[entry.index].header, None),
+            };
+
+            IndexedRecord { entry: *entry, time: header.time, device_id: header.device_id, image }
+        }).collect();
+
+        Ok(RecordIndex { records, entry: glf.entry_name.clone(), stamp })
+    }
+
+    /// Load the index of the first .dat of a GLF, from its sidecar if that
+    /// is current, or else by parsing the GLF and writing the sidecar. A
+    /// sidecar that can't be written isn't an error.
+    ///
+    /// * `path` - the path to the GLF.
+    /// * `cache_dir` - a directory for sidecars, or None to keep them beside the GLFs.
+    pub fn open(path: &Path, cache_dir: Option<&Path>) -> Result<RecordIndex, &'static str> {
+        let archive = GlfArchive::open(path)?;
+        let entry = archive.dat_entries().next().ok_or("Error parsing GLF.")?.name.clone();
+        RecordIndex::open_sidecar(path, &entry, &record_index_path(path, cache_dir), cache_dir)
+    }
+
+    /// Load the index of a named .dat entry of a GLF, as `open` does for the
+    /// first. Its sidecar is from record_entry_index_path.
+    ///
+    /// * `path` - the path to the GLF.
+    /// * `entry` - the full name of the .dat entry.
+    /// * `cache_dir` - a directory for sidecars, or None to keep them beside the GLFs.
+    pub fn open_entry(path: &Path, entry: &str, cache_dir: Option<&Path>) -> Result<RecordIndex, &'static str> {
+        RecordIndex::open_sidecar(path, entry, &record_entry_index_path(path, entry, cache_dir), cache_dir)
+    }
+
+    fn open_sidecar(path: &Path, entry: &str, sidecar: &Path, cache_dir: Option<&Path>) -> Result<RecordIndex, &'static str> {
+        if let Ok(index) = RecordIndex::load(sidecar) {
+            if index.entry == entry && index.is_current(path) {
+                return Ok(index);
+            }
+        }
+
+        let index = RecordIndex::from_glf(&GlfArchive::open(path)?.glf(entry)?)?;
+
+        if let Some(dir) = cache_dir {
+            let _ = fs::create_dir_all(dir);
+        }
+
+        let _ = index.save(sidecar);
+        Ok(index)
+    }
+
+    /// The name of the .dat entry this indexes.
+    pub fn entry_name(&self) -> &str {
+        &self.entry
+    }
+
+    /// Does the GLF still have the size and modification time it had when indexed?
+    ///
+    /// * `path` - the path to the GLF.
+    pub fn is_current(&self, path: &Path) -> bool {
+        FileStamp::of(path).is_ok_and(|stamp| stamp == self.stamp)
+    }
+
+    /// The image records.
+    pub fn images(&self) -> impl Iterator<Item = &IndexedRecord> {
+        self.records.iter().filter(|r| r.image.is_some())
+    }
+
+    /// The number of records.
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// True if there are no records.
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Write the index to a sidecar file, ending with a CRC32 of the rest.
+    ///
+    /// * `path` - the sidecar path, usually from record_index_path.
+    pub fn save(&self, path: &Path) -> Result<(), &'static str> {
+        let mut buffer: Vec<u8> = MAGIC.to_vec();
+        buffer.extend_from_slice(&RECORD_INDEX_VERSION.to_le_bytes());
+        buffer.extend_from_slice(&self.stamp.len.to_le_bytes());
+        buffer.extend_from_slice(&self.stamp.secs.to_le_bytes());
+        buffer.extend_from_slice(&self.stamp.nanos.to_le_bytes());
+        buffer.extend_from_slice(&(self.entry.len() as u16).to_le_bytes());
+        buffer.extend_from_slice(self.entry.as_bytes());
+        buffer.extend_from_slice(&(self.records.len() as u64).to_le_bytes());
+
+        for record in &self.records {
+            buffer.push(record.entry.record_type.into());
+            buffer.extend_from_slice(&(record.entry.index as u64).to_le_bytes());
+            buffer.extend_from_slice(&(record.entry.offset as u64).to_le_bytes());
+            buffer.extend_from_slice(&(record.entry.length as u64).to_le_bytes());
+            buffer.extend_from_slice(&record.time.timestamp_micros().to_le_bytes());
+            buffer.extend_from_slice(&record.device_id.to_le_bytes());
+
+            match &record.image {
+                None => buffer.push(0),
+                Some(image) => {
+                    buffer.push(1);
+                    buffer.extend_from_slice(&image.image_width.to_le_bytes());
+                    buffer.extend_from_slice(&image.image_height.to_le_bytes());
+                    buffer.extend_from_slice(&image.range_start.to_le_bytes());
+                    buffer.extend_from_slice(&image.range_end.to_le_bytes());
+                    buffer.extend_from_slice(&u16::from(image.compression_type).to_le_bytes());
+                    buffer.extend_from_slice(&image.data_size.to_le_bytes());
+                    buffer.extend_from_slice(&image.bearing_table_id.to_le_bytes());
+                    buffer.extend_from_slice(&image.modulation_frequency.to_le_bytes());
+                    buffer.extend_from_slice(&image.percent_gain.to_le_bytes());
+                    buffer.push(image.chirp);
+                    buffer.push(image.sonar_type.into());
+                },
+            }
+        }
+
+        let crc = crc32fast::hash(&buffer);
+        buffer.extend_from_slice(&crc.to_le_bytes());
+        fs::write(path, buffer).map_err(|_| "Failed to write the record index.")
+    }
+
+    /// Read an index from a sidecar file.
+    ///
+    /// * `path` - the sidecar path, usually from record_index_path.
+    pub fn load(path: &Path) -> Result<RecordIndex, &'static str> {
+        let buffer = fs::read(path).map_err(|_| "Failed to read the record index.")?;
+
+        if buffer.get(..8) != Some(&MAGIC[..]) {
+            return Err("Not a record index.");
+        }
+
+        if buffer.get(8..12) != Some(&RECORD_INDEX_VERSION.to_le_bytes()[..]) {
+            return Err("Record index is from a different version.");
+        }
+
+        let (body, crc) = buffer.split_at(buffer.len().max(16) - 4);
+
+        if crc.len() != 4 || crc32fast::hash(body) != LittleEndian::read_u32(crc) {
+            return Err("Record index is corrupt.");
+        }
+
+        RecordIndex::read(&mut Cursor::new(&body[12..])).map_err(|_| "Record index is corrupt.")
+    }
+
+    fn read(cursor: &mut Cursor<&[u8]>) -> std::io::Result<RecordIndex> {
+        let stamp = FileStamp {
+            len: cursor.read_u64::<LittleEndian>()?,
+            secs: cursor.read_u64::<LittleEndian>()?,
+            nanos: cursor.read_u32::<LittleEndian>()?,
+        };
+        let mut entry = vec![0u8; cursor.read_u16::<LittleEndian>()? as usize];
+        cursor.read_exact(&mut entry)?;
+        let entry = String::from_utf8(entry).map_err(|_| ErrorKind::InvalidData)?;
+        let count = cursor.read_u64::<LittleEndian>()?;
+        let mut records: Vec<IndexedRecord> = vec![];
+
+        for _ in 0..count {
+            let entry = RecordEntry {
+                record_type: HeaderType::from(cursor.read_u8()?),
+                index: cursor.read_u64::<LittleEndian>()? as usize,
+                offset: cursor.read_u64::<LittleEndian>()? as usize,
+                length: cursor.read_u64::<LittleEndian>()? as usize,
+            };
+            let time = DateTime::from_timestamp_micros(cursor.read_i64::<LittleEndian>()?).ok_or(ErrorKind::InvalidData)?;
+            let device_id = cursor.read_u16::<LittleEndian>()?;
+            let image = match cursor.read_u8()? {
+                0 => None,
+                _ => Some(ImageSummary {
+                    image_width: cursor.read_u32::<LittleEndian>()?,
+                    image_height: cursor.read_u32::<LittleEndian>()?,
+                    range_start: cursor.read_u32::<LittleEndian>()?,
+                    range_end: cursor.read_u32::<LittleEndian>()?,
+                    compression_type: CompressionType::from(cursor.read_u16::<LittleEndian>()?),
+                    data_size: cursor.read_u32::<LittleEndian>()?,
+                    bearing_table_id: cursor.read_u32::<LittleEndian>()?,
+                    modulation_frequency: cursor.read_u32::<LittleEndian>()?,
+                    percent_gain: cursor.read_u16::<LittleEndian>()?,
+                    chirp: cursor.read_u8()?,
+                    sonar_type: SonarType::from(cursor.read_u8()?),
+                }),
+            };
+
+            records.push(IndexedRecord { entry, time, device_id, image });
+        }
+
+        Ok(RecordIndex { records, entry, stamp })
+    }
+}
+
+impl GLF {
+    /// Build a GLF from its record index and the bytes of its .dat. Each
+    /// record is parsed where the index says it lies, so the .dat is never
+    /// scanned. With a stored .dat mapped by GlfArchive::dat_mapped, nothing
+    /// but the indexed records is read from the file.
+    ///
+    /// * `path` - the path to the GLF.
+    /// * `index` - its record index, which must be current.
+    /// * `dat` - the .dat entry the index names, from GlfArchive::dat or GlfArchive::dat_mapped.
+    pub fn with_record_index(path: &Path, index: &RecordIndex, dat: DatBuffer) -> Result<GLF, &'static str> {
+        if !index.is_current(path) {
+            return Err("Record index does not match the GLF.");
+        }
+
+        let mismatch = "Record index does not match the dat.";
+        let mut images = vec![];
+        let mut statuses = vec![];
+        let mut serials = vec![];
+        let mut bearing_tables = BearingTableSet::new();
+
+        for record in &index.records {
+            let entry = &record.entry;
+
+            let mut file_offset = entry.offset as i64;
+            let header = parse_header(&dat, &mut file_offset).map_err(|_| mismatch)?;
+
+            let end = entry.offset.checked_add(entry.length).filter(|end| *end <= dat.len()).ok_or(mismatch)?;
+
+            if header.header_type != entry.record_type {
+                return Err(mismatch);
+            }
+
+            let parsed = match header.header_type {
+                HeaderType::Image => {
+                    images.push(parse_image_record(&header, &dat, &mut file_offset, &mut bearing_tables)?);
+                    images.len() - 1
+                },
+                HeaderType::GeminiStatus => {
+                    statuses.push(parse_status_record(&header, &dat, &mut file_offset)?);
+                    statuses.len() - 1
+                },
+                HeaderType::RawSerial => {
+                    serials.push(parse_serial_record(&header, &dat, &mut file_offset)?);
+                    serials.len() - 1
+                },
+                _ => return Err(mismatch),
+            };
+
+            if parsed != entry.index || file_offset as usize != end {
+                return Err(mismatch);
+            }
+        }
+
+        Ok(GLF {
+            filepath: path.to_path_buf(),
+            entry_name: index.entry.clone(),
+            images,
+            statuses,
+            serials,
+            records: index.records.iter().map(|r| r.entry).collect(),
+            dat,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{build_dat, push_status, write_glf, TestImage, TestStatus};
+    use crate::{GlfArchive, IndexedDat};
+    use std::time::Duration;
+
+    #[test]
+    fn test_record_index() {
+        let mut dat = build_dat(&[TestImage::new(1, 1.0), TestImage::new(2, 1.5)]);
+        push_status(&mut dat, &TestStatus::new(1, 1.7));
+        let dir = std::env::temp_dir().join(format!("glf_recindex_{}", std::process::id()));
+        let cache = dir.join("cache");
+        let path = dir.join("test.glf");
+        fs::create_dir_all(&dir).unwrap();
+        write_glf(&path, &dat, zip::CompressionMethod::Deflated);
+
+        let glf = GLF::new(&path).unwrap();
+        let index = RecordIndex::open(&path, None).unwrap();
+        assert_eq!(index, RecordIndex::from_glf(&glf).unwrap());
+        assert_eq!(RecordIndex::load(&record_index_path(&path, None)).unwrap(), index);
+        assert_eq!(index.images().count(), 2);
+        assert_eq!(index.records[2].device_id, 1);
+        assert_eq!(index.records[1].time, glf.images[1].header.time);
+
+        // Read a frame with nothing but the sidecars.
+        let mut reader = IndexedDat::open(&path).unwrap();
+        let second = index.images().nth(1).unwrap();
+        assert_eq!(reader.read_image(&second.entry).unwrap(), glf.extract_image(1).unwrap());
+
+        let cached = RecordIndex::open(&path, Some(&cache)).unwrap();
+        assert!(record_index_path(&path, Some(&cache)).exists());
+        assert_eq!(cached, index);
+
+        // A GLF built from the index matches the parsed one.
+        let buffer = GlfArchive::open(&path).unwrap().dat("test.dat").unwrap();
+        let indexed = GLF::with_record_index(&path, &index, buffer.clone()).unwrap();
+        assert_eq!(indexed.records, glf.records);
+        assert_eq!(indexed.statuses[0].header.time, glf.statuses[0].header.time);
+        assert_eq!(indexed.extract_image(1).unwrap(), glf.extract_image(1).unwrap());
+
+        let mut shifted = index.clone();
+        shifted.records[1].entry.offset += 1;
+        assert!(GLF::with_record_index(&path, &shifted, buffer.clone()).is_err());
+        shifted.records[1].entry.offset -= 1;
+        shifted.records[1].entry.length = usize::MAX;
+        assert!(GLF::with_record_index(&path, &shifted, buffer.clone()).is_err());
+
+        // A flipped bit in the sidecar fails its CRC.
+        let sidecar = record_index_path(&path, None);
+        let mut bytes = fs::read(&sidecar).unwrap();
+        bytes[40] ^= 0x10;
+        fs::write(&sidecar, &bytes).unwrap();
+        assert_eq!(RecordIndex::load(&sidecar), Err("Record index is corrupt."));
+        assert!(RecordIndex::load(&sidecar.with_extension("missing")).is_err());
+        index.save(&sidecar).unwrap();
+
+        // Touching the GLF makes the sidecar stale, even at the same size.
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(1_000_000)).unwrap();
+        drop(file);
+        assert!(!index.is_current(&path));
+        assert!(GLF::with_record_index(&path, &index, buffer).is_err());
+        assert!(RecordIndex::open(&path, None).unwrap().is_current(&path));
+
+        // Rewriting the GLF makes the sidecar stale. A stored .dat is read
+        // by the IndexedDat straight from the file.
+        push_status(&mut dat, &TestStatus::new(2, 1.8));
+        write_glf(&path, &dat, zip::CompressionMethod::Stored);
+        assert!(!index.is_current(&path));
+        let index = RecordIndex::open(&path, None).unwrap();
+        assert_eq!(index.len(), 4);
+
+        let mut reader = IndexedDat::open(&path).unwrap();
+        assert!(reader.index().checkpoints.is_empty());
+        let last = &index.records[3].entry;
+        assert_eq!(reader.read_record(last).unwrap(), &dat[last.offset..last.offset + last.length]);
+        assert_eq!(reader.read_image(&index.records[0].entry).unwrap(), glf.extract_image(0).unwrap());
+        assert!(reader.read_at(dat.len() as u64, &mut [0u8; 1]).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,155 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # Segment
+//! Splits the image records of a GLF into runs that share the same sonar
+//! configuration, so each run can be processed with a single set of parameters.
+
+use crate::{ImageRecord, SonarType};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The sonar settings that must stay constant across a segment.
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SegmentSettings {
+    /// End of the range in metres.
+    pub range_end: u32,
+    /// Percentage gain.
+    pub percent_gain: u16,
+    /// CHIRP mode on?
+    pub chirp: u8,
+    /// Modulation frequency.
+    pub modulation_frequency: u32,
+    /// The bearing table shared by every frame in the segment.
+    pub bearing_table: Arc<[f64]>,
+    /// The type of the sonar.
+    pub sonar_type: SonarType,
+}
+
+impl SegmentSettings {
+    /// Take the settings from a single image record.
+    ///
+    /// * `img_rec` - the image record to read from.
+    pub fn from_record(img_rec: &ImageRecord) -> SegmentSettings {
+        SegmentSettings {
+            range_end: img_rec.range_end,
+            percent_gain: img_rec.percent_gain,
+            chirp: img_rec.chirp,
+            modulation_frequency: img_rec.modulation_frequency,
+            bearing_table: img_rec.bearing_table.clone(),
+            sonar_type: img_rec.sonar_type,
+        }
+    }
+
+    /// Does this image record share these settings?
+    ///
+    /// * `img_rec` - the image record to compare against.
+    pub fn matches(&self, img_rec: &ImageRecord) -> bool {
+        self.range_end == img_rec.range_end
+            && self.percent_gain == img_rec.percent_gain
+            && self.chirp == img_rec.chirp
+            && self.modulation_frequency == img_rec.modulation_frequency
+            && self.sonar_type == img_rec.sonar_type
+            && (Arc::ptr_eq(&self.bearing_table, &img_rec.bearing_table) || self.bearing_table == img_rec.bearing_table)
+    }
+}
+
+/// A run of frames from one device, all taken with the same settings.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Segment {
+    /// The device ID (the sonar id).
+    pub device_id: u16,
+    /// The time of the first frame in the segment.
+    pub start_time: DateTime<Utc>,
+    /// The time of the last frame in the segment.
+    pub end_time: DateTime<Utc>,
+    /// Indices into GLF::images, in time order.
+    pub frames: Vec<usize>,
+    /// The settings shared by all the frames.
+    pub settings: SegmentSettings,
+}
+
+impl Segment {
+    /// Return the number of frames in this segment.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Is this segment empty?
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+/// Split the image records into segments. A new segment starts for a device
+/// whenever any of its settings differ from the previous frame of that device.
+/// Segments are returned in the order of their first frame.
+///
+/// * `images` - the image records, in file order.
+pub fn segment_images(images: &[ImageRecord]) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = vec![];
+    let mut open: HashMap<u16, usize> = HashMap::new();
+
+    for (idx, img_rec) in images.iter().enumerate() {
+        let device_id = img_rec.header.device_id;
+
+        if let Some(&sidx) = open.get(&device_id) {
+            let segment = &mut segments[sidx];
+
+            if segment.settings.matches(img_rec) {
+                segment.frames.push(idx);
+                segment.end_time = img_rec.header.time;
+                continue;
+            }
+        }
+
+        open.insert(device_id, segments.len());
+        segments.push(Segment {
+            device_id,
+            start_time: img_rec.header.time,
+            end_time: img_rec.header.time,
+            frames: vec![idx],
+            settings: SegmentSettings::from_record(img_rec),
+        });
+    }
+
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glf::parse_dat;
+    use crate::testutil::{build_dat, TestImage};
+
+    #[test]
+    fn test_segments() {
+        let mut gain_change = TestImage::new(1, 3.0);
+        gain_change.percent_gain = 80;
+        let dat = build_dat(&[
+            TestImage::new(1, 1.0),
+            TestImage::new(2, 1.5),
+            TestImage::new(1, 2.0),
+            gain_change.clone(),
+            TestImage::new(2, 3.5),
+            TestImage::new(1, 4.0),
+        ]);
+        let images = parse_dat(&dat).unwrap().images;
+        let segments = segment_images(&images);
+
+        assert_eq!(segments.len(), 4);
+        assert_eq!(segments[0].device_id, 1);
+        assert_eq!(segments[0].frames, vec![0, 2]);
+        assert_eq!(segments[1].device_id, 2);
+        assert_eq!(segments[1].frames, vec![1, 4]);
+        assert_eq!(segments[2].frames, vec![3]);
+        assert_eq!(segments[2].settings.percent_gain, 80);
+        assert_eq!(segments[3].frames, vec![5]);
+        assert!(segments[0].start_time < segments[0].end_time);
+    }
+}
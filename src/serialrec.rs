@@ -0,0 +1,139 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # SerialRecord
+//! Raw serial data logged alongside the sonar, usually NMEA 0183 from a GPS.
+//! The payload is kept as it was logged, and any GGA or RMC sentences in it
+//! can be decoded into position fixes.
+
+use crate::CIHeader;
+
+/// A raw serial record.
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SerialRecord {
+    /// The CIHeader.
+    pub header: CIHeader,
+    /// The bytes received on the serial port.
+    pub data: Vec<u8>,
+}
+
+/// A position decoded from an NMEA sentence.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NmeaFix {
+    /// Latitude in degrees, north positive.
+    pub latitude: f64,
+    /// Longitude in degrees, east positive.
+    pub longitude: f64,
+    /// Altitude above mean sea level in metres, if the sentence has one.
+    pub altitude: Option<f64>,
+}
+
+/// Convert an NMEA `ddmm.mmmm` coordinate and hemisphere into degrees.
+///
+/// * `value` - the coordinate field.
+/// * `hemisphere` - N, S, E or W.
+fn nmea_degrees(value: &str, hemisphere: &str) -> Option<f64> {
+    let raw: f64 = value.parse().ok()?;
+    let degrees = (raw / 100.0).trunc();
+    let decimal = degrees + (raw - degrees * 100.0) / 60.0;
+
+    match hemisphere {
+        "N" | "E" => Some(decimal),
+        "S" | "W" => Some(-decimal),
+        _ => None,
+    }
+}
+
+/// Check the optional `*hh` checksum of a sentence, returning the body without it.
+///
+/// * `sentence` - the sentence without the leading `$`.
+fn nmea_body(sentence: &str) -> Option<&str> {
+    match sentence.split_once('*') {
+        Some((body, checksum)) => {
+            let expected = u8::from_str_radix(checksum.get(..2)?, 16).ok()?;
+            let actual = body.bytes().fold(0u8, |acc, b| acc ^ b);
+            if actual == expected { Some(body) } else { None }
+        },
+        None => Some(sentence),
+    }
+}
+
+/// Decode a single GGA or RMC sentence.
+///
+/// * `sentence` - the sentence, starting with `$`.
+pub fn parse_nmea(sentence: &str) -> Option<NmeaFix> {
+    let body = nmea_body(sentence.trim().strip_prefix('$')?)?;
+    let fields: Vec<&str> = body.split(',').collect();
+    let kind = fields.first()?.get(2..)?;
+
+    match kind {
+        // $xxGGA,time,lat,N,lon,E,quality,sats,hdop,alt,M,...
+        "GGA" if fields.len() > 9 && fields[6] != "0" => Some(NmeaFix {
+            latitude: nmea_degrees(fields[2], fields[3])?,
+            longitude: nmea_degrees(fields[4], fields[5])?,
+            altitude: fields[9].parse().ok(),
+        }),
+        // $xxRMC,time,status,lat,N,lon,E,...
+        "RMC" if fields.len() > 6 && fields[2] == "A" => Some(NmeaFix {
+            latitude: nmea_degrees(fields[3], fields[4])?,
+            longitude: nmea_degrees(fields[5], fields[6])?,
+            altitude: None,
+        }),
+        _ => None,
+    }
+}
+
+impl SerialRecord {
+    /// Return the position fixes in the NMEA sentences of this record.
+    pub fn nmea_fixes(&self) -> Vec<NmeaFix> {
+        String::from_utf8_lossy(&self.data)
+            .split(['\r', '\n'])
+            .filter_map(|line| line.find('$').and_then(|start| parse_nmea(&line[start..])))
+            .collect()
+    }
+}
+
+/// Extract a raw serial record, which is the whole payload of the CIHeader.
+///
+/// * `header` - the CI Header for this record.
+/// * `dat_buffer` - the bytes buffer we are reading from.
+/// * `file_offset` - the offset in the dat_buffer.
+pub fn parse_serial_record(header: &CIHeader, dat_buffer: &[u8], file_offset: &mut i64) -> Result<SerialRecord, &'static str> {
+    let start = *file_offset as usize;
+    let end = start + header.payload_length as usize;
+
+    if end > dat_buffer.len() {
+        return Err("Serial record runs past the end of the dat buffer.");
+    }
+
+    *file_offset = end as i64;
+
+    Ok(SerialRecord {
+        header: *header,
+        data: dat_buffer[start..end].to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_nmea() {
+        let fix = parse_nmea("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47").unwrap();
+        assert!((fix.latitude - 48.1173).abs() < 1e-4);
+        assert!((fix.longitude - 11.516_666).abs() < 1e-4);
+        assert_eq!(fix.altitude, Some(545.4));
+
+        let fix = parse_nmea("$GPRMC,123519,A,4807.038,S,01131.000,E,022.4,084.4,230394,003.1,W").unwrap();
+        assert!(fix.latitude < 0.0 && fix.longitude > 0.0);
+        assert_eq!(fix.altitude, None);
+
+        assert!(parse_nmea("$GPRMC,123519,V,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W").is_none());
+        assert!(parse_nmea("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*00").is_none());
+    }
+}
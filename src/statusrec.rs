@@ -8,12 +8,13 @@
 //! at the time an image was taken.
 
 use byteorder::{ByteOrder, LittleEndian};
-use crate::CIHeader;
+use crate::{CIHeader, LinkType, ShutdownStatus, StatusFlags};
 
 
 /// The Status Record. Holds information on the status of the sonar at this
 /// particular time.
-#[derive(Copy, Clone)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StatusRecord {
     /// The CIHeader.
     pub header: CIHeader,
@@ -22,7 +23,7 @@ pub struct StatusRecord {
     /// DA Version.
     pub da_version: u16,
     /// Flags.
-    pub flags: u16,
+    pub flags: StatusFlags,
     /// The Sonar ID.
     pub device_id: u16,
     /// XD Selected.
@@ -58,7 +59,7 @@ pub struct StatusRecord {
     /// AFE3 Bottom temperature.
     pub afe3_bot_temp: f64,
     /// Link type (see 0716-SDS-00001-005 (Genesis Log File Format).pdf).
-    pub link_type: u16,
+    pub link_type: LinkType,
     /// Uplink speed.
     pub uplink_speed: f64,
     /// Downlink spee.
@@ -79,13 +80,14 @@ pub struct StatusRecord {
     pub lost_line_count: u32,
     /// Packet count for all devices.
     pub general_count: u32,
-    /// Alternative IP Address.
+    /// Alternative IP Address. The octets are in network order when written out
+    /// as little endian bytes - see `sonar_alt_ipv4`.
     pub sonar_alt_ip: u32,
-    /// Currently connected surface PC IP Address.
+    /// Currently connected surface PC IP Address, stored as `sonar_alt_ip` - see `surface_ipv4`.
     pub surface_ip: u32,
-    /// The subnet mask.
+    /// The subnet mask, in network order.
     pub subnet_mask: [u8; 4],
-    /// Current MAC Address.
+    /// Current MAC Address, most significant byte first.
     pub mac_addr: [u8; 6],
     /// INTERNAL USAGE.
     pub boot_sts_register: u32,
@@ -95,27 +97,247 @@ pub struct StatusRecord {
     pub fpga_time: u64,
     /// INTERNAL USAGE.
     pub dip_switch: u16,
-    /// Shutdown reason (0 temperature, 1 out of water, 2 out of water indicator).
-    pub shutdown_status: u16,
+    /// Shutdown reasons (over temperature, out of water, out of water indicator).
+    pub shutdown_status: ShutdownStatus,
     /// Adaptor found?
     pub net_adap_found: bool,
-    // Not parsing subsea internal temp or subsea cpu temp for now. 
+    /// Subsea internal temperature, if the record has one.
+    pub subsea_internal_temp: Option<f64>,
+    /// Subsea CPU temperature, if the record has one.
+    pub subsea_cpu_temp: Option<f64>,
+    /// The layout this record was parsed with.
+    pub layout: StatusLayout,
+    /// Any bytes beyond the fields we know about, from newer firmware.
+    pub extra: Vec<u8>,
 }
 
+impl StatusRecord {
+    /// Read the record again with another layout, moving the subsea
+    /// temperatures to or from `extra`. Use this to override the layout
+    /// chosen by length, such as Legacy for firmware known to pad the record.
+    ///
+    /// * `layout` - the layout to read the record with.
+    pub fn with_layout(&self, layout: StatusLayout) -> Result<StatusRecord, &'static str> {
+        // The bytes after the Legacy fields, as they were in the dat buffer.
+        let mut tail: Vec<u8> = vec![];
 
-/// Extract the status record
+        if let (Some(internal), Some(cpu)) = (self.subsea_internal_temp, self.subsea_cpu_temp) {
+            tail.extend_from_slice(&internal.to_le_bytes());
+            tail.extend_from_slice(&cpu.to_le_bytes());
+        }
+
+        tail.extend_from_slice(&self.extra);
+        let layout = layout.check(STATUS_LEGACY_SIZE + tail.len() as u32)?;
+        let mut stat_rec = self.clone();
+        stat_rec.layout = layout;
+
+        match layout {
+            StatusLayout::Legacy => {
+                stat_rec.subsea_internal_temp = None;
+                stat_rec.subsea_cpu_temp = None;
+                stat_rec.extra = tail;
+            },
+            StatusLayout::Extended => {
+                stat_rec.subsea_internal_temp = Some(LittleEndian::read_f64(&tail[0..8]));
+                stat_rec.subsea_cpu_temp = Some(LittleEndian::read_f64(&tail[8..16]));
+                stat_rec.extra = tail[16..].to_vec();
+            },
+        }
+
+        Ok(stat_rec)
+    }
+}
+
+/// The size in bytes of a status record without the subsea temperatures.
+pub const STATUS_LEGACY_SIZE: u32 = 218;
+
+/// The size in bytes of a status record with the subsea temperatures.
+pub const STATUS_EXTENDED_SIZE: u32 = 234;
+
+/// The layouts of the status record we know how to parse.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StatusLayout {
+    /// Ends after the network adaptor flag.
+    Legacy,
+    /// Adds the subsea internal and CPU temperatures.
+    Extended,
+}
+
+impl StatusLayout {
+    /// Choose the layout for a record by its length. The bf/da versions are
+    /// firmware build numbers with no documented mapping onto the layout, so
+    /// records long enough to hold the subsea temperatures are read as
+    /// Extended, and shorter ones as Legacy. Firmware that pads a Legacy
+    /// record can be read back with `StatusRecord::with_layout` or
+    /// `GLF::set_status_layout`. Payloads too short for the Legacy layout
+    /// are rejected.
+    ///
+    /// * `payload_length` - the payload length from the CIHeader.
+    pub fn select(payload_length: u32) -> Result<StatusLayout, &'static str> {
+        if payload_length >= STATUS_EXTENDED_SIZE {
+            Ok(StatusLayout::Extended)
+        } else {
+            StatusLayout::Legacy.check(payload_length)
+        }
+    }
+
+    /// Check that a record is long enough to hold this layout.
+    ///
+    /// * `payload_length` - the payload length from the CIHeader.
+    pub fn check(self, payload_length: u32) -> Result<StatusLayout, &'static str> {
+        if payload_length >= self.size() {
+            Ok(self)
+        } else if self == StatusLayout::Extended && payload_length >= STATUS_LEGACY_SIZE {
+            Err("Status record too short for the extended layout.")
+        } else {
+            Err("Status record shorter than any known layout.")
+        }
+    }
+
+    /// The number of bytes of this layout.
+    pub fn size(self) -> u32 {
+        match self {
+            StatusLayout::Legacy => STATUS_LEGACY_SIZE,
+            StatusLayout::Extended => STATUS_EXTENDED_SIZE,
+        }
+    }
+}
+
+
+/// A borrowed view of a status record in the dat buffer, decoding fields
+/// when asked for. Use `to_record` for everything else.
+#[derive(Copy, Clone, Debug)]
+pub struct StatusRecordRef<'a> {
+    /// The CIHeader.
+    pub header: CIHeader,
+    /// The record, `payload_length` bytes from just after the CIHeader.
+    payload: &'a [u8],
+    /// The layout of the record.
+    layout: StatusLayout,
+}
+
+impl<'a> StatusRecordRef<'a> {
+    /// Check the length of a status record and return a view of it.
+    ///
+    /// * `header` - the CI Header for this record.
+    /// * `dat_buffer` - the bytes buffer we are reading from.
+    /// * `offset` - the offset of the record, just after the CIHeader.
+    pub fn parse(header: &CIHeader, dat_buffer: &'a [u8], offset: usize) -> Result<StatusRecordRef<'a>, &'static str> {
+        let payload = offset.checked_add(header.payload_length as usize)
+            .and_then(|end| dat_buffer.get(offset..end))
+            .ok_or("Status record runs past the end of the dat buffer.")?;
+        let layout = StatusLayout::select(header.payload_length)?;
+        Ok(StatusRecordRef { header: *header, payload, layout })
+    }
+
+    fn f64_at(&self, offset: usize) -> f64 {
+        LittleEndian::read_f64(&self.payload[offset..offset + 8])
+    }
+
+    /// BF Version.
+    pub fn bf_version(&self) -> u16 {
+        LittleEndian::read_u16(&self.payload[0..2])
+    }
+
+    /// DA Version.
+    pub fn da_version(&self) -> u16 {
+        LittleEndian::read_u16(&self.payload[2..4])
+    }
+
+    /// Flags.
+    pub fn flags(&self) -> StatusFlags {
+        StatusFlags::from_bits_retain(LittleEndian::read_u16(&self.payload[4..6]))
+    }
+
+    /// The Sonar ID.
+    pub fn device_id(&self) -> u16 {
+        LittleEndian::read_u16(&self.payload[6..8])
+    }
+
+    /// PSU Temperature.
+    pub fn psu_t(&self) -> f64 {
+        self.f64_at(42)
+    }
+
+    /// Die temperature.
+    pub fn die_t(&self) -> f64 {
+        self.f64_at(50)
+    }
+
+    /// Transmit temperature.
+    pub fn tx_t(&self) -> f64 {
+        self.f64_at(58)
+    }
+
+    /// Link quality as percentage.
+    pub fn link_quality(&self) -> u16 {
+        LittleEndian::read_u16(&self.payload[148..150])
+    }
+
+    /// Shutdown reasons.
+    pub fn shutdown_status(&self) -> ShutdownStatus {
+        ShutdownStatus::from_bits_retain(LittleEndian::read_u16(&self.payload[214..216]))
+    }
+
+    /// The layout of the record.
+    pub fn layout(&self) -> StatusLayout {
+        self.layout
+    }
+
+    /// Read the record with another layout, such as Extended for firmware
+    /// known to write the subsea temperatures.
+    ///
+    /// * `layout` - the layout to read the record with.
+    pub fn with_layout(self, layout: StatusLayout) -> Result<StatusRecordRef<'a>, &'static str> {
+        let layout = layout.check(self.payload.len() as u32)?;
+        Ok(StatusRecordRef { layout, ..self })
+    }
+
+    /// Size of the record after the CIHeader.
+    pub fn record_size(&self) -> u32 {
+        self.payload.len() as u32
+    }
+
+    /// Copy the record into an owned StatusRecord.
+    pub fn to_record(&self) -> StatusRecord {
+        // The view has already checked the length, so this can't fail.
+        parse_status_layout(&self.header, self.payload, &mut 0, self.layout).expect("status record view is valid")
+    }
+}
+
+/// Parse the dat file to obtain a status record. The record spans
+/// `header.payload_length` bytes and is read with the layout from
+/// `StatusLayout::select`; anything beyond the known fields is kept in `extra`.
+///
+/// * `header` - the CI Header for this record.
+/// * `dat_buffer` - the bytes buffer we are reading from.
+/// * `file_offset` - the offset in the dat_buffer.
+pub fn parse_status_record(header: &CIHeader, dat_buffer: &[u8], file_offset: &mut i64) -> Result<StatusRecord, &'static str> {
+    parse_status_layout(header, dat_buffer, file_offset, StatusLayout::select(header.payload_length)?)
+}
+
+/// Parse a status record with the given layout.
 ///
 /// * `header` - the CI Header for this record.
 /// * `dat_buffer` - the bytes buffer we are reading from.
 /// * `file_offset` - the offset in the dat_buffer.
-pub fn parse_status_record(header: &CIHeader, dat_buffer: &Vec<u8>, file_offset: &mut i64) -> StatusRecord {
-    //! Parse the dat file to obtain a status record
-    let mut fp: usize = *file_offset as usize;
+/// * `layout` - the layout to read the record with.
+fn parse_status_layout(header: &CIHeader, dat_buffer: &[u8], file_offset: &mut i64, layout: StatusLayout) -> Result<StatusRecord, &'static str> {
+    let start: usize = *file_offset as usize;
+    let end = start + header.payload_length as usize;
+
+    if end > dat_buffer.len() {
+        return Err("Status record runs past the end of the dat buffer.");
+    }
+
+    let layout = layout.check(header.payload_length)?;
+    let mut fp: usize = start;
     let bf_version = LittleEndian::read_u16(&dat_buffer[fp..(fp + 2)]);
     let da_version = LittleEndian::read_u16(&dat_buffer[(fp + 2)..(fp + 4)]);
     let flags = LittleEndian::read_u16(&dat_buffer[(fp + 4)..(fp + 6)]);
     let device_id = LittleEndian::read_u16(&dat_buffer[(fp + 6)..(fp + 8)]);
-    let xd_selected = dat_buffer[8];
+    let xd_selected = dat_buffer[fp + 8];
     fp = fp + 10;
 
     let vga_t1 = LittleEndian::read_f64(&dat_buffer[fp..(fp + 8)]);
@@ -163,16 +385,26 @@ pub fn parse_status_record(header: &CIHeader, dat_buffer: &Vec<u8>, file_offset:
     let fpga_time: u64 = LittleEndian::read_u64(&dat_buffer[(fp + 8)..(fp + 16)]);
     let dip_switch: u16 = LittleEndian::read_u16(&dat_buffer[(fp + 16)..(fp + 18)]);
     let shutdown_status: u16 = LittleEndian::read_u16(&dat_buffer[(fp + 18)..(fp + 20)]);
-    let net_adap_found: bool = dat_buffer[20] != 0;
+    let net_adap_found: bool = dat_buffer[fp + 20] != 0;
     fp = fp + 22; // Additional byte for some reason :/
 
-    let record_size = fp - *file_offset as usize;
+    let mut subsea_internal_temp: Option<f64> = None;
+    let mut subsea_cpu_temp: Option<f64> = None;
+
+    if layout == StatusLayout::Extended {
+        subsea_internal_temp = Some(LittleEndian::read_f64(&dat_buffer[fp..(fp + 8)]));
+        subsea_cpu_temp = Some(LittleEndian::read_f64(&dat_buffer[(fp + 8)..(fp + 16)]));
+        fp += 16;
+    }
+
+    let extra = dat_buffer[fp..end].to_vec();
+    let record_size = end - start;
 
     let stat_rec = StatusRecord {
         header: *header,
         bf_version: bf_version,
         da_version: da_version,
-        flags: flags,
+        flags: StatusFlags::from_bits_retain(flags),
         device_id: device_id,
         xd_selected: xd_selected,
         vga_t1: vga_t1,
@@ -190,7 +422,7 @@ pub fn parse_status_record(header: &CIHeader, dat_buffer: &Vec<u8>, file_offset:
         afe2_bot_temp: afe2_bot_temp,
         afe3_top_temp: afe3_top_temp,
         afe3_bot_temp: afe3_bot_temp,
-        link_type: link_type,
+        link_type: LinkType::from(link_type),
         uplink_speed: uplink_speed,
         downlink_speed: downlink_speed,
         link_quality: link_quality,
@@ -209,10 +441,98 @@ pub fn parse_status_record(header: &CIHeader, dat_buffer: &Vec<u8>, file_offset:
         boot_sts_register_da: boot_sts_register_da,
         fpga_time: fpga_time,
         dip_switch: dip_switch,
-        shutdown_status: shutdown_status,
+        shutdown_status: ShutdownStatus::from_bits_retain(shutdown_status),
         net_adap_found: net_adap_found,
+        subsea_internal_temp,
+        subsea_cpu_temp,
+        layout,
+        extra,
     };
 
     *file_offset = *file_offset + (record_size as i64);
-    stat_rec
+    Ok(stat_rec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ciheader::parse_header;
+    use crate::testutil::{glf_from_dat, push_status, TestStatus};
+
+    #[test]
+    fn test_select_layout() {
+        assert!(StatusLayout::select(STATUS_LEGACY_SIZE - 1).is_err());
+        assert_eq!(StatusLayout::select(STATUS_LEGACY_SIZE), Ok(StatusLayout::Legacy));
+        assert_eq!(StatusLayout::select(STATUS_EXTENDED_SIZE - 1), Ok(StatusLayout::Legacy));
+        assert_eq!(StatusLayout::select(STATUS_EXTENDED_SIZE), Ok(StatusLayout::Extended));
+        assert_eq!(StatusLayout::select(STATUS_EXTENDED_SIZE + 8), Ok(StatusLayout::Extended));
+        assert!(StatusLayout::Extended.check(STATUS_EXTENDED_SIZE - 1).is_err());
+        assert_eq!(StatusLayout::Extended.check(STATUS_EXTENDED_SIZE), Ok(StatusLayout::Extended));
+    }
+
+    #[test]
+    fn test_status_layouts() {
+        let mut dat: Vec<u8> = vec![];
+        let mut legacy = TestStatus::new(3, 1.0);
+        legacy.xd_selected = 2;
+        push_status(&mut dat, &legacy);
+        let mut extended = TestStatus::new(3, 2.0);
+        extended.subsea_temps = Some((21.5, 48.0));
+        extended.extra = vec![1, 2, 3];
+        push_status(&mut dat, &extended);
+
+        let mut offset: i64 = 0;
+        let header = parse_header(&dat, &mut offset).unwrap();
+        let first = parse_status_record(&header, &dat, &mut offset).unwrap();
+        assert_eq!(first.layout, StatusLayout::Legacy);
+        assert_eq!(first.xd_selected, 2);
+        assert!(first.net_adap_found);
+        assert_eq!(first.subsea_cpu_temp, None);
+        assert!(first.extra.is_empty());
+
+        // Records long enough for the temperatures are read as Extended.
+        let header = parse_header(&dat, &mut offset).unwrap();
+        let second = parse_status_record(&header, &dat, &mut offset).unwrap();
+        assert_eq!(second.layout, StatusLayout::Extended);
+        assert_eq!(second.device_id, 3);
+        assert_eq!(second.subsea_internal_temp, Some(21.5));
+        assert_eq!(second.subsea_cpu_temp, Some(48.0));
+        assert_eq!(second.extra, vec![1, 2, 3]);
+
+        // Legacy can still be asked for, moving the temperatures to extra.
+        let padded = second.with_layout(StatusLayout::Legacy).unwrap();
+        assert_eq!(padded.subsea_internal_temp, None);
+        assert_eq!(padded.extra.len(), 19);
+        assert_eq!(padded.with_layout(StatusLayout::Extended).unwrap().subsea_cpu_temp, Some(48.0));
+        assert!(first.with_layout(StatusLayout::Extended).is_err());
+
+        let view = StatusRecordRef::parse(&header, &dat, dat.len() - 237).unwrap();
+        assert_eq!(view.layout(), StatusLayout::Extended);
+        assert_eq!(view.to_record().subsea_cpu_temp, Some(48.0));
+        assert_eq!(view.with_layout(StatusLayout::Legacy).unwrap().to_record().subsea_cpu_temp, None);
+        assert_eq!(offset as usize, dat.len());
+    }
+
+    #[test]
+    fn test_glf_extended_status() {
+        let mut dat: Vec<u8> = vec![];
+        let mut extended = TestStatus::new(4, 1.0);
+        extended.subsea_temps = Some((19.0, 55.5));
+        push_status(&mut dat, &extended);
+        push_status(&mut dat, &TestStatus::new(4, 2.0));
+
+        // A 234 byte record gets its temperatures with no opt in.
+        let mut glf = glf_from_dat(dat);
+        assert_eq!(glf.records[0].length, 21 + STATUS_EXTENDED_SIZE as usize);
+        assert_eq!(glf.statuses[0].layout, StatusLayout::Extended);
+        assert_eq!(glf.statuses[0].subsea_internal_temp, Some(19.0));
+        assert_eq!(glf.statuses[0].subsea_cpu_temp, Some(55.5));
+        assert_eq!(glf.statuses[1].layout, StatusLayout::Legacy);
+
+        // Forcing a layout on every record fails if any is too short.
+        assert!(glf.set_status_layout(StatusLayout::Extended).is_err());
+        glf.set_status_layout(StatusLayout::Legacy).unwrap();
+        assert_eq!(glf.statuses[0].subsea_cpu_temp, None);
+        assert_eq!(glf.statuses[0].extra.len(), 16);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,369 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # Telemetry
+//! Flattens the StatusRecords into one time series per device, with a column
+//! per field, ready for charting. Counter fields are turned into per-second
+//! rates. Series can be written as CSV, or as Apache Parquet with the
+//! `parquet` feature.
+//!
+//! The addresses - `sonar_alt_ip`, `surface_ip`, `subnet_mask` and
+//! `mac_addr` - are left out. They are identifiers rather than measurements,
+//! and don't survive being charted as a number; `GLF::network_summary`
+//! reports the distinct addresses seen for each device. `fpga_time` is kept,
+//! but as an f64 it is only exact up to 2^53 ticks.
+
+use crate::StatusRecord;
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::io::Write;
+
+/// How a column was derived from the status records.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ColumnKind {
+    /// The value as recorded.
+    Gauge,
+    /// The change in a counter per second since the previous record. The
+    /// first record, and any record where the counter was reset, is NaN.
+    Rate,
+}
+
+/// A single named column of a telemetry series.
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TelemetryColumn {
+    /// The column name, matching the StatusRecord field. Rates get a `_per_s` suffix.
+    pub name: String,
+    /// How the values were derived.
+    pub kind: ColumnKind,
+    /// One value per timestamp.
+    pub values: Vec<f64>,
+}
+
+/// The status telemetry for one device.
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TelemetrySeries {
+    /// The device ID (the sonar id).
+    pub device_id: u16,
+    /// The time of each row, from the CIHeader.
+    pub times: Vec<DateTime<Utc>>,
+    /// The columns, all the same length as `times`.
+    pub columns: Vec<TelemetryColumn>,
+}
+
+type Field = (&'static str, fn(&StatusRecord) -> f64);
+
+/// The fields recorded as they are.
+const GAUGES: &[Field] = &[
+    ("bf_version", |s| s.bf_version as f64),
+    ("da_version", |s| s.da_version as f64),
+    ("flags", |s| s.flags.bits() as f64),
+    ("xd_selected", |s| s.xd_selected as f64),
+    ("vga_t1", |s| s.vga_t1),
+    ("vga_t2", |s| s.vga_t2),
+    ("vga_t3", |s| s.vga_t3),
+    ("vga_t4", |s| s.vga_t4),
+    ("psu_t", |s| s.psu_t),
+    ("die_t", |s| s.die_t),
+    ("tx_t", |s| s.tx_t),
+    ("afe0_top_temp", |s| s.afe0_top_temp),
+    ("afe0_bot_temp", |s| s.afe0_bot_temp),
+    ("afe1_top_temp", |s| s.afe1_top_temp),
+    ("afe1_bot_temp", |s| s.afe1_bot_temp),
+    ("afe2_top_temp", |s| s.afe2_top_temp),
+    ("afe2_bot_temp", |s| s.afe2_bot_temp),
+    ("afe3_top_temp", |s| s.afe3_top_temp),
+    ("afe3_bot_temp", |s| s.afe3_bot_temp),
+    ("link_type", |s| u16::from(s.link_type) as f64),
+    ("uplink_speed", |s| s.uplink_speed),
+    ("downlink_speed", |s| s.downlink_speed),
+    ("link_quality", |s| s.link_quality as f64),
+    ("boot_sts_register", |s| s.boot_sts_register as f64),
+    ("boot_sts_register_da", |s| s.boot_sts_register_da as f64),
+    ("fpga_time", |s| s.fpga_time as f64),
+    ("dip_switch", |s| s.dip_switch as f64),
+    ("shutdown_status", |s| s.shutdown_status.bits() as f64),
+    ("net_adap_found", |s| if s.net_adap_found { 1.0 } else { 0.0 }),
+    ("subsea_internal_temp", |s| s.subsea_internal_temp.unwrap_or(f64::NAN)),
+    ("subsea_cpu_temp", |s| s.subsea_cpu_temp.unwrap_or(f64::NAN)),
+];
+
+/// The counter fields, exported as rates.
+const COUNTERS: &[Field] = &[
+    ("packet_count", |s| s.packet_count as f64),
+    ("recv_error", |s| s.recv_error as f64),
+    ("resent_packet_count", |s| s.resent_packet_count as f64),
+    ("dropped_packet_count", |s| s.dropped_packet_count as f64),
+    ("unknown_packet_count", |s| s.unknown_packet_count as f64),
+    ("lost_line_count", |s| s.lost_line_count as f64),
+    ("general_count", |s| s.general_count as f64),
+];
+
+impl TelemetrySeries {
+    /// Build the series for a single device's records, in time order.
+    ///
+    /// * `device_id` - the device these records belong to.
+    /// * `records` - the status records of that device.
+    fn from_records(device_id: u16, records: &[&StatusRecord]) -> TelemetrySeries {
+        let times: Vec<DateTime<Utc>> = records.iter().map(|s| s.header.time).collect();
+        let mut columns: Vec<TelemetryColumn> = vec![];
+
+        for (name, get) in GAUGES {
+            columns.push(TelemetryColumn {
+                name: name.to_string(),
+                kind: ColumnKind::Gauge,
+                values: records.iter().map(|s| get(s)).collect(),
+            });
+        }
+
+        for (name, get) in COUNTERS {
+            let mut values: Vec<f64> = vec![f64::NAN; records.len()];
+
+            for i in 1..records.len() {
+                let dt = (times[i] - times[i - 1]).num_microseconds().unwrap_or(0) as f64 / 1e6;
+                let dv = get(records[i]) - get(records[i - 1]);
+
+                if dt > 0.0 && dv >= 0.0 {
+                    values[i] = dv / dt;
+                }
+            }
+
+            columns.push(TelemetryColumn {
+                name: format!("{}_per_s", name),
+                kind: ColumnKind::Rate,
+                values,
+            });
+        }
+
+        TelemetrySeries { device_id, times, columns }
+    }
+
+    /// Return the number of rows in this series.
+    pub fn len(&self) -> usize {
+        self.times.len()
+    }
+
+    /// Is this series empty?
+    pub fn is_empty(&self) -> bool {
+        self.times.is_empty()
+    }
+
+    /// Find a column by name.
+    ///
+    /// * `name` - the column name.
+    pub fn column(&self, name: &str) -> Option<&TelemetryColumn> {
+        self.columns.iter().find(|c| c.name == name)
+    }
+
+    /// Write the series as CSV, with a header row. The first columns are the
+    /// RFC3339 time, the seconds since the Unix epoch and the device id. NaN
+    /// values are left empty.
+    ///
+    /// * `writer` - where to write the CSV.
+    pub fn write_csv<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        write!(writer, "time,timestamp,device_id")?;
+
+        for column in &self.columns {
+            write!(writer, ",{}", column.name)?;
+        }
+
+        writeln!(writer)?;
+
+        for (row, time) in self.times.iter().enumerate() {
+            let timestamp = time.timestamp_micros() as f64 / 1e6;
+            write!(writer, "{},{},{}", time.to_rfc3339_opts(SecondsFormat::Millis, true), timestamp, self.device_id)?;
+
+            for column in &self.columns {
+                let value = column.values[row];
+
+                if value.is_nan() {
+                    write!(writer, ",")?;
+                } else {
+                    write!(writer, ",{}", value)?;
+                }
+            }
+
+            writeln!(writer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the series as an Apache Parquet file. The time is stored as a UTC
+    /// timestamp in microseconds, followed by the device id and an optional
+    /// double column for each field, with NaN values written as nulls.
+    ///
+    /// * `writer` - where to write the Parquet file.
+    #[cfg(feature = "parquet")]
+    pub fn write_parquet<W: Write + Send>(&self, writer: W) -> Result<(), &'static str> {
+        use parquet::data_type::{DoubleType, Int32Type, Int64Type};
+        use parquet::file::properties::WriterProperties;
+        use parquet::file::writer::SerializedFileWriter;
+        use parquet::schema::parser::parse_message_type;
+        use std::sync::Arc;
+
+        let mut message = String::from("message telemetry { REQUIRED INT64 time (TIMESTAMP(MICROS,true)); REQUIRED INT32 device_id (INTEGER(16,false));");
+
+        for column in &self.columns {
+            message.push_str(&format!(" OPTIONAL DOUBLE {};", column.name));
+        }
+
+        message.push_str(" }");
+        let schema = Arc::new(parse_message_type(&message).map_err(|_| "Failed to build Parquet schema.")?);
+        let props = Arc::new(WriterProperties::builder().build());
+        let mut file_writer = SerializedFileWriter::new(writer, schema, props).map_err(|_| "Failed to create Parquet writer.")?;
+        let mut row_group = file_writer.next_row_group().map_err(|_| "Failed to create Parquet row group.")?;
+
+        let times: Vec<i64> = self.times.iter().map(|t| t.timestamp_micros()).collect();
+        let devices: Vec<i32> = vec![self.device_id as i32; self.times.len()];
+        let mut idx: usize = 0;
+
+        while let Some(mut column_writer) = row_group.next_column().map_err(|_| "Failed to write Parquet column.")? {
+            let written = match idx {
+                0 => column_writer.typed::<Int64Type>().write_batch(&times, None, None),
+                1 => column_writer.typed::<Int32Type>().write_batch(&devices, None, None),
+                _ => {
+                    // A definition level of 0 marks a null, and nulls have no value.
+                    let column = &self.columns[idx - 2].values;
+                    let levels: Vec<i16> = column.iter().map(|v| if v.is_nan() { 0 } else { 1 }).collect();
+                    let values: Vec<f64> = column.iter().copied().filter(|v| !v.is_nan()).collect();
+                    column_writer.typed::<DoubleType>().write_batch(&values, Some(&levels), None)
+                },
+            };
+
+            written.map_err(|_| "Failed to write Parquet column.")?;
+            column_writer.close().map_err(|_| "Failed to write Parquet column.")?;
+            idx += 1;
+        }
+
+        row_group.close().map_err(|_| "Failed to close Parquet row group.")?;
+        file_writer.close().map_err(|_| "Failed to close Parquet file.")?;
+        Ok(())
+    }
+}
+
+/// Split the status records into one telemetry series per device, ordered by device id.
+///
+/// * `statuses` - the status records, in time order.
+pub fn telemetry_series(statuses: &[StatusRecord]) -> Vec<TelemetrySeries> {
+    let mut devices: Vec<u16> = statuses.iter().map(|s| s.device_id).collect();
+    devices.sort();
+    devices.dedup();
+
+    devices.into_iter().map(|device_id| {
+        let records: Vec<&StatusRecord> = statuses.iter().filter(|s| s.device_id == device_id).collect();
+        TelemetrySeries::from_records(device_id, &records)
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{glf_from_dat, push_status, TestStatus};
+
+    /// Two devices, with device 1 seeing a counter reset and a repeated timestamp.
+    fn statuses() -> Vec<StatusRecord> {
+        let mut dat: Vec<u8> = vec![];
+
+        for (time, recv_error, dropped) in [(1.0, 10, 0), (2.0, 30, 5), (2.5, 4, 6), (2.5, 8, 6)] {
+            let mut stat = TestStatus::new(1, time);
+            stat.recv_error = recv_error;
+            stat.dropped_packet_count = dropped;
+            push_status(&mut dat, &stat);
+        }
+
+        let mut stat = TestStatus::new(0, 1.5);
+        stat.temperature = 40.0;
+        push_status(&mut dat, &stat);
+        glf_from_dat(dat).statuses
+    }
+
+    #[test]
+    fn test_telemetry_series() {
+        let series = telemetry_series(&statuses());
+        assert_eq!(series.iter().map(|s| s.device_id).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!((series[0].len(), series[1].len()), (1, 4));
+        assert_eq!(series[1].columns.len(), GAUGES.len() + COUNTERS.len());
+        assert_eq!(series[0].column("psu_t").unwrap().values, vec![40.0]);
+        assert_eq!(series[1].column("link_quality").unwrap().kind, ColumnKind::Gauge);
+        assert!(series[1].column("subsea_cpu_temp").unwrap().values.iter().all(|v| v.is_nan()));
+        assert!(series[1].column("recv_error").is_none());
+        assert!(series[1].column("surface_ip").is_none());
+
+        let statuses = statuses();
+        let fpga_time = series[1].column("fpga_time").unwrap();
+        assert_eq!(fpga_time.values[0], statuses[0].fpga_time as f64);
+
+        let recv = series[1].column("recv_error_per_s").unwrap();
+        assert_eq!(recv.kind, ColumnKind::Rate);
+        // No rate for the first row, the counter reset, or a zero time step.
+        assert!(recv.values[0].is_nan());
+        assert_eq!(recv.values[1], 20.0);
+        assert!(recv.values[2].is_nan());
+        assert!(recv.values[3].is_nan());
+        assert_eq!(series[1].column("dropped_packet_count_per_s").unwrap().values[2], 2.0);
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let series = telemetry_series(&statuses());
+        let mut out: Vec<u8> = vec![];
+        series[1].write_csv(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 5);
+
+        let header: Vec<&str> = lines[0].split(',').collect();
+        assert_eq!(&header[..4], &["time", "timestamp", "device_id", "bf_version"]);
+        assert_eq!(header.len(), 3 + series[1].columns.len());
+
+        let row: Vec<&str> = lines[2].split(',').collect();
+        assert_eq!(row.len(), header.len());
+        assert_eq!(row[0], series[1].times[1].to_rfc3339_opts(SecondsFormat::Millis, true));
+        assert_eq!(row[2], "1");
+        let recv = header.iter().position(|h| *h == "recv_error_per_s").unwrap();
+        assert_eq!(row[recv], "20");
+        let subsea = header.iter().position(|h| *h == "subsea_cpu_temp").unwrap();
+        assert_eq!(row[subsea], "");
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_write_parquet() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+        use parquet::record::RowAccessor;
+        use parquet::schema::types::Type;
+
+        let series = telemetry_series(&statuses());
+        let path = std::env::temp_dir().join(format!("glf_telemetry_{}.parquet", std::process::id()));
+        series[1].write_parquet(std::fs::File::create(&path).unwrap()).unwrap();
+
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let metadata = reader.metadata().file_metadata();
+        assert_eq!(metadata.num_rows(), 4);
+        let fields = metadata.schema().get_fields();
+        assert_eq!(fields.len(), 2 + series[1].columns.len());
+        assert_eq!(fields[0].name(), "time");
+        assert!(matches!(fields[2].as_ref(), Type::PrimitiveType { .. }));
+        assert!(fields[2].is_optional());
+
+        let recv = fields.iter().position(|f| f.name() == "recv_error_per_s").unwrap();
+        let rows: Vec<_> = reader.get_row_iter(None).unwrap().map(|r| r.unwrap()).collect();
+        assert_eq!(rows.len(), 4);
+
+        for (i, row) in rows.iter().enumerate() {
+            assert_eq!(row.get_timestamp_micros(0).unwrap(), series[1].times[i].timestamp_micros());
+            assert_eq!(row.get_ushort(1).unwrap(), 1);
+
+            match series[1].columns[recv - 2].values[i] {
+                v if v.is_nan() => assert!(row.get_double(recv).is_err()),
+                v => assert_eq!(row.get_double(recv).unwrap(), v),
+            }
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,240 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # TestUtil
+//! Builds small, synthetic dat buffers so the tests don't need the
+//! pytritech_testdata submodule.
+
+use byteorder::{ByteOrder, LittleEndian};
+
+/// The parameters of a synthetic image record.
+#[derive(Clone)]
+pub struct TestImage {
+    pub device_id: u16,
+    pub time: f64,
+    pub range_start: u32,
+    pub range_end: u32,
+    pub percent_gain: u16,
+    pub chirp: u8,
+    pub modulation_frequency: u32,
+    pub bearings: u32,
+    pub sonar_type: u8,
+    pub ping_flags: u16,
+    pub zlib: bool,
+    pub h264: bool,
+}
+
+impl TestImage {
+    pub fn new(device_id: u16, time: f64) -> TestImage {
+        TestImage {
+            device_id,
+            time,
+            range_start: 0,
+            range_end: 8,
+            percent_gain: 50,
+            chirp: 0,
+            modulation_frequency: 720_000,
+            bearings: 4,
+            sonar_type: 0,
+            ping_flags: 0,
+            zlib: false,
+            h264: false,
+        }
+    }
+}
+
+fn push_u16(buf: &mut Vec<u8>, v: u16) {
+    let mut b = [0u8; 2];
+    LittleEndian::write_u16(&mut b, v);
+    buf.extend_from_slice(&b);
+}
+
+fn push_u32(buf: &mut Vec<u8>, v: u32) {
+    let mut b = [0u8; 4];
+    LittleEndian::write_u32(&mut b, v);
+    buf.extend_from_slice(&b);
+}
+
+fn push_f32(buf: &mut Vec<u8>, v: f32) {
+    let mut b = [0u8; 4];
+    LittleEndian::write_f32(&mut b, v);
+    buf.extend_from_slice(&b);
+}
+
+fn push_f64(buf: &mut Vec<u8>, v: f64) {
+    let mut b = [0u8; 8];
+    LittleEndian::write_f64(&mut b, v);
+    buf.extend_from_slice(&b);
+}
+
+/// Append a 21 byte CIHeader followed by the payload.
+pub fn push_record(buf: &mut Vec<u8>, header_type: u8, device_id: u16, time: f64, payload: &[u8]) {
+    buf.push(b'*');
+    buf.push(0);
+    push_u32(buf, payload.len() as u32 + 21);
+    push_f64(buf, time);
+    buf.push(header_type);
+    push_u16(buf, device_id);
+    push_u16(buf, 0);
+    push_u16(buf, 0);
+    buf.extend_from_slice(payload);
+}
+
+/// Append an image record, with pixel values derived from the frame time.
+pub fn push_image(buf: &mut Vec<u8>, img: &TestImage) {
+    let mut p: Vec<u8> = vec![];
+    push_u16(&mut p, 1);
+    push_u16(&mut p, 0xEFEF);
+    push_u16(&mut p, 3);
+    push_u32(&mut p, img.range_start);
+    push_u32(&mut p, img.range_end);
+    push_u16(&mut p, 0);
+    push_u32(&mut p, 0);
+    push_u32(&mut p, img.bearings);
+    let pixels: Vec<u8> = (0..img.bearings * (img.range_end - img.range_start)).map(|i| (i as u8).wrapping_add(img.time as u8)).collect();
+    let data = if img.zlib { miniz_oxide::deflate::compress_to_vec_zlib(&pixels, 6) } else { pixels };
+    push_u16(&mut p, if img.h264 { 2 } else if img.zlib { 0 } else { 1 });
+    push_u32(&mut p, data.len() as u32);
+    p.extend_from_slice(&data);
+
+    for i in 0..img.bearings {
+        push_f64(&mut p, -0.5 + i as f64 / img.bearings as f64);
+    }
+
+    push_u32(&mut p, 0);
+    push_u32(&mut p, img.modulation_frequency);
+    push_f32(&mut p, 0.0);
+    push_f64(&mut p, img.time);
+    push_u16(&mut p, img.ping_flags);
+    push_f32(&mut p, 1500.0);
+    push_u16(&mut p, img.percent_gain);
+    p.push(img.chirp);
+    p.push(img.sonar_type);
+    p.push(0);
+    p.push(0);
+    push_u16(&mut p, 0xDEDE);
+    push_record(buf, 0, img.device_id, img.time, &p);
+}
+
+/// Build a dat buffer holding the given images, in order.
+pub fn build_dat(images: &[TestImage]) -> Vec<u8> {
+    let mut buf: Vec<u8> = vec![];
+
+    for img in images {
+        push_image(&mut buf, img);
+    }
+
+    buf
+}
+
+/// The parameters of a synthetic status record.
+#[derive(Clone)]
+pub struct TestStatus {
+    pub device_id: u16,
+    pub time: f64,
+    pub xd_selected: u8,
+    pub temperature: f64,
+    pub link_quality: u16,
+    pub recv_error: u32,
+    pub dropped_packet_count: u32,
+    pub shutdown_status: u16,
+    pub subsea_temps: Option<(f64, f64)>,
+    pub extra: Vec<u8>,
+}
+
+impl TestStatus {
+    pub fn new(device_id: u16, time: f64) -> TestStatus {
+        TestStatus {
+            device_id,
+            time,
+            xd_selected: 0,
+            temperature: 30.0,
+            link_quality: 100,
+            recv_error: 0,
+            dropped_packet_count: 0,
+            shutdown_status: 0,
+            subsea_temps: None,
+            extra: vec![],
+        }
+    }
+}
+
+/// Append a status record, using the legacy layout unless subsea temperatures are given.
+pub fn push_status(buf: &mut Vec<u8>, stat: &TestStatus) {
+    let mut p: Vec<u8> = vec![];
+    push_u16(&mut p, 1);
+    push_u16(&mut p, 1);
+    push_u16(&mut p, 0);
+    push_u16(&mut p, stat.device_id);
+    p.push(stat.xd_selected);
+    p.push(0);
+
+    // VGA, PSU, die, TX and AFE temperatures.
+    for _ in 0..15 {
+        push_f64(&mut p, stat.temperature);
+    }
+
+    push_u16(&mut p, 0);
+    push_f64(&mut p, 100.0);
+    push_f64(&mut p, 100.0);
+    push_u16(&mut p, stat.link_quality);
+    push_u32(&mut p, 1000);
+    push_u32(&mut p, stat.recv_error);
+    push_u32(&mut p, 0);
+    push_u32(&mut p, stat.dropped_packet_count);
+    push_u32(&mut p, 0);
+    push_u32(&mut p, 0);
+    push_u32(&mut p, 0);
+    p.extend_from_slice(&[192, 168, 2, 201]);
+    p.extend_from_slice(&[192, 168, 2, 10]);
+    p.extend_from_slice(&[255, 255, 255, 0]);
+    p.extend_from_slice(&[0, 1, 2, 3, 4, 5]);
+    push_u32(&mut p, 0);
+    push_u32(&mut p, 0);
+    push_f64(&mut p, 0.0);
+    push_u16(&mut p, 0);
+    push_u16(&mut p, stat.shutdown_status);
+    p.push(1);
+    p.push(0);
+
+    if let Some((internal, cpu)) = stat.subsea_temps {
+        push_f64(&mut p, internal);
+        push_f64(&mut p, cpu);
+    }
+
+    p.extend_from_slice(&stat.extra);
+    push_record(buf, 3, stat.device_id, stat.time, &p);
+}
+
+/// Parse a dat buffer into a GLF, as if it had been read from a file.
+pub fn glf_from_dat(dat: Vec<u8>) -> crate::GLF {
+    let parsed = crate::glf::parse_dat(&dat).unwrap();
+    crate::GLF {
+        filepath: std::path::PathBuf::from("test.glf"),
+        entry_name: "test.dat".to_string(),
+        images: parsed.images,
+        statuses: parsed.statuses,
+        serials: parsed.serials,
+        records: parsed.records,
+        dat: dat.into(),
+    }
+}
+
+/// Write a dat buffer to disk as a GLF, compressing the .dat entry with `method`.
+pub fn write_glf(path: &std::path::Path, dat: &[u8], method: zip::CompressionMethod) {
+    write_glf_with(path, dat, zip::write::FileOptions::default().compression_method(method));
+}
+
+/// Write a dat buffer to disk as a GLF, with the given zip options for each entry.
+pub fn write_glf_with(path: &std::path::Path, dat: &[u8], options: zip::write::FileOptions) {
+    use std::io::Write;
+
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+    zip.start_file("test.cfg", options).unwrap();
+    zip.write_all(b"<config/>").unwrap();
+    zip.start_file("test.dat", options).unwrap();
+    zip.write_all(dat).unwrap();
+    zip.finish().unwrap();
+}
@@ -0,0 +1,490 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # Validate
+//! Integrity checks for GLF files, for use before archiving field data.
+//! Unlike GLF::new, which stops with an error at the first problem, the
+//! Validator walks every .dat in the file with bounds checked reads and
+//! reports every problem it finds:
+//!
+//! * the zip structure and the CRC of every entry.
+//! * the `*` magic and length of each CIHeader.
+//! * the `rtype`, `version` and `0xDEDE` end tag of image records, and that
+//!   the record fills exactly the CIHeader's `payload_length`.
+//! * that image payloads decompress to `image_width * image_height` bytes.
+//! * that the timestamps of each device never go backwards.
+
+use crate::archive::has_extension;
+use crate::ciheader::parse_header;
+use crate::imagerec::{IMAGE_END_TAG, IMAGE_MAGIC, IMAGE_NEGATIVE};
+use crate::{CompressionType, HeaderType, ImageRecordRef, StatusLayout};
+use byteorder::{ByteOrder, LittleEndian};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use miniz_oxide::inflate::{decompress_to_vec_zlib_with_limit, TINFLStatus};
+
+const HEADER_SIZE: usize = 21;
+
+/// How serious an issue is. Any error fails validation.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Severity {
+    /// Readable, but worth knowing about.
+    Warning,
+    /// The file is damaged or can't be read.
+    Error,
+}
+
+/// What kind of problem was found.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum IssueKind {
+    /// The file can't be opened.
+    Open,
+    /// The file isn't a zip archive.
+    Zip,
+    /// A zip entry fails to read or its CRC doesn't match.
+    ZipEntry,
+    /// There is no .dat entry in the archive.
+    NoDat,
+    /// A CIHeader doesn't start with `*`.
+    HeaderMagic,
+    /// A record runs past the end of the dat.
+    Truncated,
+    /// The record doesn't fill the CIHeader's payload_length.
+    LengthMismatch,
+    /// The rtype or version of an image record is wrong.
+    ImageMagic,
+    /// The end tag of an image record isn't 0xDEDE.
+    EndTag,
+    /// An image payload fails to decompress.
+    Decompress,
+    /// An image payload isn't `image_width * image_height` bytes.
+    ImageSize,
+    /// A status record is too short for any known layout.
+    StatusLayout,
+    /// A record type this crate can't parse.
+    UnsupportedRecord,
+    /// A record type byte that isn't known at all.
+    UnknownRecord,
+    /// A timestamp earlier than the previous one from the same device.
+    TimeOrder,
+}
+
+/// A single problem found by the Validator.
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ValidationIssue {
+    /// How serious the problem is.
+    pub severity: Severity,
+    /// What kind of problem it is.
+    pub kind: IssueKind,
+    /// The .dat entry the problem is in, or the damaged zip entry.
+    pub entry: Option<String>,
+    /// The number of the record in its .dat, if the problem is in a record.
+    pub record: Option<usize>,
+    /// The offset into the dat, if the problem is in a record.
+    pub offset: Option<usize>,
+    /// A description of the problem.
+    pub message: String,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+
+        write!(f, "{}: ", severity)?;
+
+        if let Some(entry) = &self.entry {
+            write!(f, "{} ", entry)?;
+        }
+
+        if let (Some(record), Some(offset)) = (self.record, self.offset) {
+            write!(f, "record {} @{}: ", record, offset)?;
+        }
+
+        write!(f, "{}", self.message)
+    }
+}
+
+/// The result of validating one GLF.
+#[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ValidationReport {
+    /// The file that was checked, if it came from disk.
+    pub path: Option<PathBuf>,
+    /// The number of records walked, over every .dat.
+    pub records: usize,
+    /// The number of image records.
+    pub images: usize,
+    /// The number of images whose payload was decompressed and checked.
+    pub images_checked: usize,
+    /// Everything that was found, in file order.
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    /// True if there are no errors. Warnings don't fail validation.
+    pub fn is_valid(&self) -> bool {
+        !self.issues.iter().any(|i| i.severity == Severity::Error)
+    }
+
+    /// The number of issues with the given severity.
+    ///
+    /// * `severity` - the severity to count.
+    pub fn count(&self, severity: Severity) -> usize {
+        self.issues.iter().filter(|i| i.severity == severity).count()
+    }
+
+    fn push(&mut self, severity: Severity, kind: IssueKind, location: Option<(usize, usize)>, message: String) {
+        self.issues.push(ValidationIssue {
+            severity,
+            kind,
+            entry: None,
+            record: location.map(|l| l.0),
+            offset: location.map(|l| l.1),
+            message,
+        });
+    }
+}
+
+/// Checks GLF files for damage.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Validator {
+    /// Decompress every image payload and check its size. This is the slow part.
+    pub check_images: bool,
+    /// Stop after this many errors, as a damaged file can produce one per record.
+    pub max_errors: usize,
+}
+
+impl Default for Validator {
+    fn default() -> Self {
+        Validator {
+            check_images: true,
+            max_errors: 100,
+        }
+    }
+}
+
+/// Classify an error from ImageRecordRef::parse.
+///
+/// * `error` - the error it returned.
+fn image_issue(error: &'static str) -> IssueKind {
+    match error {
+        IMAGE_MAGIC | IMAGE_NEGATIVE => IssueKind::ImageMagic,
+        IMAGE_END_TAG => IssueKind::EndTag,
+        _ => IssueKind::Truncated,
+    }
+}
+
+impl Validator {
+    /// Create a validator that checks everything.
+    pub fn new() -> Validator {
+        Validator::default()
+    }
+
+    /// Validate a GLF file on disk, and every .dat entry in it.
+    ///
+    /// * `path` - the GLF file.
+    pub fn validate_file(&self, path: &Path) -> ValidationReport {
+        let mut report = ValidationReport { path: Some(path.to_path_buf()), ..Default::default() };
+
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(_) => {
+                report.push(Severity::Error, IssueKind::Open, None, "Failed to open GLF file.".to_string());
+                return report;
+            },
+        };
+
+        let mut zip = match zip::ZipArchive::new(file) {
+            Ok(zip) => zip,
+            Err(_) => {
+                report.push(Severity::Error, IssueKind::Zip, None, "Not a zip archive.".to_string());
+                return report;
+            },
+        };
+
+        let mut dats: Vec<(String, Vec<u8>)> = vec![];
+        let mut damaged_dat = false;
+
+        // Read every entry to the end, which checks its CRC.
+        for i in 0..zip.len() {
+            let mut entry = match zip.by_index(i) {
+                Ok(entry) => entry,
+                Err(_) => {
+                    report.push(Severity::Error, IssueKind::ZipEntry, None, format!("Zip entry {} can't be read.", i));
+                    continue;
+                },
+            };
+
+            let name = entry.name().to_string();
+            let mut buffer: Vec<u8> = vec![];
+
+            if entry.read_to_end(&mut buffer).is_err() {
+                report.push(Severity::Error, IssueKind::ZipEntry, None, format!("Zip entry {} is damaged or fails its CRC.", name));
+                report.issues.last_mut().unwrap().entry = Some(name.clone());
+                damaged_dat |= has_extension(&name, "dat");
+            } else if has_extension(&name, "dat") {
+                dats.push((name, buffer));
+            }
+        }
+
+        if dats.is_empty() && !damaged_dat {
+            report.push(Severity::Error, IssueKind::NoDat, None, "No .dat entry in the archive.".to_string());
+        }
+
+        for (name, dat) in dats {
+            let first = report.issues.len();
+            self.check_dat(&dat, &mut report);
+
+            for issue in &mut report.issues[first..] {
+                issue.entry = Some(name.clone());
+            }
+
+            if report.count(Severity::Error) >= self.max_errors {
+                break;
+            }
+        }
+
+        report
+    }
+
+    /// Validate the contents of a .dat entry.
+    ///
+    /// * `dat` - the bytes of the .dat.
+    pub fn validate_dat(&self, dat: &[u8]) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        self.check_dat(dat, &mut report);
+        report
+    }
+
+    fn check_dat(&self, dat: &[u8], report: &mut ValidationReport) {
+        let mut fp: usize = 0;
+        let mut last_time: HashMap<(u16, u8), f64> = HashMap::new();
+        // Records are numbered from the start of each .dat.
+        let first_record = report.records;
+
+        // parse_dat ignores the last couple of bytes, so we do too.
+        while fp + 2 < dat.len() {
+            if report.count(Severity::Error) >= self.max_errors {
+                report.push(Severity::Warning, IssueKind::Truncated, None, format!("Stopped after {} errors.", self.max_errors));
+                return;
+            }
+
+            let location = Some((report.records - first_record, fp));
+
+            if dat.len() - fp < HEADER_SIZE {
+                report.push(Severity::Error, IssueKind::Truncated, location, "CIHeader runs past the end of the dat.".to_string());
+                return;
+            }
+
+            if dat[fp] != b'*' {
+                report.push(Severity::Error, IssueKind::HeaderMagic, location, format!("CIHeader starts with {:#04X}, expected '*'.", dat[fp]));
+                return;
+            }
+
+            let total = LittleEndian::read_u32(&dat[fp + 2..fp + 6]) as usize;
+
+            if total < HEADER_SIZE || fp + total > dat.len() {
+                report.push(Severity::Error, IssueKind::Truncated, location, format!("Record of {} bytes runs past the end of the dat.", total));
+                return;
+            }
+
+            let time = LittleEndian::read_f64(&dat[fp + 6..fp + 14]);
+            let type_byte = dat[fp + 14];
+            let device_id = LittleEndian::read_u16(&dat[fp + 15..fp + 17]);
+            let payload = &dat[fp + HEADER_SIZE..fp + total];
+            let mut next = fp + total;
+
+            match HeaderType::from(type_byte) {
+                HeaderType::Image => {
+                    report.images += 1;
+
+                    // Walked with the same view GLF::new uses, so they agree on what is damaged.
+                    let view = parse_header(dat, &mut (fp as i64)).and_then(|header| ImageRecordRef::parse(&header, dat, fp + HEADER_SIZE));
+
+                    match view {
+                        Ok(view) => {
+                            // GLF::new trusts the record, not the header, so a mismatch loses sync.
+                            let size = view.record_size() as usize;
+
+                            if size != payload.len() {
+                                report.push(Severity::Error, IssueKind::LengthMismatch, location,
+                                    format!("Image record is {} bytes but the CIHeader payload_length is {}.", size, payload.len()));
+                                next = fp + HEADER_SIZE + size;
+                            }
+
+                            if self.check_images {
+                                self.check_image(&view, location, report);
+                            }
+                        },
+                        Err(e) => {
+                            report.push(Severity::Error, image_issue(e), location, e.to_string());
+                        },
+                    }
+                },
+                HeaderType::GeminiStatus => {
+                    if let Err(e) = StatusLayout::select(payload.len() as u32) {
+                        report.push(Severity::Error, IssueKind::StatusLayout, location, e.to_string());
+                    }
+                },
+                HeaderType::RawSerial => {},
+                HeaderType::V4Protocol | HeaderType::AnalogVideo | HeaderType::Generic => {
+                    report.push(Severity::Warning, IssueKind::UnsupportedRecord, location,
+                        format!("{} records are not yet supported by this crate.", HeaderType::from(type_byte)));
+                },
+                // GLF::new skips these by their length too, so they don't fail validation.
+                HeaderType::Unknown(_) => {
+                    report.push(Severity::Warning, IssueKind::UnknownRecord, location, format!("Unknown record type {}, skipped.", type_byte));
+                },
+            }
+
+            if let Some(previous) = last_time.insert((device_id, type_byte), time) {
+                if time < previous {
+                    report.push(Severity::Error, IssueKind::TimeOrder, location,
+                        format!("Timestamp goes back {:.3}s from the previous {} record of device {}.", previous - time, HeaderType::from(type_byte), device_id));
+                }
+            }
+
+            report.records += 1;
+            fp = next;
+        }
+    }
+
+    fn check_image(&self, view: &ImageRecordRef, location: Option<(usize, usize)>, report: &mut ValidationReport) {
+        let (width, height) = (view.image_width() as usize, view.image_height() as usize);
+        let expected = width.saturating_mul(height);
+        let actual = match view.compression_type() {
+            // Inflating stops one byte past the frame, enough to tell it is too big.
+            CompressionType::Zlib => match decompress_to_vec_zlib_with_limit(view.data(), expected.saturating_add(1)) {
+                Ok(pixels) => pixels.len(),
+                Err(e) if e.status == TINFLStatus::HasMoreOutput => {
+                    report.images_checked += 1;
+                    report.push(Severity::Error, IssueKind::ImageSize, location,
+                        format!("Image payload inflates to more than {} x {} = {} bytes.", width, height, expected));
+                    return;
+                },
+                Err(_) => {
+                    report.push(Severity::Error, IssueKind::Decompress, location, "Image payload fails to decompress.".to_string());
+                    return;
+                },
+            },
+            CompressionType::Uncompressed => view.data().len(),
+            compression => {
+                report.push(Severity::Warning, IssueKind::Decompress, location,
+                    format!("Image compression type {} can't be checked.", compression));
+                return;
+            },
+        };
+
+        report.images_checked += 1;
+
+        if actual != expected {
+            report.push(Severity::Error, IssueKind::ImageSize, location,
+                format!("Image payload is {} bytes, expected {} x {} = {}.", actual, width, height, expected));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{build_dat, push_record, push_status, write_glf, TestImage, TestStatus};
+    use std::io::Write;
+
+    #[test]
+    fn test_validate_dat() {
+        let mut dat = build_dat(&[TestImage::new(1, 1.0), TestImage::new(2, 1.5), TestImage::new(1, 2.0)]);
+        push_status(&mut dat, &TestStatus::new(1, 1.2));
+        let report = Validator::new().validate_dat(&dat);
+        assert!(report.is_valid(), "{:?}", report.issues);
+        assert_eq!((report.records, report.images, report.images_checked), (4, 3, 3));
+
+        let path = std::env::temp_dir().join(format!("glf_validate_{}.glf", std::process::id()));
+        write_glf(&path, &dat, zip::CompressionMethod::Deflated);
+        let report = Validator::new().validate_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(report.is_valid(), "{:?}", report.issues);
+        assert_eq!(report.records, 4);
+
+        let dat = build_dat(&[TestImage::new(1, 2.0), TestImage::new(1, 1.0)]);
+        let report = Validator::new().validate_dat(&dat);
+        assert_eq!(report.issues.iter().map(|i| i.kind).collect::<Vec<_>>(), vec![IssueKind::TimeOrder]);
+
+        // A broken end tag and a truncated final record.
+        let mut dat = build_dat(&[TestImage::new(1, 1.0), TestImage::new(1, 2.0)]);
+        let first_len = LittleEndian::read_u32(&dat[2..6]) as usize;
+        dat[first_len - 1] = 0;
+        dat.truncate(dat.len() - 4);
+        let report = Validator::new().validate_dat(&dat);
+        let kinds: Vec<IssueKind> = report.issues.iter().map(|i| i.kind).collect();
+        assert!(!report.is_valid());
+        assert_eq!(kinds, vec![IssueKind::EndTag, IssueKind::Truncated]);
+        assert_eq!(report.issues[1].offset, Some(first_len));
+
+        // Zlib payloads are inflated, and a bad rtype is caught.
+        let mut dat = build_dat(&[TestImage { zlib: true, ..TestImage::new(1, 1.0) }, TestImage::new(1, 2.0)]);
+        assert!(Validator::new().validate_dat(&dat).is_valid());
+        assert_eq!(Validator::new().validate_dat(&dat).images_checked, 2);
+        dat[21] = 7;
+        let report = Validator::new().validate_dat(&dat);
+        assert_eq!(report.issues.iter().map(|i| i.kind).collect::<Vec<_>>(), vec![IssueKind::ImageMagic]);
+
+        // A zlib payload that inflates past the frame is too big, not corrupt.
+        dat[21] = 1;
+        dat[31] = 7;
+        let report = Validator::new().validate_dat(&dat);
+        assert_eq!(report.issues.iter().map(|i| i.kind).collect::<Vec<_>>(), vec![IssueKind::ImageSize]);
+        assert!(report.issues[0].message.contains("more than"));
+    }
+
+    #[test]
+    fn test_validate_every_dat() {
+        // Unknown records are skipped by GLF::new, so they only warn.
+        let mut first = build_dat(&[TestImage::new(1, 1.0)]);
+        push_record(&mut first, 200, 1, 1.5, &[1, 2, 3]);
+        let report = Validator::new().validate_dat(&first);
+        assert!(report.is_valid(), "{:?}", report.issues);
+        assert_eq!(report.issues.iter().map(|i| (i.severity, i.kind)).collect::<Vec<_>>(), vec![(Severity::Warning, IssueKind::UnknownRecord)]);
+
+        // Damage in the second .dat is found, and named.
+        let mut second = build_dat(&[TestImage::new(2, 1.0), TestImage::new(2, 2.0)]);
+        let end = second.len();
+        second[end - 1] = 0;
+
+        let path = std::env::temp_dir().join(format!("glf_validate_dats_{}.glf", std::process::id()));
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        zip.start_file("log/a.dat", zip::write::FileOptions::default()).unwrap();
+        zip.write_all(&first).unwrap();
+        zip.start_file("log/b.dat", zip::write::FileOptions::default()).unwrap();
+        zip.write_all(&second).unwrap();
+        zip.finish().unwrap();
+
+        let report = Validator::new().validate_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(!report.is_valid());
+        assert_eq!(report.records, 4);
+
+        let error = report.issues.iter().find(|i| i.severity == Severity::Error).unwrap();
+        assert_eq!((error.kind, error.entry.as_deref(), error.record), (IssueKind::EndTag, Some("log/b.dat"), Some(1)));
+        assert!(error.to_string().starts_with("error: log/b.dat record 1 @"));
+        assert_eq!(report.issues[0].entry.as_deref(), Some("log/a.dat"));
+
+        // A file that can't be opened is told apart from one that isn't a zip.
+        let kinds = |report: ValidationReport| report.issues.iter().map(|i| i.kind).collect::<Vec<_>>();
+        assert_eq!(kinds(Validator::new().validate_file(&path)), vec![IssueKind::Open]);
+        std::fs::write(&path, b"not a zip").unwrap();
+        let report = Validator::new().validate_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(kinds(report), vec![IssueKind::Zip]);
+    }
+}
@@ -0,0 +1,213 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # Views
+//! Walks a dat buffer without copying it. Each record comes back as a
+//! borrowed view - ImageRecordRef, StatusRecordRef or a slice - that decodes
+//! its fields when asked, so building an index of a large file allocates
+//! nothing per record. Views convert to the owned records with `to_record`.
+
+use crate::ciheader::parse_header;
+use crate::{CIHeader, HeaderType, ImageRecordRef, RecordEntry, StatusRecordRef, GLF};
+
+/// A borrowed view of any record in the dat buffer.
+#[derive(Copy, Clone, Debug)]
+pub enum RecordRef<'a> {
+    /// An image record.
+    Image(ImageRecordRef<'a>),
+    /// A status record.
+    Status(StatusRecordRef<'a>),
+    /// Raw serial data.
+    Serial {
+        /// The CIHeader.
+        header: CIHeader,
+        /// The bytes received on the serial port.
+        data: &'a [u8],
+    },
+    /// A record this crate doesn't parse yet.
+    Other {
+        /// The CIHeader.
+        header: CIHeader,
+        /// The payload after the CIHeader.
+        payload: &'a [u8],
+    },
+}
+
+impl RecordRef<'_> {
+    /// The CIHeader of the record.
+    pub fn header(&self) -> CIHeader {
+        match self {
+            RecordRef::Image(view) => view.header,
+            RecordRef::Status(view) => view.header,
+            RecordRef::Serial { header, .. } | RecordRef::Other { header, .. } => *header,
+        }
+    }
+}
+
+/// An iterator over the records of a dat buffer, yielding each record's
+/// offset and view. It stops after the first error.
+pub struct RecordRefs<'a> {
+    dat: &'a [u8],
+    offset: usize,
+    failed: bool,
+}
+
+/// Iterate over the records of a dat buffer without copying them.
+///
+/// * `dat` - the bytes of the .dat.
+pub fn record_refs(dat: &[u8]) -> RecordRefs<'_> {
+    RecordRefs { dat, offset: 0, failed: false }
+}
+
+impl<'a> RecordRefs<'a> {
+    fn read(&mut self) -> Result<(usize, RecordRef<'a>), &'static str> {
+        let start = self.offset;
+
+        let mut file_offset = start as i64;
+        let header = parse_header(self.dat, &mut file_offset)?;
+        let payload_start = file_offset as usize;
+        let payload_end = payload_start + header.payload_length as usize;
+
+        let (view, end) = match header.header_type {
+            HeaderType::Image => {
+                let view = ImageRecordRef::parse(&header, self.dat, payload_start)?;
+                (RecordRef::Image(view), payload_start + view.record_size() as usize)
+            },
+            HeaderType::GeminiStatus => (RecordRef::Status(StatusRecordRef::parse(&header, self.dat, payload_start)?), payload_end),
+            _ => {
+                let payload = self.dat.get(payload_start..payload_end).ok_or("Record runs past the end of the dat buffer.")?;
+
+                match header.header_type {
+                    HeaderType::RawSerial => (RecordRef::Serial { header, data: payload }, payload_end),
+                    _ => (RecordRef::Other { header, payload }, payload_end),
+                }
+            },
+        };
+
+        self.offset = end;
+        Ok((start, view))
+    }
+}
+
+impl<'a> Iterator for RecordRefs<'a> {
+    type Item = Result<(usize, RecordRef<'a>), &'static str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Like parse_dat, ignore the last couple of bytes.
+        if self.failed || self.offset + 2 >= self.dat.len() {
+            return None;
+        }
+
+        let item = self.read();
+        self.failed = item.is_err();
+        Some(item)
+    }
+}
+
+/// Build the record index of a dat buffer, as GLF::records, without parsing
+/// the records into owned form. Like parse_dat, records of types that
+/// aren't parsed are skipped and left out.
+///
+/// * `dat` - the bytes of the .dat.
+pub fn index_records(dat: &[u8]) -> Result<Vec<RecordEntry>, &'static str> {
+    let mut counts: [usize; 3] = [0; 3];
+    let mut records: Vec<RecordEntry> = vec![];
+    let mut refs = record_refs(dat).peekable();
+
+    while let Some(item) = refs.next() {
+        let (offset, view) = item?;
+        let slot = match view {
+            RecordRef::Image(_) => 0,
+            RecordRef::Status(_) => 1,
+            RecordRef::Serial { .. } => 2,
+            RecordRef::Other { .. } => continue,
+        };
+
+        let end = match refs.peek() {
+            Some(Ok((next, _))) => *next,
+            _ => match view {
+                RecordRef::Image(image) => offset + 21 + image.record_size() as usize,
+                _ => offset + 21 + view.header().payload_length as usize,
+            },
+        };
+
+        records.push(RecordEntry {
+            record_type: view.header().header_type,
+            index: counts[slot],
+            offset,
+            length: end - offset,
+        });
+        counts[slot] += 1;
+    }
+
+    Ok(records)
+}
+
+impl GLF {
+    /// Iterate over the records of this GLF as borrowed views.
+    pub fn record_refs(&self) -> RecordRefs<'_> {
+        record_refs(&self.dat)
+    }
+
+    /// A borrowed view of an image record.
+    ///
+    /// * `idx` - index into GLF::images.
+    pub fn image_ref(&self, idx: usize) -> Option<ImageRecordRef<'_>> {
+        let img_rec = self.images.get(idx)?;
+        // The pixels follow the fixed fields and data size - two bytes more with a compression field.
+        let fixed = if img_rec.image_version == 3 { 30 } else { 28 };
+        let offset = usize::try_from(img_rec.data_ptr).ok()?.checked_sub(fixed)?;
+        ImageRecordRef::parse(&img_rec.header, &self.dat, offset).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{build_dat, glf_from_dat, push_record, push_status, TestImage, TestStatus};
+
+    #[test]
+    fn test_record_refs() {
+        let mut images = vec![TestImage::new(1, 1.0), TestImage::new(2, 1.5)];
+        images[1].percent_gain = 70;
+        let mut dat = build_dat(&images);
+        push_status(&mut dat, &TestStatus::new(1, 1.2));
+        let glf = glf_from_dat(dat);
+
+        assert_eq!(index_records(&glf.dat).unwrap(), glf.records);
+
+        let views: Vec<RecordRef> = glf.record_refs().map(|r| r.unwrap().1).collect();
+        assert_eq!(views.len(), 3);
+
+        match views[1] {
+            RecordRef::Image(view) => {
+                let owned = view.to_record();
+                assert_eq!(view.percent_gain(), 70);
+                assert_eq!(view.bearings().collect::<Vec<f64>>(), &*glf.images[1].bearing_table);
+                assert_eq!(view.data(), &glf.dat[owned.data_ptr as usize..(owned.data_ptr + owned.data_size as u64) as usize]);
+                assert_eq!((owned.data_ptr, owned.record_size), (glf.images[1].data_ptr, glf.images[1].record_size));
+                assert_eq!(glf.image_ref(1).unwrap().data_ptr(), view.data_ptr());
+            },
+            _ => panic!("Expected an image record."),
+        }
+
+        match views[2] {
+            RecordRef::Status(view) => assert_eq!(view.device_id(), glf.statuses[0].device_id),
+            _ => panic!("Expected a status record."),
+        }
+
+        assert!(record_refs(&glf.dat[..50]).last().unwrap().is_err());
+
+        // Generic and unknown records are skipped, as GLF::new skips them.
+        let mut dat = build_dat(&[TestImage::new(1, 1.0)]);
+        push_record(&mut dat, u8::from(crate::HeaderType::Generic), 1, 1.5, &[1, 2, 3]);
+        push_record(&mut dat, 42, 1, 1.6, &[]);
+        dat.extend(build_dat(&[TestImage::new(1, 2.0)]));
+        let glf = glf_from_dat(dat);
+        assert_eq!(glf.records.len(), 2);
+        assert_eq!(index_records(&glf.dat).unwrap(), glf.records);
+        assert_eq!(glf.record_refs().count(), 4);
+    }
+}
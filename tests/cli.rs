@@ -0,0 +1,208 @@
+//!    ___  __    ____
+//!   / __)(  )  (  __)
+//!  ( (_ \/ (_/\ ) _)
+//!   \___/\____/(__)
+//!
+//! # CLI
+//! Runs the `glf` binary on small synthetic GLF files, checking the output
+//! and exit codes of each subcommand.
+
+#![cfg(feature = "cli")]
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Append an uncompressed 4 x 8 image record. The pixels are offset by `shift`.
+fn push_image(buf: &mut Vec<u8>, device_id: u16, time: f64, shift: u8) {
+    let mut p: Vec<u8> = vec![];
+    p.extend_from_slice(&1u16.to_le_bytes());
+    p.extend_from_slice(&0xEFEFu16.to_le_bytes());
+    p.extend_from_slice(&3u16.to_le_bytes());
+    p.extend_from_slice(&0u32.to_le_bytes());
+    p.extend_from_slice(&8u32.to_le_bytes());
+    p.extend_from_slice(&0u16.to_le_bytes());
+    p.extend_from_slice(&0u32.to_le_bytes());
+    p.extend_from_slice(&4u32.to_le_bytes());
+    p.extend_from_slice(&1u16.to_le_bytes());
+    p.extend_from_slice(&32u32.to_le_bytes());
+    p.extend((0..32u8).map(|i| i.wrapping_add(shift)));
+
+    for i in 0..4 {
+        p.extend_from_slice(&(-0.5 + i as f64 / 4.0).to_le_bytes());
+    }
+
+    p.extend_from_slice(&0u32.to_le_bytes());
+    p.extend_from_slice(&720_000u32.to_le_bytes());
+    p.extend_from_slice(&0f32.to_le_bytes());
+    p.extend_from_slice(&time.to_le_bytes());
+    p.extend_from_slice(&0u16.to_le_bytes());
+    p.extend_from_slice(&1500f32.to_le_bytes());
+    p.extend_from_slice(&50u16.to_le_bytes());
+    p.extend_from_slice(&[0, 6, 0, 0]);
+    p.extend_from_slice(&0xDEDEu16.to_le_bytes());
+
+    buf.push(b'*');
+    buf.push(0);
+    buf.extend_from_slice(&(p.len() as u32 + 21).to_le_bytes());
+    buf.extend_from_slice(&time.to_le_bytes());
+    buf.push(0);
+    buf.extend_from_slice(&device_id.to_le_bytes());
+    buf.extend_from_slice(&[0; 4]);
+    buf.extend_from_slice(&p);
+}
+
+/// A scratch directory for one test, removed when dropped.
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new(name: &str) -> Scratch {
+        let dir = std::env::temp_dir().join(format!("glf_cli_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Scratch(dir)
+    }
+
+    /// Write a GLF holding three frames, two from device 1 and one from device 2.
+    fn glf(&self, name: &str, shift: u8) -> PathBuf {
+        let mut dat: Vec<u8> = vec![];
+        push_image(&mut dat, 1, 1.0, shift);
+        push_image(&mut dat, 2, 1.5, shift);
+        push_image(&mut dat, 1, 2.0, shift);
+
+        let path = self.0.join(name);
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+        zip.start_file("log.cfg", zip::write::FileOptions::default()).unwrap();
+        zip.write_all(b"<config/>").unwrap();
+        zip.start_file("log.dat", zip::write::FileOptions::default()).unwrap();
+        zip.write_all(&dat).unwrap();
+        zip.finish().unwrap();
+        path
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn glf(args: &[&dyn AsRef<std::ffi::OsStr>]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_glf")).args(args).output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn json(output: &Output) -> serde_json::Value {
+    serde_json::from_str(&stdout(output)).unwrap()
+}
+
+#[test]
+fn test_info_and_list() {
+    let scratch = Scratch::new("info");
+    let file = scratch.glf("a.glf", 0);
+
+    let output = glf(&[&"info", &file, &"--json"]);
+    assert!(output.status.success());
+    let info = json(&output);
+    assert_eq!(info["images"], 3);
+    assert_eq!(info["entry"], "log.dat");
+    assert_eq!(info["devices"][0]["device_id"], 1);
+    assert_eq!(info["devices"][0]["frames"], 2);
+    assert_eq!(info["devices"][1]["models"][0], "Gemini 1200ik");
+
+    let output = glf(&[&"info", &file]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("3 images"));
+
+    let output = glf(&[&"list", &file, &"--json"]);
+    assert!(output.status.success());
+    let lines: Vec<serde_json::Value> = stdout(&output).lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[1]["device_id"], 2);
+    assert_eq!(lines[2]["detail"]["width"], 4);
+}
+
+#[test]
+fn test_extract_and_convert() {
+    let scratch = Scratch::new("extract");
+    let file = scratch.glf("a.glf", 0);
+    let frames = scratch.0.join("frames");
+
+    let output = glf(&[&"extract", &file, &frames, &"--device", &"1", &"--json"]);
+    assert!(output.status.success());
+    let written = json(&output)["written"].as_array().unwrap().clone();
+    assert_eq!(written.iter().map(|w| w["frame"].as_u64().unwrap()).collect::<Vec<_>>(), vec![0, 2]);
+    assert!(Path::new(written[0]["file"].as_str().unwrap()).exists());
+
+    let output = glf(&[&"extract", &file, &frames, &"--fan", &"16", &"--format", &"tiff"]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output).lines().count(), 3);
+    assert!(frames.join("a_000001.tiff").exists());
+
+    let ndjson = scratch.0.join("a.ndjson");
+    let output = glf(&[&"convert", &file, &ndjson, &"--to", &"ndjson"]);
+    assert!(output.status.success());
+    assert_eq!(std::fs::read_to_string(&ndjson).unwrap().lines().count(), 3);
+}
+
+#[test]
+fn test_validate_exit_codes() {
+    let scratch = Scratch::new("validate");
+    let good = scratch.glf("a.glf", 0);
+    let bad = scratch.0.join("bad.glf");
+    std::fs::write(&bad, b"not a zip").unwrap();
+
+    let output = glf(&[&"validate", &good]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains(": ok"));
+
+    // Damage exits 1, as differences do for diff(1).
+    let output = glf(&[&"validate", &good, &bad, &"--json"]);
+    assert_eq!(output.status.code(), Some(1));
+    let reports: Vec<serde_json::Value> = stdout(&output).lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert_eq!(reports.len(), 2);
+
+    // A file that can't be opened is trouble, and exits 2.
+    let missing = scratch.0.join("missing.glf");
+    assert_eq!(glf(&[&"validate", &missing]).status.code(), Some(2));
+    assert_eq!(glf(&[&"validate", &bad, &missing]).status.code(), Some(2));
+}
+
+#[test]
+fn test_diff_exit_codes() {
+    let scratch = Scratch::new("diff");
+    let left = scratch.glf("a.glf", 0);
+    let same = scratch.glf("b.glf", 0);
+    let changed = scratch.glf("c.glf", 3);
+
+    assert_eq!(glf(&[&"diff", &left, &same]).status.code(), Some(0));
+
+    let output = glf(&[&"diff", &left, &changed, &"--json"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(json(&output)["frames_compared"], 3);
+
+    assert!(glf(&[&"diff", &left, &changed, &"--tolerance", &"3"]).status.success());
+    assert!(glf(&[&"diff", &left, &changed, &"--no-pixels"]).status.success());
+
+    // A file that can't be read is trouble, not a difference.
+    let missing = scratch.0.join("missing.glf");
+    let output = glf(&[&"diff", &left, &missing]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("glf: "));
+}
+
+#[test]
+fn test_errors() {
+    let scratch = Scratch::new("errors");
+    let missing = scratch.0.join("missing.glf");
+
+    let output = glf(&[&"info", &missing]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("glf: "));
+
+    // Bad arguments are usage errors.
+    assert_eq!(glf(&[&"info"]).status.code(), Some(2));
+    assert_eq!(glf(&[&"convert", &missing, &missing, &"--to", &"bogus"]).status.code(), Some(2));
+}